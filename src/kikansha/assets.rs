@@ -0,0 +1,84 @@
+use std::env;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Environment variable with an additional asset root.
+pub const ASSET_ROOT_ENV: &str = "KIKANSHA_ASSET_ROOT";
+
+/// Default texture, used as a placeholder and for figures without textures.
+pub const DEFAULT_TEXTURE: &[u8] = include_bytes!("frame/resources/tex.png");
+
+/// Resolves asset paths against a list of root directories.
+///
+/// Every loader goes through the resolver, so relative paths stored in scenes work no matter
+/// where the binary is started from. Roots are tried in order, the first existing file wins.
+#[derive(Debug, Clone)]
+pub struct AssetResolver {
+    roots: Vec<PathBuf>,
+}
+
+impl AssetResolver {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        AssetResolver { roots }
+    }
+
+    /// Builds the default list of roots:
+    /// - `explicit_root`, usually given on the command line,
+    /// - the directory from `KIKANSHA_ASSET_ROOT`,
+    /// - the directory of the running binary,
+    /// - the current directory.
+    pub fn discover(explicit_root: Option<&Path>) -> Self {
+        let mut roots = Vec::new();
+        if let Some(root) = explicit_root {
+            roots.push(root.to_path_buf());
+        }
+        if let Some(root) = env::var_os(ASSET_ROOT_ENV) {
+            roots.push(PathBuf::from(root));
+        }
+        if let Some(exe_dir) = env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        {
+            roots.push(exe_dir);
+        }
+        if let Ok(current_dir) = env::current_dir() {
+            roots.push(current_dir);
+        }
+        log::info!("Asset roots: {:?}", roots);
+        Self::new(roots)
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Returns the first existing file for `path`. Absolute paths are only checked for existence.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+        let path = path.as_ref();
+        if path.is_absolute() {
+            return Some(path.to_path_buf()).filter(|p| p.exists());
+        }
+        self.roots
+            .iter()
+            .map(|root| root.join(path))
+            .find(|candidate| candidate.exists())
+    }
+
+    pub fn resolve_or_err<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
+        let path = path.as_ref();
+        self.resolve(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found in {:?}", path.display(), self.roots),
+            )
+        })
+    }
+}
+
+impl Default for AssetResolver {
+    fn default() -> Self {
+        Self::discover(None)
+    }
+}
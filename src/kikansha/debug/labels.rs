@@ -0,0 +1,60 @@
+use std::ffi::CStr;
+use std::ffi::CString;
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::device::Device;
+use vulkano::device::DeviceOwned;
+use vulkano::image::ImageAccess;
+use vulkano::VulkanObject;
+
+/// Names and labels are only recorded when the instance loaded `ext_debug_utils`, which
+/// happens when validation is enabled.
+pub fn debug_utils_enabled(device: &Device) -> bool {
+    device.instance().loaded_extensions().ext_debug_utils
+}
+
+/// Attaches a name to a Vulkan object, shown by RenderDoc and in validation messages.
+pub fn name_object<T: VulkanObject + DeviceOwned>(object: &T, name: &str) {
+    let device = object.device();
+    if !debug_utils_enabled(device) {
+        return;
+    }
+    match CString::new(name) {
+        Ok(c_name) => {
+            if let Err(e) = device.set_object_name(object, &c_name) {
+                log::warn!("Failed to name {}: {:?}", name, e);
+            }
+        }
+        Err(e) => log::warn!("Invalid object name {:?}: {}", name, e),
+    }
+}
+
+pub fn name_image<I: ImageAccess + ?Sized>(image: &I, name: &str) {
+    name_object(image.inner().image, name);
+}
+
+pub fn name_buffer<B: BufferAccess + ?Sized>(buffer: &B, name: &str) {
+    name_object(buffer.inner().buffer, name);
+}
+
+/// Opens a command buffer label, it has to be closed with `end_label` in the same subpass.
+pub fn begin_label(
+    device: &Device,
+    builder: &mut AutoCommandBufferBuilder,
+    name: &'static CStr,
+    color: [f32; 4],
+) {
+    if debug_utils_enabled(device) {
+        if let Err(e) = builder.debug_marker_begin(name, color) {
+            log::warn!("Failed to begin label {:?}: {:?}", name, e);
+        }
+    }
+}
+
+pub fn end_label(device: &Device, builder: &mut AutoCommandBufferBuilder) {
+    if debug_utils_enabled(device) {
+        if let Err(e) = builder.debug_marker_end() {
+            log::warn!("Failed to end label: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use serde::Deserialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::sync::Mutex;
+use vulkano::instance::debug::{DebugCallback, Message, MessageSeverity, MessageType};
+use vulkano::instance::Instance;
+
+/// Log target of the messages coming from the validation layers.
+pub const LOG_TARGET: &str = "vulkan";
+
+static VALIDATION_ERRORS: AtomicUsize = AtomicUsize::new(0);
+static LAST_VALIDATION_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// Least severe message that is still reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Verbose,
+}
+
+/// Filters of the debug-utils messenger.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DebugMessengerConfig {
+    pub min_severity: Severity,
+    pub general: bool,
+    pub validation: bool,
+    pub performance: bool,
+    /// Makes the render loop panic after a frame which produced a validation error.
+    ///
+    /// Panicking in the callback itself would unwind through the Vulkan loader, so errors are
+    /// only counted there.
+    pub panic_on_error: bool,
+}
+
+impl Default for DebugMessengerConfig {
+    fn default() -> Self {
+        DebugMessengerConfig {
+            min_severity: Severity::Warning,
+            general: true,
+            validation: true,
+            performance: true,
+            panic_on_error: false,
+        }
+    }
+}
+
+impl DebugMessengerConfig {
+    fn severity(&self) -> MessageSeverity {
+        MessageSeverity {
+            error: true,
+            warning: self.min_severity != Severity::Error,
+            information: self.min_severity == Severity::Information
+                || self.min_severity == Severity::Verbose,
+            verbose: self.min_severity == Severity::Verbose,
+        }
+    }
+
+    fn types(&self) -> MessageType {
+        MessageType {
+            general: self.general,
+            validation: self.validation,
+            performance: self.performance,
+        }
+    }
+}
+
+fn log_level(severity: &MessageSeverity) -> log::Level {
+    if severity.error {
+        log::Level::Error
+    } else if severity.warning {
+        log::Level::Warn
+    } else if severity.information {
+        log::Level::Info
+    } else {
+        log::Level::Trace
+    }
+}
+
+fn message_type(ty: &MessageType) -> &'static str {
+    if ty.validation {
+        "validation"
+    } else if ty.performance {
+        "performance"
+    } else {
+        "general"
+    }
+}
+
+/// Forwards validation layer messages to `log` under the `vulkan` target.
+///
+/// The instance has to be created with `ext_debug_utils`.
+pub fn create_messenger(
+    instance: &Arc<Instance>,
+    config: &DebugMessengerConfig,
+) -> Option<DebugCallback> {
+    DebugCallback::new(
+        instance,
+        config.severity(),
+        config.types(),
+        |msg: &Message| {
+            let level = log_level(&msg.severity);
+            log::log!(
+                target: LOG_TARGET,
+                level,
+                "[{}] {}: {}",
+                message_type(&msg.ty),
+                msg.layer_prefix,
+                msg.description
+            );
+            if msg.severity.error {
+                VALIDATION_ERRORS.fetch_add(1, Ordering::SeqCst);
+                if let Ok(mut last) = LAST_VALIDATION_ERROR.lock() {
+                    *last = Some(msg.description.to_string());
+                }
+            }
+        },
+    )
+    .map_err(|e| log::error!("Failed to create debug messenger: {:?}", e))
+    .ok()
+}
+
+/// Returns the number of error messages since the previous call and the last of them.
+pub fn take_validation_errors() -> (usize, Option<String>) {
+    let count = VALIDATION_ERRORS.swap(0, Ordering::SeqCst);
+    let last = LAST_VALIDATION_ERROR
+        .lock()
+        .ok()
+        .and_then(|mut last| last.take());
+    (count, last)
+}
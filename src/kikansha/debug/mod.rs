@@ -1,2 +1,5 @@
 pub mod fps;
+pub mod labels;
+pub mod messenger;
+pub mod profiler;
 pub mod tracing;
@@ -0,0 +1,518 @@
+use crate::debug::tracing;
+use std::collections::VecDeque;
+use std::mem;
+use std::ptr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::pool::standard::StandardCommandPoolAlloc;
+use vulkano::command_buffer::pool::standard::StandardCommandPoolBuilder;
+use vulkano::command_buffer::sys::Flags;
+use vulkano::command_buffer::sys::Kind;
+use vulkano::command_buffer::sys::KindOcclusionQuery;
+use vulkano::command_buffer::sys::KindSecondaryRenderPass;
+use vulkano::command_buffer::sys::UnsafeCommandBuffer;
+use vulkano::command_buffer::sys::UnsafeCommandBufferBuilder;
+use vulkano::command_buffer::CommandBuffer;
+use vulkano::command_buffer::CommandBufferExecError;
+use vulkano::device::Device;
+use vulkano::device::DeviceOwned;
+use vulkano::device::Queue;
+use vulkano::framebuffer::FramebufferAbstract;
+use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::framebuffer::Subpass;
+use vulkano::image::ImageAccess;
+use vulkano::image::ImageLayout;
+use vulkano::query::QueryPipelineStatisticFlags;
+use vulkano::query::QueryType;
+use vulkano::query::UnsafeQueryPool;
+use vulkano::sync::AccessCheckError;
+use vulkano::sync::AccessFlagBits;
+use vulkano::sync::GpuFuture;
+use vulkano::sync::PipelineStages;
+use vulkano::VulkanObject;
+
+pub type ProfiledSubpass = Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>;
+
+// Resolved frames kept for the statistics.
+const HISTORY: usize = 240;
+
+/// Points of the frame where a timestamp is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampPoint {
+    FrameStart = 0,
+    GeometryEnd = 1,
+    LightingEnd = 2,
+    FrameEnd = 3,
+}
+
+const TIMESTAMPS_PER_FRAME: usize = 4;
+
+/// GPU time of the passes of a single frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameTimings {
+    pub geometry_ms: f64,
+    pub lighting_ms: f64,
+    /// Final attachment stores and the transition of the swapchain image to the present layout.
+    pub present_ms: f64,
+    pub gpu_ms: f64,
+    /// Time since the previous frame was submitted, measured on the CPU.
+    pub frame_ms: f64,
+    pub draw_calls: usize,
+    /// Instances drawn and skipped by frustum culling.
+    pub visible_objects: usize,
+    pub culled_objects: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    fn of(mut values: Vec<f64>) -> Self {
+        if values.is_empty() {
+            return Percentiles::default();
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let at = |p: f64| values[((values.len() - 1) as f64 * p).round() as usize];
+        Percentiles {
+            p50: at(0.5),
+            p95: at(0.95),
+            p99: at(0.99),
+            max: values[values.len() - 1],
+        }
+    }
+}
+
+/// Statistics over the last resolved frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProfilerStats {
+    pub frames: usize,
+    pub geometry_ms: f64,
+    pub lighting_ms: f64,
+    pub present_ms: f64,
+    pub gpu_ms: Percentiles,
+    pub frame_ms: Percentiles,
+    pub draw_calls: f64,
+    pub visible_objects: f64,
+    pub culled_objects: f64,
+}
+
+struct PendingFrame {
+    number: u64,
+    slot: usize,
+    // Set by `frame_finished`, until then the queries of the slot may still hold the results of
+    // the previous frame using it, as they are reset by the GPU.
+    finished: bool,
+    submitted: Instant,
+    frame_ms: f64,
+    draw_calls: usize,
+    visible_objects: usize,
+    culled_objects: usize,
+}
+
+/// Measures the passes of `Frame` with timestamp queries.
+///
+/// Timestamps are written by tiny secondary command buffers executed at the pass boundaries,
+/// results are read back without waiting once the renderer reports the frame as finished with
+/// `frame_finished`.
+pub struct GpuProfiler {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    pool: Arc<UnsafeQueryPool>,
+    // Nanoseconds per timestamp tick.
+    timestamp_period: f64,
+    // Bits of the timestamps that are written, the others are undefined.
+    timestamp_mask: u64,
+    // Frames whose queries may still be written by the GPU or waiting to be read back. A frame is
+    // resolved once the renderer has waited for its fence, `frames_in_flight` frames after
+    // submission, so one more slot is used for the frame being recorded.
+    frame_slots: usize,
+    next_slot: usize,
+    ended_frames: u64,
+    current_slot: Option<usize>,
+    pending: VecDeque<PendingFrame>,
+    history: VecDeque<FrameTimings>,
+    last_submit: Option<Instant>,
+    last_report: Instant,
+    report_every: Duration,
+}
+
+impl GpuProfiler {
+    /// Returns `None` if the device can't write timestamps on graphics queues.
+    ///
+    /// `frames_in_flight` is the number of frames the renderer records ahead of the GPU, the
+    /// query pool has room for all of them.
+    pub fn new(queue: Arc<Queue>, report_every: u8, frames_in_flight: usize) -> Option<Self> {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        let device = queue.device().clone();
+        let limits = device.physical_device().limits();
+        if limits.timestamp_compute_and_graphics() == 0 {
+            log::warn!("GPU profiler disabled: timestamps are not supported");
+            return None;
+        }
+        let valid_bits = timestamp_valid_bits(&queue);
+        if valid_bits == 0 {
+            log::warn!("GPU profiler disabled: the graphics queue doesn't write timestamps");
+            return None;
+        }
+        let frame_slots = frames_in_flight.max(1) + 1;
+        let pool = UnsafeQueryPool::new(
+            device.clone(),
+            QueryType::Timestamp,
+            (frame_slots * TIMESTAMPS_PER_FRAME) as u32,
+        )
+        .map_err(|e| log::error!("Failed to create timestamp query pool: {:?}", e))
+        .ok()?;
+        Some(GpuProfiler {
+            device,
+            queue,
+            pool: Arc::new(pool),
+            timestamp_period: limits.timestamp_period() as f64,
+            timestamp_mask: if valid_bits >= 64 {
+                u64::MAX
+            } else {
+                (1 << valid_bits) - 1
+            },
+            frame_slots,
+            next_slot: 0,
+            ended_frames: 0,
+            current_slot: None,
+            pending: VecDeque::new(),
+            history: VecDeque::with_capacity(HISTORY),
+            last_submit: None,
+            last_report: Instant::now(),
+            report_every: Duration::from_secs(report_every as u64),
+        })
+    }
+
+    /// Starts profiling a frame, the returned command buffer has to be executed before the
+    /// render pass begins.
+    pub fn begin_frame(&mut self) -> TimestampCommandBuffer {
+        self.resolve();
+
+        let slot = self.next_slot;
+        self.next_slot = (self.next_slot + 1) % self.frame_slots;
+        if let Some(position) = self.pending.iter().position(|frame| frame.slot == slot) {
+            log::trace!("GPU profiler dropped unresolved frame in slot {}", slot);
+            self.pending.remove(position);
+        }
+        self.current_slot = Some(slot);
+
+        let first = (slot * TIMESTAMPS_PER_FRAME) as u32;
+        let pool = self.pool.clone();
+        self.record(None, |builder| unsafe {
+            builder.reset_query_pool(
+                pool.queries_range(first, TIMESTAMPS_PER_FRAME as u32)
+                    .unwrap(),
+            );
+            builder.write_timestamp(
+                pool.query(first).unwrap(),
+                PipelineStages {
+                    top_of_pipe: true,
+                    ..PipelineStages::none()
+                },
+            );
+        })
+    }
+
+    /// Command buffer writing `point` of the current frame, executed in `subpass` or outside of
+    /// the render pass.
+    pub fn timestamp(
+        &self,
+        point: TimestampPoint,
+        subpass: Option<ProfiledSubpass>,
+    ) -> Option<TimestampCommandBuffer> {
+        let slot = self.current_slot?;
+        let index = (slot * TIMESTAMPS_PER_FRAME + point as usize) as u32;
+        let pool = self.pool.clone();
+        Some(self.record(subpass, |builder| unsafe {
+            builder.write_timestamp(
+                pool.query(index).unwrap(),
+                PipelineStages {
+                    bottom_of_pipe: true,
+                    ..PipelineStages::none()
+                },
+            );
+        }))
+    }
+
+    /// Marks the current frame as submitted.
+    pub fn end_frame(&mut self, draw_calls: usize, visible_objects: usize, culled_objects: usize) {
+        if let Some(slot) = self.current_slot.take() {
+            let now = Instant::now();
+            let frame_ms = self
+                .last_submit
+                .map(|last| (now - last).as_secs_f64() * 1000.0)
+                .unwrap_or(0.0);
+            self.last_submit = Some(now);
+            self.ended_frames += 1;
+            self.pending.push_back(PendingFrame {
+                number: self.ended_frames,
+                slot,
+                finished: false,
+                submitted: now,
+                frame_ms,
+                draw_calls,
+                visible_objects,
+                culled_objects,
+            });
+        }
+    }
+
+    /// Number of the last frame marked as submitted, to be passed to `frame_finished` once its
+    /// fence is signalled.
+    pub fn last_frame(&self) -> Option<u64> {
+        if self.ended_frames == 0 {
+            None
+        } else {
+            Some(self.ended_frames)
+        }
+    }
+
+    /// Allows the queries of frame `number` to be read back, the GPU has finished it.
+    pub fn frame_finished(&mut self, number: u64) {
+        for frame in self.pending.iter_mut() {
+            if frame.number == number {
+                frame.finished = true;
+            }
+        }
+    }
+
+    /// Timings of the most recently resolved frame.
+    pub fn latest(&self) -> Option<FrameTimings> {
+        self.history.back().copied()
+    }
+
+    /// Timings of the resolved frames, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &FrameTimings> {
+        self.history.iter()
+    }
+
+    pub fn stats(&self) -> Option<ProfilerStats> {
+        if self.history.is_empty() {
+            return None;
+        }
+        let frames = self.history.len();
+        let average =
+            |f: fn(&FrameTimings) -> f64| self.history.iter().map(f).sum::<f64>() / frames as f64;
+        Some(ProfilerStats {
+            frames,
+            geometry_ms: average(|t| t.geometry_ms),
+            lighting_ms: average(|t| t.lighting_ms),
+            present_ms: average(|t| t.present_ms),
+            gpu_ms: Percentiles::of(self.history.iter().map(|t| t.gpu_ms).collect()),
+            frame_ms: Percentiles::of(self.history.iter().map(|t| t.frame_ms).collect()),
+            draw_calls: average(|t| t.draw_calls as f64),
+            visible_objects: average(|t| t.visible_objects as f64),
+            culled_objects: average(|t| t.culled_objects as f64),
+        })
+    }
+
+    /// Reads back every finished frame, in submission order.
+    fn resolve(&mut self) {
+        while let Some(frame) = self.pending.front() {
+            if !frame.finished {
+                // Frames finish in order, so a later finished frame means this one was never
+                // submitted.
+                if self.pending.iter().any(|frame| frame.finished) {
+                    self.pending.pop_front();
+                    continue;
+                }
+                break;
+            }
+            let timestamps = match self.read_slot(frame.slot) {
+                Some(timestamps) => timestamps,
+                None => break,
+            };
+            let ms = |from: TimestampPoint, to: TimestampPoint| {
+                let ticks = timestamps[to as usize].wrapping_sub(timestamps[from as usize])
+                    & self.timestamp_mask;
+                ticks as f64 * self.timestamp_period / 1_000_000.0
+            };
+            let timings = FrameTimings {
+                geometry_ms: ms(TimestampPoint::FrameStart, TimestampPoint::GeometryEnd),
+                lighting_ms: ms(TimestampPoint::GeometryEnd, TimestampPoint::LightingEnd),
+                present_ms: ms(TimestampPoint::LightingEnd, TimestampPoint::FrameEnd),
+                gpu_ms: ms(TimestampPoint::FrameStart, TimestampPoint::FrameEnd),
+                frame_ms: frame.frame_ms,
+                draw_calls: frame.draw_calls,
+                visible_objects: frame.visible_objects,
+                culled_objects: frame.culled_objects,
+            };
+            Self::trace_frame(frame.submitted, &timings);
+            self.pending.pop_front();
+            if self.history.len() == HISTORY {
+                self.history.pop_front();
+            }
+            self.history.push_back(timings);
+        }
+
+        if self.last_report.elapsed() >= self.report_every {
+            self.last_report = Instant::now();
+            if let Some(stats) = self.stats() {
+                log::info!(
+                    "GPU geometry {:.3} ms, lighting {:.3} ms, present {:.3} ms; gpu p50/p95/p99 {:.3}/{:.3}/{:.3} ms; frame p50/p95/p99 {:.3}/{:.3}/{:.3} ms; {:.0} draw calls, {:.0} visible and {:.0} culled objects",
+                    stats.geometry_ms,
+                    stats.lighting_ms,
+                    stats.present_ms,
+                    stats.gpu_ms.p50,
+                    stats.gpu_ms.p95,
+                    stats.gpu_ms.p99,
+                    stats.frame_ms.p50,
+                    stats.frame_ms.p95,
+                    stats.frame_ms.p99,
+                    stats.draw_calls,
+                    stats.visible_objects,
+                    stats.culled_objects
+                );
+            }
+        }
+    }
+
+    // The trace shows the passes back to back starting at the submission of the frame, the GPU
+    // clock is not calibrated against the CPU one.
+    fn trace_frame(submitted: Instant, timings: &FrameTimings) {
+        let mut start = submitted;
+        for (name, ms) in [
+            ("geometry pass", timings.geometry_ms),
+            ("lighting pass", timings.lighting_ms),
+            ("present", timings.present_ms),
+        ]
+        .iter()
+        {
+            let duration = Duration::from_secs_f64(ms.max(0.0) / 1000.0);
+            tracing::record_gpu_span(name, start, duration);
+            start += duration;
+        }
+    }
+
+    fn read_slot(&self, slot: usize) -> Option<[u64; TIMESTAMPS_PER_FRAME]> {
+        let mut timestamps = [0u64; TIMESTAMPS_PER_FRAME];
+        let result = unsafe {
+            self.device.pointers().GetQueryPoolResults(
+                self.device.internal_object(),
+                self.pool.internal_object(),
+                (slot * TIMESTAMPS_PER_FRAME) as u32,
+                TIMESTAMPS_PER_FRAME as u32,
+                mem::size_of_val(&timestamps),
+                timestamps.as_mut_ptr() as *mut _,
+                mem::size_of::<u64>() as u64,
+                vk::QUERY_RESULT_64_BIT,
+            )
+        };
+        match result {
+            vk::SUCCESS => Some(timestamps),
+            vk::NOT_READY => None,
+            error => {
+                log::error!("Failed to read timestamps: {}", error);
+                None
+            }
+        }
+    }
+
+    fn record<F>(&self, subpass: Option<ProfiledSubpass>, body: F) -> TimestampCommandBuffer
+    where
+        F: FnOnce(&mut UnsafeCommandBufferBuilder<StandardCommandPoolBuilder>),
+    {
+        let kind: Kind<_, Arc<dyn FramebufferAbstract + Send + Sync>> = Kind::Secondary {
+            render_pass: subpass.map(|subpass| KindSecondaryRenderPass {
+                subpass,
+                framebuffer: None,
+            }),
+            occlusion_query: KindOcclusionQuery::Forbidden,
+            query_statistics_flags: QueryPipelineStatisticFlags::none(),
+        };
+        let command_pool = Device::standard_command_pool(&self.device, self.queue.family());
+        unsafe {
+            let mut builder =
+                UnsafeCommandBufferBuilder::new(&command_pool, kind, Flags::OneTimeSubmit).unwrap();
+            body(&mut builder);
+            TimestampCommandBuffer {
+                inner: builder.build().unwrap(),
+                _pool: self.pool.clone(),
+            }
+        }
+    }
+}
+
+// `timestampValidBits` of the queue family of `queue`, vulkano doesn't expose it.
+fn timestamp_valid_bits(queue: &Queue) -> u32 {
+    let physical = queue.device().physical_device();
+    let pointers = physical.instance().pointers();
+    unsafe {
+        let mut count = 0;
+        pointers.GetPhysicalDeviceQueueFamilyProperties(
+            physical.internal_object(),
+            &mut count,
+            ptr::null_mut(),
+        );
+        let mut properties = Vec::with_capacity(count as usize);
+        pointers.GetPhysicalDeviceQueueFamilyProperties(
+            physical.internal_object(),
+            &mut count,
+            properties.as_mut_ptr(),
+        );
+        properties.set_len(count as usize);
+        properties
+            .get(queue.family().id() as usize)
+            .map_or(0, |family: &vk::QueueFamilyProperties| {
+                family.timestampValidBits
+            })
+    }
+}
+
+/// Secondary command buffer that only touches the query pool of the profiler.
+pub struct TimestampCommandBuffer {
+    inner: UnsafeCommandBuffer<StandardCommandPoolAlloc>,
+    // The queries have to outlive the command buffer.
+    _pool: Arc<UnsafeQueryPool>,
+}
+
+unsafe impl DeviceOwned for TimestampCommandBuffer {
+    fn device(&self) -> &Arc<Device> {
+        self.inner.device()
+    }
+}
+
+// No buffer or image is accessed, so there is nothing to lock or synchronize.
+unsafe impl CommandBuffer for TimestampCommandBuffer {
+    type PoolAlloc = StandardCommandPoolAlloc;
+
+    fn inner(&self) -> &UnsafeCommandBuffer<StandardCommandPoolAlloc> {
+        &self.inner
+    }
+
+    fn lock_submit(
+        &self,
+        _future: &dyn GpuFuture,
+        _queue: &Queue,
+    ) -> Result<(), CommandBufferExecError> {
+        Ok(())
+    }
+
+    unsafe fn unlock(&self) {}
+
+    fn check_buffer_access(
+        &self,
+        _buffer: &dyn BufferAccess,
+        _exclusive: bool,
+        _queue: &Queue,
+    ) -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        Err(AccessCheckError::Unknown)
+    }
+
+    fn check_image_access(
+        &self,
+        _image: &dyn ImageAccess,
+        _layout: ImageLayout,
+        _exclusive: bool,
+        _queue: &Queue,
+    ) -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        Err(AccessCheckError::Unknown)
+    }
+}
@@ -1,11 +1,203 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::time::Duration;
 use std::time::Instant;
 use std::time::SystemTime;
+
+/// Track of the GPU pass timings in the exported trace.
+const GPU_TID: u64 = 0;
+
+static TRACE: Mutex<Option<TraceBuffer>> = Mutex::new(None);
+static NEXT_TID: AtomicU64 = AtomicU64::new(GPU_TID + 1);
+
+thread_local! {
+    static TID: Cell<u64> = Cell::new(0);
+}
+
+struct TraceEvent {
+    name: String,
+    category: &'static str,
+    tid: u64,
+    start: Instant,
+    duration: Duration,
+}
+
+/// Ring buffer of the last recorded spans.
+struct TraceBuffer {
+    epoch: Instant,
+    capacity: usize,
+    events: VecDeque<TraceEvent>,
+    threads: Vec<(u64, String)>,
+}
+
+/// Starts recording spans of `timed`, `Tracer::run` and the GPU profiler, keeping the last
+/// `capacity` of them.
+pub fn enable_trace(capacity: usize) {
+    let mut trace = TRACE.lock().unwrap();
+    if trace.is_none() {
+        *trace = Some(TraceBuffer {
+            epoch: Instant::now(),
+            capacity: capacity.max(1),
+            events: VecDeque::with_capacity(capacity.max(1)),
+            threads: vec![(GPU_TID, "GPU".to_string())],
+        });
+    }
+}
+
+pub fn is_trace_enabled() -> bool {
+    TRACE.lock().map(|trace| trace.is_some()).unwrap_or(false)
+}
+
+fn current_tid(trace: &mut TraceBuffer) -> u64 {
+    TID.with(|tid| {
+        if tid.get() == 0 {
+            tid.set(NEXT_TID.fetch_add(1, Ordering::SeqCst));
+        }
+        let id = tid.get();
+        if !trace.threads.iter().any(|(known, _)| *known == id) {
+            let thread = std::thread::current();
+            let name = thread.name().unwrap_or("unnamed").to_string();
+            trace.threads.push((id, name));
+        }
+        id
+    })
+}
+
+fn push_event(trace: &mut TraceBuffer, event: TraceEvent) {
+    if trace.events.len() == trace.capacity {
+        trace.events.pop_front();
+    }
+    trace.events.push_back(event);
+}
+
+/// Records a span of the calling thread, does nothing unless tracing is enabled.
+pub fn record_span(name: &str, category: &'static str, start: Instant, duration: Duration) {
+    if let Ok(mut trace) = TRACE.lock() {
+        if let Some(trace) = trace.as_mut() {
+            let tid = current_tid(trace);
+            push_event(
+                trace,
+                TraceEvent {
+                    name: name.to_string(),
+                    category,
+                    tid,
+                    start,
+                    duration,
+                },
+            );
+        }
+    }
+}
+
+/// Records GPU work on the GPU track.
+///
+/// GPU and CPU clocks are not calibrated, callers place the span relative to a CPU instant such
+/// as the submission of the frame.
+pub fn record_gpu_span(name: &str, start: Instant, duration: Duration) {
+    if let Ok(mut trace) = TRACE.lock() {
+        if let Some(trace) = trace.as_mut() {
+            push_event(
+                trace,
+                TraceEvent {
+                    name: name.to_string(),
+                    category: "gpu",
+                    tid: GPU_TID,
+                    start,
+                    duration,
+                },
+            );
+        }
+    }
+}
+
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes the recorded spans in the Chrome Trace Event format, readable by `chrome://tracing`
+/// and Perfetto.
+pub fn write_chrome_trace<W: Write>(writer: &mut W) -> io::Result<()> {
+    let trace = TRACE.lock().unwrap();
+    let trace = match trace.as_ref() {
+        Some(trace) => trace,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "tracing is not enabled",
+            ))
+        }
+    };
+    let pid = std::process::id();
+    writeln!(writer, "{{\"traceEvents\":[")?;
+    let mut first = true;
+    for (tid, name) in &trace.threads {
+        if !first {
+            writeln!(writer, ",")?;
+        }
+        first = false;
+        write!(
+            writer,
+            "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":{},\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+            pid,
+            tid,
+            escape_json(name)
+        )?;
+    }
+    for event in &trace.events {
+        if !first {
+            writeln!(writer, ",")?;
+        }
+        first = false;
+        // Spans recorded before `enable_trace` can't be represented, they start at zero.
+        let ts = event
+            .start
+            .checked_duration_since(trace.epoch)
+            .unwrap_or_default();
+        write!(
+            writer,
+            "{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"pid\":{},\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}}}",
+            escape_json(&event.name),
+            event.category,
+            pid,
+            event.tid,
+            ts.as_secs_f64() * 1_000_000.0,
+            event.duration.as_secs_f64() * 1_000_000.0
+        )?;
+    }
+    writeln!(writer, "\n],\"displayTimeUnit\":\"ms\"}}")?;
+    Ok(())
+}
+
+pub fn dump_chrome_trace<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path.as_ref())?);
+    write_chrome_trace(&mut writer)?;
+    writer.flush()?;
+    log::info!("Trace written to {}", path.as_ref().display());
+    Ok(())
+}
+
 pub fn timed<T>(tag: &str, body: impl FnOnce() -> T) -> T {
     let start = Instant::now();
     let result = body();
     let end = Instant::now();
     log::trace!("run of {} {:?}", tag, (end - start));
+    record_span(tag, "cpu", start, end - start);
     result
 }
 
@@ -34,6 +226,7 @@ impl Tracer {
         let end = Instant::now();
         let new_ts = SystemTime::now();
         let dur = end - start;
+        record_span(&self.tag, "cpu", start, dur);
         self.counter = (self.counter + dur) / 2;
         match new_ts.duration_since(self.last_time) {
             Ok(elapsed) => {
@@ -1,19 +1,94 @@
+use crate::assets::AssetResolver;
+use crate::assets::DEFAULT_TEXTURE;
+use crate::debug::labels;
+use crate::debug::tracing::timed;
+use crate::engine::error::EngineError;
+use crate::engine::loader::AssetLoader;
+use crate::engine::loader::AssetRequest;
+use crate::engine::loader::LoadedAsset;
+use crate::engine::loader::PreparedLod;
+use crate::engine::loader::PreparedMesh;
+use crate::engine::texture::load_texture_from_bytes;
+use crate::engine::texture::TextureCache;
+use crate::figure::bounds::Aabb;
 use crate::figure::FigureMutation;
-use crate::figure::FigureSet;
 use crate::figure::PerVerexParams;
-use crate::figure::RenderableMesh;
 use crate::scene::camera::ViewAndProject;
+use crate::scene::FigureHandle;
 use crate::scene::Scene;
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io;
-use std::io::BufReader;
+use std::iter;
 use std::sync::Arc;
+use std::sync::Mutex;
 use vulkano::buffer::BufferUsage;
 use vulkano::buffer::CpuAccessibleBuffer;
-use vulkano::device::Device;
+use vulkano::buffer::ImmutableBuffer;
+use vulkano::descriptor::DescriptorSet;
 use vulkano::device::Queue;
 use vulkano::format::Format;
-use vulkano::image::{Dimensions, ImmutableImage, MipmapsCount};
+use vulkano::image::ImmutableImage;
+use vulkano::sync::GpuFuture;
+
+/// Placement of one drawn instance of a `CachedEntity`.
+#[derive(Debug, Clone, Copy)]
+pub struct CachedInstance {
+    /// Model matrix, columns first like the shader uniforms.
+    pub model: [[f32; 4]; 4],
+    /// World space bounds, `None` for an empty mesh.
+    pub bounds: Option<Aabb>,
+}
+
+/// One instance per mutation, or a single untransformed one for a figure without mutations.
+fn cached_instances(
+    mesh_bounds: Option<Aabb>,
+    mutations: &[FigureMutation],
+) -> Vec<CachedInstance> {
+    if mutations.is_empty() {
+        return vec![CachedInstance {
+            model: FigureMutation::unit().model_matrix().into(),
+            bounds: mesh_bounds,
+        }];
+    }
+    mutations
+        .iter()
+        .map(|mutation| CachedInstance {
+            model: mutation.model_matrix().into(),
+            bounds: mesh_bounds.map(|bounds| bounds.transformed(mutation)),
+        })
+        .collect()
+}
+
+/// Level of detail of a `CachedIndexedEntity`.
+#[derive(Debug, Clone)]
+pub struct CachedLod {
+    pub vert_params: Arc<ImmutableBuffer<[PerVerexParams]>>,
+    pub indices: Arc<ImmutableBuffer<[u32]>>,
+    pub screen_size: f32,
+}
+
+/// Descriptor set sampling the textures of an entity, built by the geometry pass on first use.
+///
+/// Shared by the snapshots of the entity until its textures change.
+#[derive(Clone, Default)]
+pub struct TextureSet(Arc<Mutex<Option<Arc<dyn DescriptorSet + Send + Sync>>>>);
+
+impl TextureSet {
+    pub fn get_or_build<F>(&self, build: F) -> Arc<dyn DescriptorSet + Send + Sync>
+    where
+        F: FnOnce() -> Arc<dyn DescriptorSet + Send + Sync>,
+    {
+        self.0.lock().unwrap().get_or_insert_with(build).clone()
+    }
+}
+
+impl fmt::Debug for TextureSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let built = self.0.lock().map(|set| set.is_some()).unwrap_or(false);
+        f.debug_struct("TextureSet").field("built", &built).finish()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum CachedEntity {
@@ -23,18 +98,25 @@ pub enum CachedEntity {
 
 #[derive(Debug, Clone)]
 pub struct CachedIndexedEntity {
-    pub vert_params: Arc<CpuAccessibleBuffer<[PerVerexParams]>>,
-    pub indices: Arc<CpuAccessibleBuffer<[u32]>>,
-    pub mutations: Vec<Arc<CpuAccessibleBuffer<FigureMutation>>>,
+    pub vert_params: Arc<ImmutableBuffer<[PerVerexParams]>>,
+    pub indices: Arc<ImmutableBuffer<[u32]>>,
+    pub instances: Vec<CachedInstance>,
+    /// Coarser meshes, from the finest to the coarsest.
+    pub lods: Vec<CachedLod>,
+    /// Level each instance was last drawn with, shared by the snapshots of the entity so that
+    /// the geometry pass can apply hysteresis.
+    pub lod_levels: Arc<Mutex<Vec<usize>>>,
     pub color_texture: Arc<ImmutableImage<Format>>,
     pub normal_texture: Arc<ImmutableImage<Format>>,
+    pub texture_set: TextureSet,
 }
 
 impl CachedIndexedEntity {
     pub fn new(
-        vert_params: Arc<CpuAccessibleBuffer<[PerVerexParams]>>,
-        indices: Arc<CpuAccessibleBuffer<[u32]>>,
-        mutations: Vec<Arc<CpuAccessibleBuffer<FigureMutation>>>,
+        vert_params: Arc<ImmutableBuffer<[PerVerexParams]>>,
+        indices: Arc<ImmutableBuffer<[u32]>>,
+        instances: Vec<CachedInstance>,
+        lods: Vec<CachedLod>,
         color_texture: Arc<ImmutableImage<Format>>,
         normal_texture: Arc<ImmutableImage<Format>>,
     ) -> Self {
@@ -42,211 +124,493 @@ impl CachedIndexedEntity {
         CachedIndexedEntity {
             vert_params,
             indices,
-            mutations,
+            lod_levels: Arc::new(Mutex::new(vec![0; instances.len()])),
+            instances,
+            lods,
             color_texture,
             normal_texture,
+            texture_set: TextureSet::default(),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct CachedRegularEntity {
-    pub vert_params: Arc<CpuAccessibleBuffer<[PerVerexParams]>>,
-    pub mutations: Vec<Arc<CpuAccessibleBuffer<FigureMutation>>>,
+    pub vert_params: Arc<ImmutableBuffer<[PerVerexParams]>>,
+    pub instances: Vec<CachedInstance>,
     pub color_texture: Arc<ImmutableImage<Format>>,
     pub normal_texture: Arc<ImmutableImage<Format>>,
+    pub texture_set: TextureSet,
 }
 
 impl CachedRegularEntity {
     pub fn new(
-        vert_params: Arc<CpuAccessibleBuffer<[PerVerexParams]>>,
-        mutations: Vec<Arc<CpuAccessibleBuffer<FigureMutation>>>,
+        vert_params: Arc<ImmutableBuffer<[PerVerexParams]>>,
+        instances: Vec<CachedInstance>,
         color_texture: Arc<ImmutableImage<Format>>,
         normal_texture: Arc<ImmutableImage<Format>>,
     ) -> Self {
         log::trace!("insance of {}", std::any::type_name::<Self>());
         CachedRegularEntity {
             vert_params,
-            mutations,
+            instances,
             color_texture,
             normal_texture,
+            texture_set: TextureSet::default(),
         }
     }
 }
 
+impl CachedEntity {
+    pub fn instances(&self) -> &[CachedInstance] {
+        match self {
+            CachedEntity::Indexed(i) => &i.instances,
+            CachedEntity::Regular(r) => &r.instances,
+        }
+    }
+
+    fn set_instances(&mut self, instances: Vec<CachedInstance>) {
+        match self {
+            CachedEntity::Indexed(i) => {
+                i.lod_levels = Arc::new(Mutex::new(vec![0; instances.len()]));
+                i.instances = instances;
+            }
+            CachedEntity::Regular(r) => {
+                r.instances = instances;
+            }
+        }
+    }
+
+    /// Color and normal textures and the descriptor set sampling them.
+    pub fn textures(
+        &self,
+    ) -> (
+        &Arc<ImmutableImage<Format>>,
+        &Arc<ImmutableImage<Format>>,
+        &TextureSet,
+    ) {
+        match self {
+            CachedEntity::Indexed(i) => (&i.color_texture, &i.normal_texture, &i.texture_set),
+            CachedEntity::Regular(r) => (&r.color_texture, &r.normal_texture, &r.texture_set),
+        }
+    }
+
+    // The descriptor set is dropped only when a texture actually changes, typically when it
+    // replaces the placeholder.
+    fn set_textures(
+        &mut self,
+        color_texture: Arc<ImmutableImage<Format>>,
+        normal_texture: Arc<ImmutableImage<Format>>,
+    ) {
+        let (color, normal, texture_set) = match self {
+            CachedEntity::Indexed(i) => (
+                &mut i.color_texture,
+                &mut i.normal_texture,
+                &mut i.texture_set,
+            ),
+            CachedEntity::Regular(r) => (
+                &mut r.color_texture,
+                &mut r.normal_texture,
+                &mut r.texture_set,
+            ),
+        };
+        if Arc::ptr_eq(color, &color_texture) && Arc::ptr_eq(normal, &normal_texture) {
+            return;
+        }
+        *color = color_texture;
+        *normal = normal_texture;
+        *texture_set = TextureSet::default();
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CachedEntities {
+    /// Changes whenever the entities do, passes can keep what they derive from them until then.
+    pub revision: u64,
     pub entities: Vec<CachedEntity>,
 }
 
+const TEXTURE_FORMAT: Format = Format::R8G8B8A8Srgb;
+
 #[derive(Debug, Clone)]
+struct CachedFigure {
+    mesh_revision: u32,
+    mutations_revision: u32,
+    color_texture_path: String,
+    normal_texture_path: String,
+    mesh_bounds: Option<Aabb>,
+    instances: Vec<CachedInstance>,
+    // `None` until the loader has prepared the mesh.
+    entity: Option<CachedEntity>,
+}
+
+/// GPU side copy of a `Scene`.
+///
+/// Figures are tracked by their `FigureHandle`, so a scene change only uploads the figures whose
+/// revisions differ from the cached ones.
+///
+/// Meshes are static and live in device local memory, they are uploaded through staging buffers.
+/// The uploads are not waited for: `take_uploads` hands them over to the frame which is going to
+/// use the buffers, so they are executed before the first draw.
+///
+/// Decoding and mesh preparation run on the `AssetLoader` workers. Until a mesh is ready its
+/// figure is not drawn, until a texture is ready the placeholder texture is used instead.
 pub struct SceneCache {
+    // `Scene::scene_id` of the cached scene, handles of other scenes mean different figures.
+    scene_id: u64,
     cache_id: u32,
+    figures: BTreeMap<FigureHandle, CachedFigure>,
     state: Option<CachedEntities>,
+    // `CachedEntities::revision` of the last snapshot.
+    revision: u64,
+    textures: TextureCache,
+    placeholder: Option<Arc<ImmutableImage<Format>>>,
+    loader: AssetLoader,
+    pending_uploads: Option<Box<dyn GpuFuture>>,
 }
 
 impl SceneCache {
-    pub fn default() -> Self {
+    pub fn default() -> Result<Self, EngineError> {
+        Self::new(AssetResolver::default())
+    }
+
+    /// Fails if the loader threads can't be spawned.
+    pub fn new(assets: AssetResolver) -> Result<Self, EngineError> {
         log::trace!("insance of {}", std::any::type_name::<Self>());
-        SceneCache {
+        Ok(SceneCache {
+            scene_id: 0,
             cache_id: 0,
+            figures: BTreeMap::new(),
             state: None,
-        }
+            revision: 0,
+            textures: TextureCache::new(),
+            placeholder: None,
+            loader: AssetLoader::new(2, assets).map_err(EngineError::Thread)?,
+            pending_uploads: None,
+        })
+    }
+
+    /// Returns the future of every upload started since the previous call.
+    pub fn take_uploads(&mut self) -> Option<Box<dyn GpuFuture>> {
+        self.pending_uploads.take()
     }
 
+    /// Returns the current GPU state of the scene.
+    ///
+    /// `queue` is used for uploads, it should be a transfer queue when the device has one.
+    /// Fails if the placeholder texture or a mesh can't be uploaded.
     pub fn get_cache<T: ViewAndProject + Sized>(
         &mut self,
         scene: &Scene<T>,
-        device: Arc<Device>,
         queue: Arc<Queue>,
-    ) -> CachedEntities {
-        if scene.global_scene_id == self.cache_id {
-            match &self.state {
-                Some(cached) => cached.clone(),
-                None => {
-                    let new_cache = Self::prepare_cache(&scene.figures, device, queue);
-                    self.state = Some(new_cache.clone());
-                    new_cache
-                }
+    ) -> Result<CachedEntities, EngineError> {
+        let loaded = self.receive_loaded(&queue)?;
+        match &self.state {
+            Some(cached)
+                if scene.scene_id() == self.scene_id
+                    && scene.global_scene_id() == self.cache_id
+                    && !loaded =>
+            {
+                Ok(cached.clone())
             }
-        } else {
-            let new_cache = Self::prepare_cache(&scene.figures, device, queue);
-            self.state = Some(new_cache.clone());
-            self.cache_id = scene.global_scene_id;
-            new_cache
+            _ => timed("scene cache rebuild", || {
+                let switched = scene.scene_id() != self.scene_id;
+                if switched {
+                    log::trace!("scene cache switched to scene {}", scene.scene_id());
+                    self.figures.clear();
+                    self.scene_id = scene.scene_id();
+                }
+                if switched || scene.global_scene_id() != self.cache_id {
+                    self.update(scene);
+                    self.cache_id = scene.global_scene_id();
+                }
+                let new_cache = self.snapshot(&queue)?;
+                self.state = Some(new_cache.clone());
+                let referenced = self.figures.values().flat_map(|cached| {
+                    iter::once(cached.color_texture_path.as_str())
+                        .chain(iter::once(cached.normal_texture_path.as_str()))
+                });
+                self.textures.evict_unused(referenced);
+                Ok(new_cache)
+            }),
         }
     }
 
-    fn prepare_cache(
-        figures: &[FigureSet],
-        device: Arc<Device>,
-        queue: Arc<Queue>,
-    ) -> CachedEntities {
-        log::trace!("insance of {}", std::any::type_name::<CachedEntities>());
-
-        let entities = figures
-            .iter()
-            .map(|figure_set| {
-                let mutations = figure_set
-                    .mutations
-                    .clone()
-                    .into_iter()
-                    .map(|mutation| {
-                        CpuAccessibleBuffer::from_data(
-                            device.clone(),
-                            BufferUsage::all(),
-                            false,
-                            mutation,
-                        )
-                        .unwrap()
-                    })
-                    .collect();
-
-                let color_texture = load_texture_by_path(
-                    figure_set.color_texture_path.clone(),
-                    Format::R8G8B8A8Srgb,
-                    queue.clone(),
-                )
-                .unwrap();
-                let normal_texture = load_texture_by_path(
-                    figure_set.color_texture_path.clone(),
-                    Format::R8G8B8A8Srgb,
-                    queue.clone(),
-                )
-                .unwrap();
-
-                match figure_set.mesh.clone() {
-                    RenderableMesh::Indexed(ind) => {
-                        let per_vertex_params: Vec<PerVerexParams> =
-                            ind.points.into_iter().map(|p| p.to_vert()).collect();
-
-                        let ver_buff = CpuAccessibleBuffer::from_iter(
-                            device.clone(),
-                            BufferUsage::all(),
-                            false,
-                            per_vertex_params.into_iter(),
-                        )
-                        .unwrap();
-
-                        let indices: Vec<u32> = ind.indices;
-
-                        let indices_buff = CpuAccessibleBuffer::from_iter(
-                            device.clone(),
-                            BufferUsage::all(),
-                            false,
-                            indices.into_iter(),
-                        )
-                        .unwrap();
-
-                        CachedEntity::Indexed(CachedIndexedEntity::new(
-                            ver_buff,
-                            indices_buff,
-                            mutations,
-                            color_texture,
-                            normal_texture,
-                        ))
+    /// Brings cached figures in sync with the scene, requesting only what changed.
+    fn update<T: ViewAndProject + Sized>(&mut self, scene: &Scene<T>) {
+        let before = self.figures.len();
+        self.figures
+            .retain(|handle, _| scene.figure(*handle).is_some());
+        let removed = before - self.figures.len();
+
+        let mut requested = 0;
+        let mut mutated = 0;
+        for (handle, figure) in scene.figures() {
+            match self.figures.get_mut(&handle) {
+                Some(cached) if cached.mesh_revision == figure.mesh_revision => {
+                    if cached.mutations_revision != figure.mutations_revision {
+                        cached.instances =
+                            cached_instances(cached.mesh_bounds, &figure.set.mutations);
+                        if let Some(entity) = cached.entity.as_mut() {
+                            entity.set_instances(cached.instances.clone());
+                        }
+                        cached.mutations_revision = figure.mutations_revision;
+                        mutated += 1;
                     }
-                    RenderableMesh::Regular(reg) => {
-                        let per_vertex_params: Vec<PerVerexParams> =
-                            reg.points.into_iter().map(|p| p.to_vert()).collect();
-
-                        let ver_buff = CpuAccessibleBuffer::from_iter(
-                            device.clone(),
-                            BufferUsage::all(),
-                            false,
-                            per_vertex_params.into_iter(),
-                        )
-                        .unwrap();
-                        CachedEntity::Regular(CachedRegularEntity::new(
-                            ver_buff,
-                            mutations,
-                            color_texture,
-                            normal_texture,
-                        ))
+                }
+                _ => {
+                    // The previous version of the figure stays visible until the new mesh is
+                    // uploaded.
+                    let previous_entity = self
+                        .figures
+                        .remove(&handle)
+                        .and_then(|cached| cached.entity);
+                    self.loader.request(AssetRequest::Mesh {
+                        handle,
+                        revision: figure.mesh_revision,
+                        mesh: figure.set.mesh.clone(),
+                        lods: figure.set.lods.clone(),
+                    });
+                    for path in [
+                        &figure.set.color_texture_path,
+                        &figure.set.normal_texture_path,
+                    ]
+                    .iter()
+                    {
+                        if self.textures.start_loading(path, TEXTURE_FORMAT) {
+                            self.loader.request(AssetRequest::Texture {
+                                path: path.to_string(),
+                            });
+                        }
                     }
+                    let mesh_bounds = figure.set.mesh.bounds();
+                    let instances = cached_instances(mesh_bounds, &figure.set.mutations);
+                    self.figures.insert(
+                        handle,
+                        CachedFigure {
+                            mesh_revision: figure.mesh_revision,
+                            mutations_revision: figure.mutations_revision,
+                            color_texture_path: figure.set.color_texture_path.clone(),
+                            normal_texture_path: figure.set.normal_texture_path.clone(),
+                            mesh_bounds,
+                            instances,
+                            entity: previous_entity,
+                        },
+                    );
+                    requested += 1;
                 }
+            }
+        }
+        log::trace!(
+            "scene cache updated: {} requested, {} mutated, {} removed",
+            requested,
+            mutated,
+            removed
+        );
+    }
+
+    /// Uploads everything the loader has finished since the previous frame.
+    ///
+    /// Returns `true` if the drawn state has to be rebuilt.
+    fn receive_loaded(&mut self, queue: &Arc<Queue>) -> Result<bool, EngineError> {
+        let placeholder = self.placeholder(queue)?;
+        let mut changed = false;
+        for asset in self.loader.poll() {
+            match asset {
+                LoadedAsset::Texture {
+                    path,
+                    texture: Ok(decoded),
+                } => {
+                    let textures = &mut self.textures;
+                    match timed("texture upload", || {
+                        textures.insert_decoded(&path, TEXTURE_FORMAT, decoded, queue)
+                    }) {
+                        Ok(Some(upload)) => join_upload(&mut self.pending_uploads, upload),
+                        Ok(None) => (),
+                        Err(e) => log::error!("Failed to upload texture {}: {}", path, e),
+                    }
+                    changed = true;
+                }
+                LoadedAsset::Texture {
+                    path,
+                    texture: Err(e),
+                } => {
+                    log::error!("Failed to load texture {}: {}", path, e);
+                    self.textures.failed_loading(&path);
+                }
+                LoadedAsset::Mesh {
+                    handle,
+                    revision,
+                    mesh,
+                    lods,
+                } => match self.figures.get_mut(&handle) {
+                    // Results for removed or since updated figures are dropped.
+                    Some(cached) if cached.mesh_revision == revision => {
+                        let (entity, upload) = timed("mesh upload", || {
+                            upload_mesh(
+                                handle,
+                                mesh,
+                                lods,
+                                cached.instances.clone(),
+                                placeholder.clone(),
+                                queue,
+                            )
+                        })?;
+                        cached.entity = Some(entity);
+                        join_upload(&mut self.pending_uploads, upload);
+                        changed = true;
+                    }
+                    _ => (),
+                },
+            }
+        }
+        Ok(changed)
+    }
+
+    fn placeholder(
+        &mut self,
+        queue: &Arc<Queue>,
+    ) -> Result<Arc<ImmutableImage<Format>>, EngineError> {
+        if let Some(placeholder) = &self.placeholder {
+            return Ok(placeholder.clone());
+        }
+        let placeholder = empty_texture(TEXTURE_FORMAT, queue.clone())?;
+        self.placeholder = Some(placeholder.clone());
+        Ok(placeholder)
+    }
+
+    fn snapshot(&mut self, queue: &Arc<Queue>) -> Result<CachedEntities, EngineError> {
+        let placeholder = self.placeholder(queue)?;
+        let textures = &self.textures;
+        let entities = self
+            .figures
+            .values_mut()
+            .filter_map(|cached| {
+                let color_texture = textures
+                    .lookup(&cached.color_texture_path, TEXTURE_FORMAT)
+                    .unwrap_or_else(|| placeholder.clone());
+                let normal_texture = textures
+                    .lookup(&cached.normal_texture_path, TEXTURE_FORMAT)
+                    .unwrap_or_else(|| placeholder.clone());
+                cached.entity.as_mut().map(|entity| {
+                    entity.set_textures(color_texture, normal_texture);
+                    entity.clone()
+                })
             })
             .collect();
-        CachedEntities { entities }
+        self.revision += 1;
+        Ok(CachedEntities {
+            revision: self.revision,
+            entities,
+        })
     }
 }
 
-fn load_texture_by_path(
-    path: String,
-    format: Format,
-    queue: Arc<Queue>,
-) -> io::Result<Arc<ImmutableImage<Format>>> {
-    let (texture, _tex_future) = {
-        let f = File::open(path)?;
-        let b = BufReader::new(f);
+fn join_upload(pending_uploads: &mut Option<Box<dyn GpuFuture>>, upload: Box<dyn GpuFuture>) {
+    *pending_uploads = Some(match pending_uploads.take() {
+        Some(pending) => pending.join(upload).boxed(),
+        None => upload,
+    });
+}
 
-        let decoder = png::Decoder::new(b);
-        let (info, mut reader) = decoder.read_info().unwrap();
-        let dimensions = Dimensions::Dim2d {
-            width: info.width,
-            height: info.height,
-        };
-        let mut image_data = Vec::new();
-        image_data.resize((info.width * info.height * 4) as usize, 0);
-        reader.next_frame(&mut image_data).unwrap();
-
-        ImmutableImage::from_iter(
-            image_data.iter().cloned(),
-            dimensions,
-            MipmapsCount::One,
-            format,
-            queue,
-        )
-        .unwrap()
-    };
-    io::Result::Ok(texture)
-}
-
-pub fn empty_texture(format: Format, queue: Arc<Queue>) -> Arc<ImmutableImage<Format>> {
-    load_texture_by_path(
-        "/home/michey/Projects/hello_vulkan/src/kikansha/frame/resources/tex.png".to_string(),
-        format,
-        queue,
-    )
-    .unwrap()
+/// Uploads a prepared mesh, the textures are filled in by `SceneCache::snapshot`.
+///
+/// Only indexed meshes have levels of detail, the `lods` of a regular one are dropped.
+fn upload_mesh(
+    handle: FigureHandle,
+    mesh: PreparedMesh,
+    lods: Vec<PreparedLod>,
+    instances: Vec<CachedInstance>,
+    placeholder: Arc<ImmutableImage<Format>>,
+    queue: &Arc<Queue>,
+) -> Result<(CachedEntity, Box<dyn GpuFuture>), EngineError> {
+    match mesh {
+        PreparedMesh::Indexed { vertices, indices } => {
+            let (ver_buff, ver_upload) =
+                upload_device_local(vertices.into_iter(), BufferUsage::vertex_buffer(), queue)?;
+
+            let (indices_buff, indices_upload) =
+                upload_device_local(indices.into_iter(), BufferUsage::index_buffer(), queue)?;
+            labels::name_buffer(&*ver_buff, &format!("{:?} vertices", handle));
+            labels::name_buffer(&*indices_buff, &format!("{:?} indices", handle));
+            let mut upload = ver_upload.join(indices_upload).boxed();
+
+            let mut cached_lods = Vec::with_capacity(lods.len());
+            for (level, lod) in lods.into_iter().enumerate() {
+                let (lod_ver_buff, lod_ver_upload) = upload_device_local(
+                    lod.vertices.into_iter(),
+                    BufferUsage::vertex_buffer(),
+                    queue,
+                )?;
+                let (lod_indices_buff, lod_indices_upload) = upload_device_local(
+                    lod.indices.into_iter(),
+                    BufferUsage::index_buffer(),
+                    queue,
+                )?;
+                labels::name_buffer(
+                    &*lod_ver_buff,
+                    &format!("{:?} LOD {} vertices", handle, level + 1),
+                );
+                labels::name_buffer(
+                    &*lod_indices_buff,
+                    &format!("{:?} LOD {} indices", handle, level + 1),
+                );
+                upload = upload.join(lod_ver_upload).join(lod_indices_upload).boxed();
+                cached_lods.push(CachedLod {
+                    vert_params: lod_ver_buff,
+                    indices: lod_indices_buff,
+                    screen_size: lod.screen_size,
+                });
+            }
+
+            let entity = CachedEntity::Indexed(CachedIndexedEntity::new(
+                ver_buff,
+                indices_buff,
+                instances,
+                cached_lods,
+                placeholder.clone(),
+                placeholder,
+            ));
+            Ok((entity, upload))
+        }
+        PreparedMesh::Regular { vertices } => {
+            let (ver_buff, ver_upload) =
+                upload_device_local(vertices.into_iter(), BufferUsage::vertex_buffer(), queue)?;
+            labels::name_buffer(&*ver_buff, &format!("{:?} vertices", handle));
+            let entity = CachedEntity::Regular(CachedRegularEntity::new(
+                ver_buff,
+                instances,
+                placeholder.clone(),
+                placeholder,
+            ));
+            Ok((entity, ver_upload))
+        }
+    }
+}
+
+/// Copies `data` into a device local buffer through a host visible staging buffer.
+fn upload_device_local<T, I>(
+    data: I,
+    usage: BufferUsage,
+    queue: &Arc<Queue>,
+) -> Result<(Arc<ImmutableBuffer<[T]>>, Box<dyn GpuFuture>), EngineError>
+where
+    T: Send + Sync + 'static,
+    I: ExactSizeIterator<Item = T>,
+{
+    let staging = CpuAccessibleBuffer::from_iter(
+        queue.device().clone(),
+        BufferUsage::transfer_source(),
+        false,
+        data,
+    )?;
+    let (buffer, upload) = ImmutableBuffer::from_buffer(staging, usage, queue.clone())?;
+    // The buffer may be uploaded on a transfer queue, the semaphore orders it before the draws.
+    let upload = upload.then_signal_semaphore_and_flush()?;
+    Ok((buffer, upload.boxed()))
+}
+
+/// Texture embedded into the library, does not depend on any asset being installed.
+pub fn empty_texture(format: Format, queue: Arc<Queue>) -> io::Result<Arc<ImmutableImage<Format>>> {
+    load_texture_from_bytes(DEFAULT_TEXTURE, format, queue)
 }
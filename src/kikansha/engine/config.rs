@@ -0,0 +1,254 @@
+use crate::debug::messenger::DebugMessengerConfig;
+use serde::Deserialize;
+use vulkano::device::Features;
+use vulkano::format::Format;
+use vulkano::swapchain::{PresentMode, SupportedPresentModes};
+
+/// Swapchain present mode preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentModePreference {
+    /// Lowest latency available: mailbox, then immediate, then fifo.
+    Auto,
+    /// Wait for vertical blank, always supported.
+    Vsync,
+    Mailbox,
+    Immediate,
+}
+
+impl PresentModePreference {
+    /// Picks the present mode, falling back to fifo when the preferred one is not supported.
+    pub fn choose(self, available: SupportedPresentModes) -> PresentMode {
+        match self {
+            PresentModePreference::Auto => {
+                if available.mailbox {
+                    PresentMode::Mailbox
+                } else if available.immediate {
+                    PresentMode::Immediate
+                } else {
+                    PresentMode::Fifo
+                }
+            }
+            PresentModePreference::Mailbox if available.mailbox => PresentMode::Mailbox,
+            PresentModePreference::Immediate if available.immediate => PresentMode::Immediate,
+            _ => PresentMode::Fifo,
+        }
+    }
+}
+
+/// Swapchain surface format preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SurfaceFormatPreference {
+    Unorm,
+    Srgb,
+}
+
+impl SurfaceFormatPreference {
+    pub fn format(self) -> Format {
+        match self {
+            SurfaceFormatPreference::Unorm => Format::B8G8R8A8Unorm,
+            SurfaceFormatPreference::Srgb => Format::B8G8R8A8Srgb,
+        }
+    }
+}
+
+/// Which physical device to render with.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GpuSelection {
+    /// Best suitable device, see `engine::gpu::score`.
+    Auto,
+    /// Device index in enumeration order.
+    Index(usize),
+    /// Best suitable device whose name contains the string, case insensitive.
+    Name(String),
+    /// Device UUID as printed by `ressha --list-gpus`.
+    Uuid(String),
+}
+
+/// G-buffer attachment displayed instead of the lit scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugView {
+    None,
+    Position,
+    Normals,
+    Albedo,
+    Specular,
+}
+
+impl DebugView {
+    /// Value of `displayDebugTarget` in the deferred shader.
+    pub fn level(self) -> i32 {
+        match self {
+            DebugView::None => 0,
+            DebugView::Position => 1,
+            DebugView::Normals => 2,
+            DebugView::Albedo => 3,
+            DebugView::Specular => 4,
+        }
+    }
+
+    pub fn from_level(level: i32) -> Self {
+        match level {
+            1 => DebugView::Position,
+            2 => DebugView::Normals,
+            3 => DebugView::Albedo,
+            4 => DebugView::Specular,
+            _ => DebugView::None,
+        }
+    }
+}
+
+/// Settings of the renderer and of the window it creates.
+///
+/// Every field has a default, so a configuration file only needs the values it changes:
+///
+/// ```toml
+/// window_size = [1920, 1080]
+/// present_mode = "vsync"
+/// gpu = { name = "radeon" }
+///
+/// [debug_messenger]
+/// min_severity = "information"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RendererConfig {
+    pub window_size: [u32; 2],
+    pub title: String,
+    pub fullscreen: bool,
+    pub present_mode: PresentModePreference,
+    pub surface_format: SurfaceFormatPreference,
+    pub gpu: GpuSelection,
+    /// Device features a GPU has to support to be selected, they are enabled on the logical
+    /// device. Only set with `with_required_features`, not read from TOML.
+    #[serde(skip, default = "Features::none")]
+    pub required_features: Features,
+    pub validation: bool,
+    /// Filters of the validation messages, used only with `validation`.
+    pub debug_messenger: DebugMessengerConfig,
+    pub debug_view: DebugView,
+    /// Frames recorded ahead of the GPU, each with its own uniforms and fence.
+    pub frames_in_flight: usize,
+    /// Measures the passes with timestamp queries and logs the timings, see
+    /// `debug::profiler::GpuProfiler`.
+    pub gpu_profiler: bool,
+    /// Culls the instances of indexed meshes in a compute pass and draws them indirectly, see
+    /// `frame::culling::CullingSystem`. Falls back to culling on the CPU if the GPU doesn't
+    /// support `draw_indirect_first_instance`.
+    pub gpu_culling: bool,
+    /// Chrome Trace Event file written on exit and when F12 is pressed, tracing is disabled
+    /// without it. The GPU passes are only traced with `gpu_profiler`.
+    pub trace_file: Option<String>,
+    /// Number of most recent spans kept for the trace file.
+    pub trace_capacity: usize,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        RendererConfig {
+            window_size: [1280, 720],
+            title: "Vulkan".to_string(),
+            fullscreen: false,
+            present_mode: PresentModePreference::Auto,
+            surface_format: SurfaceFormatPreference::Unorm,
+            gpu: GpuSelection::Auto,
+            required_features: Features::none(),
+            validation: false,
+            debug_messenger: DebugMessengerConfig::default(),
+            debug_view: DebugView::None,
+            frames_in_flight: 2,
+            gpu_profiler: false,
+            gpu_culling: false,
+            trace_file: None,
+            trace_capacity: 100_000,
+        }
+    }
+}
+
+impl RendererConfig {
+    pub fn from_toml(source: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(source)
+    }
+
+    pub fn with_window_size(mut self, width: u32, height: u32) -> Self {
+        self.window_size = [width, height];
+        self
+    }
+
+    pub fn with_title<S: Into<String>>(mut self, title: S) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_fullscreen(mut self, fullscreen: bool) -> Self {
+        self.fullscreen = fullscreen;
+        self
+    }
+
+    pub fn with_present_mode(mut self, present_mode: PresentModePreference) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    /// Shortcut for `with_present_mode(PresentModePreference::Vsync)`.
+    pub fn with_vsync(self, vsync: bool) -> Self {
+        if vsync {
+            self.with_present_mode(PresentModePreference::Vsync)
+        } else {
+            self.with_present_mode(PresentModePreference::Auto)
+        }
+    }
+
+    pub fn with_surface_format(mut self, surface_format: SurfaceFormatPreference) -> Self {
+        self.surface_format = surface_format;
+        self
+    }
+
+    pub fn with_gpu(mut self, gpu: GpuSelection) -> Self {
+        self.gpu = gpu;
+        self
+    }
+
+    pub fn with_required_features(mut self, required_features: Features) -> Self {
+        self.required_features = required_features;
+        self
+    }
+
+    pub fn with_validation(mut self, validation: bool) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    pub fn with_debug_messenger(mut self, debug_messenger: DebugMessengerConfig) -> Self {
+        self.debug_messenger = debug_messenger;
+        self
+    }
+
+    pub fn with_debug_view(mut self, debug_view: DebugView) -> Self {
+        self.debug_view = debug_view;
+        self
+    }
+
+    pub fn with_frames_in_flight(mut self, frames_in_flight: usize) -> Self {
+        self.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    pub fn with_gpu_profiler(mut self, gpu_profiler: bool) -> Self {
+        self.gpu_profiler = gpu_profiler;
+        self
+    }
+
+    pub fn with_gpu_culling(mut self, gpu_culling: bool) -> Self {
+        self.gpu_culling = gpu_culling;
+        self
+    }
+
+    pub fn with_trace_file<S: Into<String>>(mut self, trace_file: S) -> Self {
+        self.trace_file = Some(trace_file.into());
+        self
+    }
+}
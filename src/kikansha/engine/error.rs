@@ -0,0 +1,249 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use vulkano::command_buffer::CommandBufferExecError;
+use vulkano::device::DeviceCreationError;
+use vulkano::framebuffer::RenderPassCreationError;
+use vulkano::image::ImageCreationError;
+use vulkano::instance::{InstanceCreationError, LayersListError, SupportedExtensionsError};
+use vulkano::memory::DeviceMemoryAllocError;
+use vulkano::pipeline::ComputePipelineCreationError;
+use vulkano::pipeline::GraphicsPipelineCreationError;
+use vulkano::sampler::SamplerCreationError;
+use vulkano::swapchain::{AcquireError, CapabilitiesError, SwapchainCreationError};
+use vulkano::sync::FlushError;
+use vulkano::OomError;
+
+/// Failure of the renderer.
+///
+/// `SurfaceLost` and `DeviceLost` are recovered from by the render loop, they are returned only
+/// if the recovery fails as well.
+#[derive(Debug)]
+pub enum EngineError {
+    /// The Vulkan library could not be loaded.
+    NoVulkanLoader,
+    Instance(InstanceCreationError),
+    /// No physical device supports the required features, extensions and queues, or the forced
+    /// one doesn't.
+    NoSuitableGpu,
+    /// A required instance or device extension or layer is not available.
+    MissingExtension(String),
+    Device(DeviceCreationError),
+    OutOfMemory(OomError),
+    ShaderLoad(&'static str, OomError),
+    Pipeline(GraphicsPipelineCreationError),
+    /// Failure to create a render pass, compute pipeline, image, sampler or texture.
+    Resource(String),
+    Window(vulkano_win::CreationError),
+    SurfaceLost,
+    DeviceLost,
+    Surface(CapabilitiesError),
+    Swapchain(SwapchainCreationError),
+    Acquire(AcquireError),
+    Flush(FlushError),
+    /// A command buffer of the frame could not be queued, like the culling pass.
+    Submit(CommandBufferExecError),
+    /// Failure to copy a rendered frame back or to write it to a file.
+    Capture(String),
+    /// A worker thread, like the ones of the asset loader, could not be spawned.
+    Thread(io::Error),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::NoVulkanLoader => write!(f, "the Vulkan library could not be loaded"),
+            EngineError::Instance(e) => write!(f, "failed to create the Vulkan instance: {}", e),
+            EngineError::NoSuitableGpu => write!(f, "no suitable GPU found"),
+            EngineError::MissingExtension(name) => write!(f, "{} is not supported", name),
+            EngineError::Device(e) => write!(f, "failed to create the logical device: {}", e),
+            EngineError::OutOfMemory(e) => write!(f, "out of memory: {}", e),
+            EngineError::ShaderLoad(name, e) => {
+                write!(f, "failed to load the {} shader: {}", name, e)
+            }
+            EngineError::Pipeline(e) => write!(f, "failed to create a pipeline: {}", e),
+            EngineError::Resource(e) => write!(f, "failed to create a resource: {}", e),
+            EngineError::Window(e) => write!(f, "failed to create the window: {}", e),
+            EngineError::SurfaceLost => write!(f, "the window surface was lost"),
+            EngineError::DeviceLost => write!(f, "the device was lost"),
+            EngineError::Surface(e) => write!(f, "failed to query the surface: {}", e),
+            EngineError::Swapchain(e) => write!(f, "failed to create the swapchain: {}", e),
+            EngineError::Acquire(e) => write!(f, "failed to acquire a swapchain image: {}", e),
+            EngineError::Flush(e) => write!(f, "failed to submit a frame: {}", e),
+            EngineError::Submit(e) => write!(f, "failed to submit a command buffer: {}", e),
+            EngineError::Capture(e) => write!(f, "failed to capture a frame: {}", e),
+            EngineError::Thread(e) => write!(f, "failed to spawn a thread: {}", e),
+        }
+    }
+}
+
+impl Error for EngineError {}
+
+impl From<InstanceCreationError> for EngineError {
+    fn from(e: InstanceCreationError) -> Self {
+        match e {
+            InstanceCreationError::LoadingError(_) => EngineError::NoVulkanLoader,
+            InstanceCreationError::OomError(e) => EngineError::OutOfMemory(e),
+            InstanceCreationError::LayerNotPresent => {
+                EngineError::MissingExtension("an instance layer".to_string())
+            }
+            InstanceCreationError::ExtensionNotPresent => {
+                EngineError::MissingExtension("an instance extension".to_string())
+            }
+            e => EngineError::Instance(e),
+        }
+    }
+}
+
+impl From<LayersListError> for EngineError {
+    fn from(e: LayersListError) -> Self {
+        match e {
+            LayersListError::LoadingError(_) => EngineError::NoVulkanLoader,
+            LayersListError::OomError(e) => EngineError::OutOfMemory(e),
+        }
+    }
+}
+
+impl From<SupportedExtensionsError> for EngineError {
+    fn from(e: SupportedExtensionsError) -> Self {
+        match e {
+            SupportedExtensionsError::LoadingError(_) => EngineError::NoVulkanLoader,
+            SupportedExtensionsError::OomError(e) => EngineError::OutOfMemory(e),
+        }
+    }
+}
+
+impl From<DeviceCreationError> for EngineError {
+    fn from(e: DeviceCreationError) -> Self {
+        match e {
+            DeviceCreationError::ExtensionNotPresent => {
+                EngineError::MissingExtension("a device extension".to_string())
+            }
+            DeviceCreationError::OutOfHostMemory => {
+                EngineError::OutOfMemory(OomError::OutOfHostMemory)
+            }
+            DeviceCreationError::OutOfDeviceMemory => {
+                EngineError::OutOfMemory(OomError::OutOfDeviceMemory)
+            }
+            DeviceCreationError::DeviceLost => EngineError::DeviceLost,
+            e => EngineError::Device(e),
+        }
+    }
+}
+
+impl From<OomError> for EngineError {
+    fn from(e: OomError) -> Self {
+        EngineError::OutOfMemory(e)
+    }
+}
+
+impl From<DeviceMemoryAllocError> for EngineError {
+    fn from(e: DeviceMemoryAllocError) -> Self {
+        match e {
+            DeviceMemoryAllocError::OomError(e) => EngineError::OutOfMemory(e),
+            e => EngineError::Resource(e.to_string()),
+        }
+    }
+}
+
+impl From<ImageCreationError> for EngineError {
+    fn from(e: ImageCreationError) -> Self {
+        match e {
+            ImageCreationError::AllocError(e) => e.into(),
+            e => EngineError::Resource(e.to_string()),
+        }
+    }
+}
+
+impl From<RenderPassCreationError> for EngineError {
+    fn from(e: RenderPassCreationError) -> Self {
+        match e {
+            RenderPassCreationError::OomError(e) => EngineError::OutOfMemory(e),
+            e => EngineError::Resource(e.to_string()),
+        }
+    }
+}
+
+impl From<SamplerCreationError> for EngineError {
+    fn from(e: SamplerCreationError) -> Self {
+        match e {
+            SamplerCreationError::OomError(e) => EngineError::OutOfMemory(e),
+            e => EngineError::Resource(e.to_string()),
+        }
+    }
+}
+
+impl From<GraphicsPipelineCreationError> for EngineError {
+    fn from(e: GraphicsPipelineCreationError) -> Self {
+        match e {
+            GraphicsPipelineCreationError::OomError(e) => EngineError::OutOfMemory(e),
+            e => EngineError::Pipeline(e),
+        }
+    }
+}
+
+impl From<ComputePipelineCreationError> for EngineError {
+    fn from(e: ComputePipelineCreationError) -> Self {
+        match e {
+            ComputePipelineCreationError::OomError(e) => EngineError::OutOfMemory(e),
+            e => EngineError::Resource(e.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for EngineError {
+    fn from(e: io::Error) -> Self {
+        EngineError::Resource(e.to_string())
+    }
+}
+
+impl From<vulkano_win::CreationError> for EngineError {
+    fn from(e: vulkano_win::CreationError) -> Self {
+        EngineError::Window(e)
+    }
+}
+
+impl From<CapabilitiesError> for EngineError {
+    fn from(e: CapabilitiesError) -> Self {
+        match e {
+            CapabilitiesError::SurfaceLost => EngineError::SurfaceLost,
+            e => EngineError::Surface(e),
+        }
+    }
+}
+
+impl From<SwapchainCreationError> for EngineError {
+    fn from(e: SwapchainCreationError) -> Self {
+        match e {
+            SwapchainCreationError::SurfaceLost => EngineError::SurfaceLost,
+            SwapchainCreationError::DeviceLost => EngineError::DeviceLost,
+            e => EngineError::Swapchain(e),
+        }
+    }
+}
+
+impl From<AcquireError> for EngineError {
+    fn from(e: AcquireError) -> Self {
+        match e {
+            AcquireError::SurfaceLost => EngineError::SurfaceLost,
+            AcquireError::DeviceLost => EngineError::DeviceLost,
+            e => EngineError::Acquire(e),
+        }
+    }
+}
+
+impl From<FlushError> for EngineError {
+    fn from(e: FlushError) -> Self {
+        match e {
+            FlushError::SurfaceLost => EngineError::SurfaceLost,
+            FlushError::DeviceLost => EngineError::DeviceLost,
+            e => EngineError::Flush(e),
+        }
+    }
+}
+
+impl From<CommandBufferExecError> for EngineError {
+    fn from(e: CommandBufferExecError) -> Self {
+        EngineError::Submit(e)
+    }
+}
@@ -0,0 +1,239 @@
+use crate::engine::config::GpuSelection;
+use std::fmt;
+use std::sync::Arc;
+use vulkano::device::DeviceExtensions;
+use vulkano::device::Features;
+use vulkano::instance::{Instance, PhysicalDevice, PhysicalDeviceType};
+
+#[derive(Debug, Clone)]
+pub struct QueueFamilyInfo {
+    pub id: u32,
+    pub queues_count: usize,
+    pub graphics: bool,
+    pub compute: bool,
+    pub transfer: bool,
+}
+
+/// Description of a physical device, as printed by `ressha --list-gpus`.
+#[derive(Debug, Clone)]
+pub struct GpuInfo {
+    pub index: usize,
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    pub api_version: String,
+    pub driver_version: u32,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub uuid: String,
+    pub device_local_memory: u64,
+    pub score: u64,
+    pub queue_families: Vec<QueueFamilyInfo>,
+    pub extensions: String,
+}
+
+impl GpuInfo {
+    pub fn new(device: &PhysicalDevice) -> Self {
+        let api_version = device.api_version();
+        GpuInfo {
+            index: device.index(),
+            name: device.name().to_string(),
+            device_type: device.ty(),
+            api_version: format!(
+                "{}.{}.{}",
+                api_version.major, api_version.minor, api_version.patch
+            ),
+            driver_version: device.driver_version(),
+            vendor_id: device.pci_vendor_id(),
+            device_id: device.pci_device_id(),
+            uuid: format_uuid(device.uuid()),
+            device_local_memory: device_local_memory(device),
+            score: score(device),
+            queue_families: device
+                .queue_families()
+                .map(|family| QueueFamilyInfo {
+                    id: family.id(),
+                    queues_count: family.queues_count(),
+                    graphics: family.supports_graphics(),
+                    compute: family.supports_compute(),
+                    transfer: family.explicitly_supports_transfers(),
+                })
+                .collect(),
+            extensions: format!("{:?}", DeviceExtensions::supported_by_device(*device)),
+        }
+    }
+}
+
+impl fmt::Display for GpuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[{}] {} ({:?})", self.index, self.name, self.device_type)?;
+        writeln!(f, "    uuid: {}", self.uuid)?;
+        writeln!(
+            f,
+            "    vendor: {:#06x}, device: {:#06x}, driver: {}, api: {}",
+            self.vendor_id, self.device_id, self.driver_version, self.api_version
+        )?;
+        writeln!(
+            f,
+            "    device local memory: {} MiB, score: {}",
+            self.device_local_memory / (1024 * 1024),
+            self.score
+        )?;
+        for family in &self.queue_families {
+            writeln!(
+                f,
+                "    queue family {}: {} queues, graphics: {}, compute: {}, transfer: {}",
+                family.id, family.queues_count, family.graphics, family.compute, family.transfer
+            )?;
+        }
+        write!(f, "    extensions: {}", self.extensions)
+    }
+}
+
+pub fn list_gpus(instance: &Arc<Instance>) -> Vec<GpuInfo> {
+    PhysicalDevice::enumerate(instance)
+        .map(|device| GpuInfo::new(&device))
+        .collect()
+}
+
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex: Vec<String> = uuid.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex[0..4].concat(),
+        hex[4..6].concat(),
+        hex[6..8].concat(),
+        hex[8..10].concat(),
+        hex[10..16].concat()
+    )
+}
+
+fn device_local_memory(device: &PhysicalDevice) -> u64 {
+    device
+        .memory_heaps()
+        .filter(|heap| heap.is_device_local())
+        .map(|heap| heap.size() as u64)
+        .sum()
+}
+
+/// Ranks devices for automatic selection: the device type comes first (discrete, integrated,
+/// virtual, others, software), the amount of device local memory breaks ties.
+pub fn score(device: &PhysicalDevice) -> u64 {
+    rank(device.ty(), device_local_memory(device))
+}
+
+fn rank(device_type: PhysicalDeviceType, device_local_memory: u64) -> u64 {
+    let type_rank: u64 = match device_type {
+        PhysicalDeviceType::DiscreteGpu => 4,
+        PhysicalDeviceType::IntegratedGpu => 3,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Other => 1,
+        PhysicalDeviceType::Cpu => 0,
+    };
+    let memory_mib = device_local_memory / (1024 * 1024);
+    (type_rank << 48) | memory_mib.min((1 << 48) - 1)
+}
+
+fn matches_selection(selection: &GpuSelection, index: usize, name: &str, uuid: &[u8; 16]) -> bool {
+    match selection {
+        GpuSelection::Auto => true,
+        GpuSelection::Index(selected) => index == *selected,
+        GpuSelection::Name(selected) => name.to_lowercase().contains(&selected.to_lowercase()),
+        GpuSelection::Uuid(selected) => format_uuid(uuid).eq_ignore_ascii_case(selected.trim()),
+    }
+}
+
+/// Returns the index of the device to render with.
+///
+/// Suitable devices support `required` and pass `is_suitable`. A forced device is used only if it
+/// is suitable, otherwise `None` is returned rather than silently rendering on another GPU.
+pub fn select<F>(
+    instance: &Arc<Instance>,
+    selection: &GpuSelection,
+    required: &Features,
+    is_suitable: F,
+) -> Option<usize>
+where
+    F: Fn(&PhysicalDevice) -> bool,
+{
+    let candidates = PhysicalDevice::enumerate(instance)
+        .filter(|device| matches_selection(selection, device.index(), device.name(), device.uuid()))
+        .filter(|device| {
+            let suitable = device.supported_features().superset_of(required) && is_suitable(device);
+            if !suitable {
+                log::info!("GPU [{}] {} is not suitable", device.index(), device.name());
+            }
+            suitable
+        });
+    let selected =
+        candidates.max_by_key(|device| (score(device), std::cmp::Reverse(device.index())));
+    if let Some(device) = &selected {
+        log::info!(
+            "Selected GPU [{}] {} ({:?})",
+            device.index(),
+            device.name(),
+            device.ty()
+        );
+    }
+    selected.map(|device| device.index())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: [u8; 16] = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66,
+        0x77,
+    ];
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    #[test]
+    fn auto_matches_every_device() {
+        assert!(matches_selection(&GpuSelection::Auto, 3, "llvmpipe", &UUID));
+    }
+
+    #[test]
+    fn index_matches_only_that_device() {
+        let selection = GpuSelection::Index(1);
+        assert!(matches_selection(&selection, 1, "Radeon", &UUID));
+        assert!(!matches_selection(&selection, 0, "Radeon", &UUID));
+    }
+
+    #[test]
+    fn name_matches_a_case_insensitive_substring() {
+        let selection = GpuSelection::Name("radeon".to_string());
+        assert!(matches_selection(&selection, 0, "AMD Radeon RX 580", &UUID));
+        assert!(!matches_selection(&selection, 0, "NVIDIA GeForce", &UUID));
+    }
+
+    #[test]
+    fn uuid_matches_the_printed_form() {
+        assert_eq!(format_uuid(&UUID), "01234567-89ab-cdef-0011-223344556677");
+        let selection = GpuSelection::Uuid(" 01234567-89AB-CDEF-0011-223344556677 ".to_string());
+        assert!(matches_selection(&selection, 0, "Radeon", &UUID));
+        let mut other = UUID;
+        other[15] = 0;
+        assert!(!matches_selection(&selection, 0, "Radeon", &other));
+    }
+
+    #[test]
+    fn device_type_ranks_before_memory() {
+        let discrete = rank(PhysicalDeviceType::DiscreteGpu, GIB);
+        let integrated = rank(PhysicalDeviceType::IntegratedGpu, 16 * GIB);
+        let cpu = rank(PhysicalDeviceType::Cpu, 64 * GIB);
+        assert!(discrete > integrated);
+        assert!(integrated > cpu);
+    }
+
+    #[test]
+    fn memory_breaks_ties_between_device_types() {
+        assert!(
+            rank(PhysicalDeviceType::DiscreteGpu, 8 * GIB)
+                > rank(PhysicalDeviceType::DiscreteGpu, 4 * GIB)
+        );
+        assert_eq!(
+            rank(PhysicalDeviceType::IntegratedGpu, GIB),
+            rank(PhysicalDeviceType::IntegratedGpu, GIB + 1024)
+        );
+    }
+}
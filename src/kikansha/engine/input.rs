@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::TrySendError;
+use std::time::Duration;
+use winit::event::{
+    DeviceEvent, ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta,
+    VirtualKeyCode, WindowEvent,
+};
+
+// Lines scrolled by a pixel delta of a touchpad.
+const PIXELS_PER_LINE: f32 = 20.0;
+
+/// Window and device events forwarded by `engine::run_loop` to the application.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEvent {
+    KeyPressed(VirtualKeyCode),
+    KeyReleased(VirtualKeyCode),
+    ModifiersChanged(ModifiersState),
+    /// Cursor position in physical pixels from the top left corner of the window.
+    CursorMoved([f32; 2]),
+    CursorLeft,
+    /// Raw mouse movement, not limited by the window borders.
+    MouseMotion([f32; 2]),
+    MouseButtonPressed(MouseButton),
+    MouseButtonReleased(MouseButton),
+    /// Scroll in lines, positive `y` when scrolling up.
+    Scroll([f32; 2]),
+    Focused(bool),
+    FileDropped(PathBuf),
+    /// New inner size of the window in physical pixels.
+    Resized([u32; 2]),
+}
+
+impl InputEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed => Some(InputEvent::KeyPressed(*key)),
+                ElementState::Released => Some(InputEvent::KeyReleased(*key)),
+            },
+            WindowEvent::ModifiersChanged(modifiers) => {
+                Some(InputEvent::ModifiersChanged(*modifiers))
+            }
+            WindowEvent::CursorMoved { position, .. } => Some(InputEvent::CursorMoved([
+                position.x as f32,
+                position.y as f32,
+            ])),
+            WindowEvent::CursorLeft { .. } => Some(InputEvent::CursorLeft),
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => Some(InputEvent::MouseButtonPressed(*button)),
+                ElementState::Released => Some(InputEvent::MouseButtonReleased(*button)),
+            },
+            WindowEvent::MouseWheel { delta, .. } => match delta {
+                MouseScrollDelta::LineDelta(x, y) => Some(InputEvent::Scroll([*x, *y])),
+                MouseScrollDelta::PixelDelta(position) => Some(InputEvent::Scroll([
+                    position.x as f32 / PIXELS_PER_LINE,
+                    position.y as f32 / PIXELS_PER_LINE,
+                ])),
+            },
+            WindowEvent::Focused(focused) => Some(InputEvent::Focused(*focused)),
+            WindowEvent::DroppedFile(path) => Some(InputEvent::FileDropped(path.clone())),
+            WindowEvent::Resized(size) => Some(InputEvent::Resized([size.width, size.height])),
+            _ => None,
+        }
+    }
+
+    pub fn from_device_event(event: &DeviceEvent) -> Option<Self> {
+        match event {
+            DeviceEvent::MouseMotion { delta } => {
+                Some(InputEvent::MouseMotion([delta.0 as f32, delta.1 as f32]))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Input accumulated since the previous frame, passed to `InputHandler::on_frame`.
+#[derive(Debug, Clone, Default)]
+pub struct InputState {
+    keys_down: HashSet<VirtualKeyCode>,
+    buttons_down: HashSet<MouseButton>,
+    modifiers: ModifiersState,
+    cursor: Option<[f32; 2]>,
+    mouse_delta: [f32; 2],
+    scroll: [f32; 2],
+    focused: bool,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        InputState {
+            focused: true,
+            ..InputState::default()
+        }
+    }
+
+    pub fn apply(&mut self, event: &InputEvent) {
+        match event {
+            InputEvent::KeyPressed(key) => {
+                self.keys_down.insert(*key);
+            }
+            InputEvent::KeyReleased(key) => {
+                self.keys_down.remove(key);
+            }
+            InputEvent::ModifiersChanged(modifiers) => self.modifiers = *modifiers,
+            InputEvent::CursorMoved(position) => self.cursor = Some(*position),
+            InputEvent::CursorLeft => self.cursor = None,
+            InputEvent::MouseMotion(delta) => {
+                self.mouse_delta[0] += delta[0];
+                self.mouse_delta[1] += delta[1];
+            }
+            InputEvent::MouseButtonPressed(button) => {
+                self.buttons_down.insert(*button);
+            }
+            InputEvent::MouseButtonReleased(button) => {
+                self.buttons_down.remove(button);
+            }
+            InputEvent::Scroll(delta) => {
+                self.scroll[0] += delta[0];
+                self.scroll[1] += delta[1];
+            }
+            InputEvent::Focused(focused) => {
+                self.focused = *focused;
+                // Release events are not delivered to an unfocused window.
+                if !focused {
+                    self.keys_down.clear();
+                    self.buttons_down.clear();
+                }
+            }
+            InputEvent::FileDropped(_) | InputEvent::Resized(_) => (),
+        }
+    }
+
+    /// Resets the per frame deltas, called after `InputHandler::on_frame`.
+    pub fn end_frame(&mut self) {
+        self.mouse_delta = [0.0, 0.0];
+        self.scroll = [0.0, 0.0];
+    }
+
+    pub fn is_key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn keys_down(&self) -> impl Iterator<Item = &VirtualKeyCode> {
+        self.keys_down.iter()
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    /// Cursor position, `None` while the cursor is outside of the window.
+    pub fn cursor(&self) -> Option<[f32; 2]> {
+        self.cursor
+    }
+
+    /// Raw mouse movement since the previous frame.
+    pub fn mouse_delta(&self) -> [f32; 2] {
+        self.mouse_delta
+    }
+
+    /// Lines scrolled since the previous frame.
+    pub fn scroll(&self) -> [f32; 2] {
+        self.scroll
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+}
+
+/// Receives the input of the window rendered by `engine::run_loop`.
+///
+/// Both methods are called on the render loop thread, long running work should be moved
+/// elsewhere.
+pub trait InputHandler {
+    fn on_event(&mut self, _event: &InputEvent) {}
+
+    /// Called once per frame before it is rendered, `elapsed` is the time since the previous
+    /// call.
+    fn on_frame(&mut self, _input: &InputState, _elapsed: Duration) {}
+}
+
+/// Ignores the input.
+impl InputHandler for () {}
+
+/// Forwards the events to another thread, events are dropped while the channel is full.
+impl InputHandler for SyncSender<InputEvent> {
+    fn on_event(&mut self, event: &InputEvent) {
+        match self.try_send(event.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => (),
+            Err(TrySendError::Disconnected(_)) => log::trace!("Input receiver disconnected"),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use crate::assets::AssetResolver;
+use crate::debug::tracing::timed;
+use crate::figure::lod::MeshLod;
+use crate::figure::PerVerexParams;
+use crate::figure::RenderableMesh;
+use crate::scene::FigureHandle;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+pub enum AssetRequest {
+    Texture {
+        path: String,
+    },
+    Mesh {
+        handle: FigureHandle,
+        revision: u32,
+        mesh: RenderableMesh,
+        lods: Vec<MeshLod>,
+    },
+}
+
+pub enum LoadedAsset {
+    Texture {
+        path: String,
+        texture: io::Result<DecodedTexture>,
+    },
+    Mesh {
+        handle: FigureHandle,
+        revision: u32,
+        mesh: PreparedMesh,
+        lods: Vec<PreparedLod>,
+    },
+}
+
+/// RGBA8 pixels of a decoded image.
+pub struct DecodedTexture {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+    // Hash of the encoded file, used to share textures loaded from different paths.
+    pub content_hash: u64,
+}
+
+/// Mesh converted to the vertex layout used by the geometry pass.
+pub enum PreparedMesh {
+    Indexed {
+        vertices: Vec<PerVerexParams>,
+        indices: Vec<u32>,
+    },
+    Regular {
+        vertices: Vec<PerVerexParams>,
+    },
+}
+
+/// Level of detail converted like `PreparedMesh::Indexed`.
+pub struct PreparedLod {
+    pub vertices: Vec<PerVerexParams>,
+    pub indices: Vec<u32>,
+    pub screen_size: f32,
+}
+
+/// Pool of worker threads decoding textures and preparing meshes off the render loop.
+///
+/// Requests are shared by all workers, results are collected with `poll` which never blocks.
+/// Texture paths are resolved with the `AssetResolver` given on creation.
+pub struct AssetLoader {
+    request_send: Option<Sender<AssetRequest>>,
+    result_recv: Receiver<LoadedAsset>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl AssetLoader {
+    pub fn new(workers_count: usize, assets: AssetResolver) -> io::Result<Self> {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        let (request_send, request_recv) = channel::<AssetRequest>();
+        let (result_send, result_recv) = channel();
+        let request_recv = Arc::new(Mutex::new(request_recv));
+
+        let workers = (0..workers_count.max(1))
+            .map(|i| {
+                let request_recv = request_recv.clone();
+                let result_send = result_send.clone();
+                let assets = assets.clone();
+                std::thread::Builder::new()
+                    .name(format!("kikansha-loader-{}", i))
+                    .spawn(move || loop {
+                        let request = {
+                            let locked_recv = request_recv.lock().unwrap();
+                            locked_recv.recv()
+                        };
+                        match request {
+                            Ok(request) => {
+                                if result_send.send(Self::load(&assets, request)).is_err() {
+                                    break;
+                                }
+                            }
+                            Err(_) => break,
+                        }
+                    })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(AssetLoader {
+            request_send: Some(request_send),
+            result_recv,
+            workers,
+        })
+    }
+
+    pub fn request(&self, request: AssetRequest) {
+        if let Some(request_send) = &self.request_send {
+            if request_send.send(request).is_err() {
+                log::error!("Asset loader workers are gone");
+            }
+        }
+    }
+
+    /// Returns every asset loaded since the previous call.
+    pub fn poll(&self) -> Vec<LoadedAsset> {
+        self.result_recv.try_iter().collect()
+    }
+
+    fn load(assets: &AssetResolver, request: AssetRequest) -> LoadedAsset {
+        match request {
+            AssetRequest::Texture { path } => {
+                let texture = timed("decode texture", || {
+                    assets
+                        .resolve_or_err(&path)
+                        .and_then(|resolved| fs::read(resolved))
+                        .and_then(|bytes| decode_png(&bytes))
+                });
+                LoadedAsset::Texture { path, texture }
+            }
+            AssetRequest::Mesh {
+                handle,
+                revision,
+                mesh,
+                lods,
+            } => LoadedAsset::Mesh {
+                handle,
+                revision,
+                mesh: timed("prepare mesh", || prepare_mesh(mesh)),
+                lods: lods.into_iter().map(prepare_lod).collect(),
+            },
+        }
+    }
+}
+
+impl Drop for AssetLoader {
+    fn drop(&mut self) {
+        // Closing the request channel stops the workers once they finish the current request.
+        self.request_send = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+pub fn decode_png(bytes: &[u8]) -> io::Result<DecodedTexture> {
+    let decoder = png::Decoder::new(bytes);
+    let (info, mut reader) = decoder
+        .read_info()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut data = Vec::new();
+    data.resize((info.width * info.height * 4) as usize, 0);
+    reader
+        .next_frame(&mut data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    Ok(DecodedTexture {
+        width: info.width,
+        height: info.height,
+        data,
+        content_hash: hasher.finish(),
+    })
+}
+
+pub fn prepare_mesh(mesh: RenderableMesh) -> PreparedMesh {
+    match mesh {
+        RenderableMesh::Indexed(ind) => PreparedMesh::Indexed {
+            vertices: ind.points.iter().map(|p| p.to_vert()).collect(),
+            indices: ind.indices,
+        },
+        RenderableMesh::Regular(reg) => PreparedMesh::Regular {
+            vertices: reg.points.iter().map(|p| p.to_vert()).collect(),
+        },
+    }
+}
+
+pub fn prepare_lod(lod: MeshLod) -> PreparedLod {
+    PreparedLod {
+        vertices: lod.mesh.points.iter().map(|p| p.to_vert()).collect(),
+        indices: lod.mesh.indices,
+        screen_size: lod.screen_size,
+    }
+}
@@ -1,535 +1,139 @@
 pub mod cache;
+pub mod config;
+pub mod error;
+pub mod gpu;
+pub mod input;
+pub mod loader;
 mod queue;
+pub mod renderer;
+pub mod texture;
 
+use crate::assets::AssetResolver;
 use crate::debug::fps::Counter;
-use crate::engine::cache::SceneCache;
-use crate::engine::queue::QueueFamilyIndices;
-use crate::frame::frame::Pass;
-use crate::frame::geometry::TriangleDrawSystem;
-use crate::frame::system::FrameSystem;
+use crate::debug::tracing;
+use crate::engine::config::RendererConfig;
+use crate::engine::error::EngineError;
+use crate::engine::input::InputEvent;
+use crate::engine::input::InputHandler;
+use crate::engine::input::InputState;
+use crate::engine::renderer::Renderer;
+use crate::engine::renderer::SharedWindow;
 use crate::scene::camera::ViewAndProject;
+use crate::scene::path::CameraPath;
+use crate::scene::path::PathCamera;
 use crate::scene::Scene;
-use std::collections::HashSet;
+use std::path::Path;
 use std::sync::mpsc::Receiver;
-use std::sync::mpsc::SyncSender;
 use std::sync::Arc;
-use std::sync::Mutex;
-use vulkano::command_buffer::DynamicState;
-use vulkano::device::{Device, DeviceExtensions, Features, Queue};
-use vulkano::format::Format;
-use vulkano::image::{ImageUsage, SwapchainImage};
-use vulkano::instance::{
-    debug::DebugCallback, debug::MessageSeverity, debug::MessageType, layers_list, ApplicationInfo,
-    Instance, InstanceExtensions, PhysicalDevice, Version,
-};
-use vulkano::pipeline::viewport::Viewport;
-use vulkano::swapchain;
-use vulkano::swapchain::AcquireError;
-use vulkano::swapchain::{
-    Capabilities, ColorSpace, FullscreenExclusive, PresentMode, SupportedPresentModes, Surface,
-    Swapchain,
-};
-use vulkano::sync;
-use vulkano::sync::{FlushError, GpuFuture, SharingMode};
-use vulkano_win::VkSurfaceBuild;
+use std::time::{Duration, Instant};
+use vulkano::instance::Instance;
+use vulkano::swapchain::Surface;
+use vulkano::swapchain::SurfaceCreationError;
 use winit::dpi::LogicalSize;
-use winit::event::{Event, WindowEvent};
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::ControlFlow;
 use winit::event_loop::EventLoop;
+use winit::event_loop::EventLoopWindowTarget;
 use winit::platform::run_return::EventLoopExtRunReturn;
-use winit::window::Window;
+use winit::window::Fullscreen;
 use winit::window::WindowBuilder;
 
-const WIDTH: u32 = 1280;
-const HEIGHT: u32 = 720;
+// Consecutive device losses recovered from before the render loop gives up.
+const MAX_DEVICE_RECOVERIES: u32 = 3;
+// How often a paused render loop checks for the quit signal.
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
-/// Required device extensions
-fn device_extensions(validation_layer: bool) -> DeviceExtensions {
-    DeviceExtensions {
-        khr_swapchain: true,
-        khr_storage_buffer_storage_class: true,
-        ext_debug_utils: validation_layer,
-        ..DeviceExtensions::none()
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoopState {
+    Rendering,
+    /// The window is minimized, nothing is drawn until it has an area again.
+    Paused,
 }
 
-const VALIDATION_LAYERS: &[&str] = &[
-    // "VK_LAYER_RENDERDOC_Capture",
-    // "VK_LAYER_NV_optimus",
-    // "VK_LAYER_LUNARG_monitor",
-    // "VK_LAYER_LUNARG_screenshot",
-    // "VK_LAYER_LUNARG_device_simulation",
-    // "VK_LAYER_LUNARG_api_dump",
-    "VK_LAYER_KHRONOS_validation",
-];
-
-pub struct State {
-    instance: Arc<Instance>,
-    #[allow(dead_code)]
-    debug_callback: Option<DebugCallback>,
-    physical_device_index: usize,
-    pub device: Arc<Device>,
-    graphics_queue: Arc<Queue>,
-    present_queue: Arc<Queue>,
-    pub surface: Arc<Surface<Window>>,
-    pub swap_chain: Arc<Swapchain<Window>>,
-    pub swap_chain_images: Vec<Arc<SwapchainImage<Window>>>,
-    pub dynamic_state: Mutex<DynamicState>,
-    previous_frame_end: Option<Box<dyn GpuFuture>>,
-    recreate_swap_chain: bool,
-    scene_cache: SceneCache,
-    pub frame_system: FrameSystem,
-    pub triangle_draw_system: TriangleDrawSystem,
+/// Creates a window with the size, title and fullscreen mode of `config` and its surface.
+///
+/// `instance` should come from `Renderer::create_instance`.
+pub fn create_window(
+    target: &EventLoopWindowTarget<()>,
+    instance: &Arc<Instance>,
+    config: &RendererConfig,
+) -> Result<Arc<Surface<SharedWindow>>, vulkano_win::CreationError> {
+    let [width, height] = config.window_size;
+    let fullscreen = if config.fullscreen {
+        Some(Fullscreen::Borderless(None))
+    } else {
+        None
+    };
+    let window = WindowBuilder::new()
+        .with_title(config.title.clone())
+        .with_inner_size(LogicalSize::new(f64::from(width), f64::from(height)))
+        .with_fullscreen(fullscreen)
+        .build(target)
+        .map_err(vulkano_win::CreationError::WindowCreationError)?;
+    vulkano_win::create_vk_surface(Arc::new(window), instance.clone())
+        .map_err(vulkano_win::CreationError::SurfaceCreationError)
 }
 
-impl State {
-    fn init(
-        surface: Arc<Surface<Window>>,
-        instance: Arc<Instance>,
-        validation_layer: bool,
-        color_debug_level: i32,
-    ) -> Self {
-        let debug_callback = Self::setup_debug_callback(&instance, validation_layer);
-
-        let physical_device_index =
-            Self::pick_physical_device(&instance, &surface, validation_layer);
-        let (device, graphics_queue, present_queue) = Self::create_logical_device(
-            physical_device_index,
-            &instance,
-            &surface,
-            validation_layer,
-        );
-        let dynamic_state_raw = DynamicState::none();
-
-        let dynamic_state = Mutex::new(dynamic_state_raw);
-
-        let (swap_chain, swap_chain_images) = Self::create_swap_chain(
-            &instance,
-            &surface,
-            physical_device_index,
-            &device,
-            &graphics_queue,
-            &present_queue,
-            None,
-            &dynamic_state,
-        );
-
-        let dimensions = swap_chain_images[0].dimensions();
-        let frame_system = FrameSystem::new(
-            graphics_queue.clone(),
-            swap_chain.format(),
-            dimensions,
-            color_debug_level,
-        );
-
-        let previous_frame_end = Some(sync::now(device.clone()).boxed());
-
-        let triangle_draw_system = TriangleDrawSystem::new(
-            graphics_queue.clone(),
-            frame_system.deferred_subpass(),
-            swap_chain.format(),
-        );
-
-        State {
-            instance,
-            debug_callback,
-            physical_device_index,
-            device,
-            graphics_queue,
-            present_queue,
-            surface,
-            swap_chain,
-            swap_chain_images,
-            dynamic_state,
-            previous_frame_end,
-            recreate_swap_chain: false,
-            scene_cache: SceneCache::default(),
-            frame_system,
-            triangle_draw_system,
-        }
-    }
-
-    fn init_loop(instance: &Arc<Instance>) -> (EventLoop<()>, Arc<Surface<Window>>) {
-        let events_loop = EventLoop::new();
-        let surface = WindowBuilder::new()
-            .with_title("Vulkan")
-            .with_inner_size(LogicalSize::new(f64::from(WIDTH), f64::from(HEIGHT)))
-            .build_vk_surface(&events_loop, instance.clone())
-            .expect("Failed to create window surface");
-        (events_loop, surface)
-    }
-
-    fn create_instance(validation_layer: bool) -> Arc<Instance> {
-        if validation_layer && !Self::check_validation_layer_support() {
-            log::error!("Validation layers requested, but not available!")
-        }
-        let supported_extensions = InstanceExtensions::supported_by_core()
-            .expect("failed to retrieve supported extensions");
-        let layers: Vec<_> = layers_list()
-            .unwrap()
-            .map(|l| l.name().to_owned())
-            .collect();
-
-        log::info!("Supported core extensions: {:?}", supported_extensions);
-        log::info!("Supported extensions: {:?}", layers);
-        let app_info = ApplicationInfo {
-            application_name: Some("Kikansha".into()),
-            application_version: Some(Version {
-                major: 1,
-                minor: 0,
-                patch: 0,
-            }),
-            engine_name: Some("No Engine".into()),
-            engine_version: Some(Version {
-                major: 1,
-                minor: 0,
-                patch: 0,
-            }),
-        };
-        let required_extensions = Self::get_required_extensions(validation_layer);
-        if validation_layer && Self::check_validation_layer_support() {
-            Instance::new(
-                Some(&app_info),
-                &required_extensions,
-                VALIDATION_LAYERS.iter().cloned(),
-            )
-            .expect("failed to create Vulkan instance")
-        } else {
-            Instance::new(Some(&app_info), &required_extensions, None)
-                .expect("failed to create Vulkan instance")
-        }
-    }
-
-    fn pick_physical_device(
-        instance: &Arc<Instance>,
-        surface: &Arc<Surface<Window>>,
-        validation_layer: bool,
-    ) -> usize {
-        PhysicalDevice::enumerate(&instance)
-            .position(|device| Self::is_device_suitable(&device, surface, validation_layer))
-            .expect("failed to find a suitable GPU!")
-    }
-
-    fn create_logical_device(
-        physical_device_idx: usize,
-        instance: &Arc<Instance>,
-        surface: &Arc<Surface<Window>>,
-        validation_layer: bool,
-    ) -> (Arc<Device>, Arc<Queue>, Arc<Queue>) {
-        let physical_device = PhysicalDevice::from_index(instance, physical_device_idx).unwrap();
-        let indices = Self::find_queue_families(surface, &physical_device);
-        log::trace!("1");
-        let families = [indices.graphics_family, indices.present_family];
-
-        let uniquer_queue_family: HashSet<&i32> = families.iter().collect();
-        let queue_priority = 1.0;
-
-        let queue_families = uniquer_queue_family.iter().map(|i| {
-            (
-                physical_device.queue_families().nth(**i as usize).unwrap(),
-                queue_priority,
-            )
-        });
-
-        let (device, mut queues) = Device::new(
-            physical_device,
-            &Features::none(),
-            &device_extensions(validation_layer),
-            queue_families,
-        )
-        .expect("Failed to create logical device");
-        let graphics_queue = queues.next().unwrap();
-        let present_queue = queues.next().unwrap_or_else(|| graphics_queue.clone());
-        (device, graphics_queue, present_queue)
-    }
-
-    fn check_device_support_extension(device: &PhysicalDevice, validation_layer: bool) -> bool {
-        let available_extensions = DeviceExtensions::supported_by_device(*device);
-        let device_extensions = device_extensions(validation_layer);
-        log::trace!(
-            "available_extensions: {:?}, \ndevice_extensions: {:?}",
-            available_extensions,
-            device_extensions,
-        );
-        available_extensions.intersection(&device_extensions) == device_extensions
-    }
+/// Creates a new surface for the window of a lost `surface`, see `Renderer::replace_surface`.
+pub fn recreate_surface(
+    instance: &Arc<Instance>,
+    surface: &Arc<Surface<SharedWindow>>,
+) -> Result<Arc<Surface<SharedWindow>>, SurfaceCreationError> {
+    vulkano_win::create_vk_surface(surface.window().clone(), instance.clone())
+}
 
-    fn is_device_suitable(
-        device: &PhysicalDevice,
-        surface: &Arc<Surface<Window>>,
-        validation_layer: bool,
-    ) -> bool {
-        let indices = Self::find_queue_families(surface, device);
-        let extension_supported = Self::check_device_support_extension(device, validation_layer);
-        let swap_chain_adequate = if extension_supported {
-            let capabilities = surface
-                .capabilities(*device)
-                .expect("Failes to get surface capabilities");
-            !capabilities.supported_formats.is_empty()
-                && capabilities.present_modes.iter().next().is_some()
-        } else {
-            false
-        };
-        log::trace!(
-            "3 {}, {}, {},",
-            indices.is_complete(),
-            extension_supported,
-            swap_chain_adequate
-        );
-        indices.is_complete() && extension_supported && swap_chain_adequate
+/// Renders `scene` in a window of its own until it is closed or `true` is received on
+/// `quit_recv`.
+///
+/// Window input is passed to `input_handler`, with a snapshot of it before every frame.
+/// Rendering pauses while the window is minimized. Lost surfaces and devices are recreated,
+/// an error is returned only when that fails or for errors nothing can be done about.
+/// Applications with an event loop of their own use a `Renderer` directly.
+pub fn run_loop<T: ViewAndProject + Sized, H: InputHandler>(
+    scene: &Scene<T>,
+    input_handler: &mut H,
+    quit_recv: Receiver<bool>,
+    config: RendererConfig,
+    assets: AssetResolver,
+) -> Result<(), EngineError> {
+    if config.trace_file.is_some() {
+        tracing::enable_trace(config.trace_capacity);
     }
-
-    fn find_queue_families(
-        surface: &Arc<Surface<Window>>,
-        device: &PhysicalDevice,
-    ) -> QueueFamilyIndices {
-        let mut indices = QueueFamilyIndices::new();
-        // TODO: replace index with id to simplify?
-        for (i, queue_family) in device.queue_families().enumerate() {
-            if queue_family.supports_graphics() {
-                indices.graphics_family = i as i32;
-            }
-
-            if surface.is_supported(queue_family).unwrap() {
-                indices.present_family = i as i32;
-            }
-
-            if indices.is_complete() {
-                break;
+    let instance = Renderer::create_instance(config.validation)?;
+    let mut event_loop = EventLoop::new();
+    let surface = create_window(&event_loop, &instance, &config)?;
+    let trace_file = config.trace_file.clone();
+    let mut renderer = Some(Renderer::new(surface, config, assets)?);
+    let mut loop_state = LoopState::Rendering;
+    let mut device_losses = 0;
+    let mut result = Ok(());
+
+    let mut counter = Counter::new(10);
+    let mut input = InputState::new();
+    let mut last_frame = Instant::now();
+
+    event_loop.run_return(|event, _, control_flow| {
+        // The renderer is gone only after a failed device recovery.
+        let current = match renderer.as_mut() {
+            Some(current) => current,
+            None => {
+                *control_flow = ControlFlow::Exit;
+                return;
             }
-        }
-
-        indices
-    }
-
-    fn create_swap_chain(
-        instance: &Arc<Instance>,
-        surface: &Arc<Surface<Window>>,
-        physical_device_index: usize,
-        device: &Arc<Device>,
-        graphics_queue: &Arc<Queue>,
-        present_queue: &Arc<Queue>,
-        old_swapchain: Option<Arc<Swapchain<Window>>>,
-        dynamic_state: &Mutex<DynamicState>,
-    ) -> (Arc<Swapchain<Window>>, Vec<Arc<SwapchainImage<Window>>>) {
-        let physical_device = PhysicalDevice::from_index(&instance, physical_device_index).unwrap();
-        let capabilities = surface
-            .capabilities(physical_device)
-            .expect("Failed to get surface capabilities");
-
-        let surface_format = Self::choose_swap_surface_format(&capabilities.supported_formats);
-        let present_mode = Self::choose_swap_present_mode(capabilities.present_modes);
-        let extent = Self::choose_swap_extent(&capabilities);
-
-        let mut image_count = capabilities.min_image_count + 1;
-        if capabilities.max_image_count.is_some()
-            && image_count > capabilities.max_image_count.unwrap()
-        {
-            image_count = capabilities.max_image_count.unwrap();
-        }
-        log::trace!("image_count {}", image_count);
-
-        let alpha = capabilities
-            .supported_composite_alpha
-            .iter()
-            .next()
-            .unwrap();
-
-        let image_usage = ImageUsage::color_attachment();
-
-        let indicies = Self::find_queue_families(&surface, &physical_device);
-
-        let sharing: SharingMode = if indicies.graphics_family != indicies.present_family {
-            vec![graphics_queue, present_queue].as_slice().into()
-        } else {
-            graphics_queue.into()
-        };
-
-        log::trace!("1");
-
-        let (swap_chain, images) = match old_swapchain {
-            Some(old) => Swapchain::with_old_swapchain(
-                device.clone(),
-                surface.clone(),
-                image_count,
-                surface_format.0,
-                extent,
-                1,
-                image_usage,
-                sharing,
-                capabilities.current_transform,
-                alpha,
-                present_mode,
-                FullscreenExclusive::Default,
-                true,
-                ColorSpace::SrgbNonLinear,
-                old,
-            )
-            .expect("Failed to create swap chain"),
-            None => Swapchain::new(
-                device.clone(),
-                surface.clone(),
-                image_count,
-                surface_format.0,
-                extent,
-                1,
-                image_usage,
-                sharing,
-                capabilities.current_transform,
-                alpha,
-                present_mode,
-                FullscreenExclusive::Default,
-                true,
-                ColorSpace::SrgbNonLinear,
-            )
-            .expect("Failed to create swap chain"),
-        };
-
-        let swap_chain_extent = swap_chain.dimensions();
-        let dimensions = [swap_chain_extent[0] as f32, swap_chain_extent[1] as f32];
-        let viewport = Viewport {
-            origin: [0.0, 0.0],
-            dimensions,
-            depth_range: 0.0..1.0,
         };
-        {
-            dynamic_state.lock().unwrap().viewports = Some(vec![viewport]);
+        let input_event = match &event {
+            Event::WindowEvent { event, .. } => InputEvent::from_window_event(event),
+            // Raw mouse motion is reported even when another window has the focus.
+            Event::DeviceEvent { event, .. } if input.is_focused() => {
+                InputEvent::from_device_event(event)
+            }
+            _ => None,
         };
-
-        log::trace!("2");
-        (swap_chain, images)
-    }
-
-    fn choose_swap_surface_format(
-        available_formats: &[(Format, ColorSpace)],
-    ) -> (Format, ColorSpace) {
-        // NOTE: the 'preferred format' mentioned in the tutorial doesn't seem to be
-        // queryable in Vulkano (no VK_FORMAT_UNDEFINED enum)
-        *available_formats
-            .iter()
-            .find(|(format, color_space)| {
-                *format == Format::B8G8R8A8Unorm && *color_space == ColorSpace::SrgbNonLinear
-                // false
-            })
-            .unwrap_or_else(|| &available_formats[0])
-    }
-
-    fn choose_swap_present_mode(available_present_modes: SupportedPresentModes) -> PresentMode {
-        if available_present_modes.mailbox {
-            PresentMode::Mailbox
-        } else if available_present_modes.immediate {
-            PresentMode::Immediate
-        } else {
-            PresentMode::Fifo
+        if let Some(input_event) = input_event {
+            input.apply(&input_event);
+            input_handler.on_event(&input_event);
         }
-    }
-
-    fn choose_swap_extent(capabilities: &Capabilities) -> [u32; 2] {
-        if let Some(current_extent) = capabilities.current_extent {
-            current_extent
-        } else {
-            let mut actual_extent = [WIDTH, HEIGHT];
-            actual_extent[0] = capabilities.min_image_extent[0]
-                .max(capabilities.max_image_extent[0].min(actual_extent[0]));
-            actual_extent[1] = capabilities.min_image_extent[1]
-                .max(capabilities.max_image_extent[1].min(actual_extent[1]));
-            actual_extent
-        }
-    }
-
-    fn check_validation_layer_support() -> bool {
-        let layers: Vec<_> = layers_list()
-            .unwrap()
-            .map(|l| l.name().to_owned())
-            .collect();
-        log::trace!(
-            "supported layers: {:?}, \n reuired layers: {:?}",
-            layers,
-            VALIDATION_LAYERS
-        );
-        VALIDATION_LAYERS
-            .iter()
-            .all(|layer_name| layers.contains(&layer_name.to_string()))
-    }
-
-    fn get_required_extensions(validation_layer: bool) -> InstanceExtensions {
-        let mut extensions = vulkano_win::required_extensions();
-
-        // extensions.khr_swapchain = true;
-        // extensions.khr_storage_buffer_storage_class = true;
-        // extensions.ext_debug_utils = true;
-
-        extensions.ext_debug_utils = validation_layer;
-        // extensions.khr_wayland_surface = false;
-        // extensions.khr_android_surface = false;
-        // extensions.khr_win32_surface = false;
-        // extensions.mvk_ios_surface = false;
-        // extensions.mvk_macos_surface = false;
-        // extensions.nn_vi_surface = false;
-
-        extensions
-    }
-    fn setup_debug_callback(
-        instance: &Arc<Instance>,
-        validation_layer: bool,
-    ) -> Option<DebugCallback> {
-        if !validation_layer {
-            return None;
-        }
-        // let msg_types = MessageType::all();
-
-        // let msg_severity = MessageSeverity {
-        //     error: true,
-        //     warning: true,
-        //     information: true,
-        //     verbose: true,
-        // };
-        // DebugCallback::new(&instance, msg_severity, msg_types, |msg| {
-        //     log::info!("Debug Layer: {:?}", msg.description,);
-        // })
-        // .ok();
-        None
-    }
-    fn recreate_swap_chain(&mut self, color_debug_level: i32) {
-        let (swap_chain, images) = Self::create_swap_chain(
-            &self.instance,
-            &self.surface,
-            self.physical_device_index,
-            &self.device,
-            &self.graphics_queue,
-            &self.present_queue,
-            Some(self.swap_chain.clone()),
-            &self.dynamic_state,
-        );
-
-        let dimensions = images[0].dimensions();
-
-        self.frame_system
-            .recreate_render_pass(swap_chain.format(), dimensions, color_debug_level);
-        self.swap_chain = swap_chain;
-        self.swap_chain_images = images;
-    }
-
-    pub fn run_loop<T: ViewAndProject + Sized>(
-        scene: &Scene<T>,
-        _event_send: SyncSender<f32>,
-        quit_recv: Receiver<bool>,
-        validation_layer: bool,
-        color_debug_level: i32,
-    ) {
-        let instance_unb = Self::create_instance(validation_layer);
-        let (mut event_loop, surface) = Self::init_loop(&instance_unb);
-        let mut state = Self::init(surface, instance_unb, validation_layer, color_debug_level);
-
-        let mut counter = Counter::new(10);
-
-        {
-            let dimensions = state.swap_chain_images[0].dimensions();
-            let mut locked_camera = scene.camera.lock().unwrap();
-            locked_camera.update_ar(dimensions[0] as f32 / dimensions[1] as f32);
-        }
-
-        event_loop.run_return(|event, _, control_flow| match event {
+        match event {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
@@ -540,120 +144,170 @@ impl State {
                 event: WindowEvent::Resized(_),
                 ..
             } => {
-                state.recreate_swap_chain = true;
+                current.resize();
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F12),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                if let Some(path) = &trace_file {
+                    if let Err(e) = tracing::dump_chrome_trace(path) {
+                        log::error!("Failed to write trace {}: {}", path, e);
+                    }
+                }
             }
             Event::RedrawEventsCleared => {
                 if let Ok(flag) = quit_recv.try_recv() {
                     if flag {
-                        *control_flow = ControlFlow::Exit
+                        *control_flow = ControlFlow::Exit;
+                        return;
                     }
                 }
 
-                state
-                    .previous_frame_end
-                    .as_mut()
-                    .unwrap()
-                    .cleanup_finished();
-
-                if state.recreate_swap_chain {
-                    log::trace!("recreate_swap_chain");
-                    {
-                        let mut locked_camera = scene.camera.lock().unwrap();
-                        let dimensions: [u32; 2] = state.surface.window().inner_size().into();
-                        locked_camera.update_ar(dimensions[0] as f32 / dimensions[1] as f32);
+                // Wait until the window is restored while still polling `quit_recv`.
+                if current.is_minimized() {
+                    if loop_state == LoopState::Rendering {
+                        log::info!("Window minimized, rendering paused");
+                        loop_state = LoopState::Paused;
                     }
-                    state.recreate_swap_chain(color_debug_level);
-                    state.recreate_swap_chain = false;
+                    *control_flow = ControlFlow::WaitUntil(Instant::now() + PAUSED_POLL_INTERVAL);
+                    return;
                 }
-
-                let (image_num, suboptimal, acquire_future) =
-                    match swapchain::acquire_next_image(state.swap_chain.clone(), None) {
-                        Ok(r) => r,
-                        Err(AcquireError::OutOfDate) => {
-                            state.recreate_swap_chain = true;
-                            return;
-                        }
-                        Err(e) => panic!("Failed to acquire next image: {:?}", e),
-                    };
-                if suboptimal {
-                    state.recreate_swap_chain = true;
+                if loop_state == LoopState::Paused {
+                    log::info!("Window restored, rendering resumed");
+                    loop_state = LoopState::Rendering;
+                    current.resize();
+                    *control_flow = ControlFlow::Poll;
+                    last_frame = Instant::now();
                 }
 
-                let future = state
-                    .previous_frame_end
-                    .take()
-                    .unwrap()
-                    .join(acquire_future);
-
-                let mut after_future = None;
-
-                let matrices = {
-                    let locked_camera = scene.camera.lock().unwrap();
-                    locked_camera.get_matrices()
-                };
-
-                let dynamic_state = { state.dynamic_state.lock().unwrap().clone() };
-                let cached_scene = state.scene_cache.get_cache(
-                    scene,
-                    state.device.clone(),
-                    state.graphics_queue.clone(),
-                );
-
-                let mut frame = state.frame_system.frame(
-                    future,
-                    state.swap_chain_images[image_num].clone(),
-                    scene.lights.clone(),
-                    matrices,
-                    cached_scene.clone(),
-                    dynamic_state.clone(),
-                );
-
-                while let Some(pass) = frame.next_pass() {
-                    match pass {
-                        Pass::Deferred(mut draw_pass) => {
-                            let cb = state.triangle_draw_system.draw(
-                                &matrices,
-                                &cached_scene,
-                                &dynamic_state,
-                            );
-                            draw_pass.execute(cb);
-                        }
-                        Pass::Lighting(mut lighting) => {
-                            lighting.light(color_debug_level);
-                        }
-                        Pass::Finished(af) => {
-                            after_future = Some(af);
+                let now = Instant::now();
+                input_handler.on_frame(&input, now - last_frame);
+                input.end_frame();
+                last_frame = now;
+
+                let rendered = match current.render_frame(scene) {
+                    Err(EngineError::SurfaceLost) => {
+                        log::warn!("Surface lost, recreating it");
+                        match recreate_surface(&instance, &current.surface) {
+                            Ok(surface) => current.replace_surface(surface),
+                            Err(e) => {
+                                log::error!("Failed to recreate the window surface: {}", e);
+                                Err(EngineError::SurfaceLost)
+                            }
                         }
                     }
-                }
-
-                let future = after_future
-                    .unwrap()
-                    .then_swapchain_present(
-                        state.graphics_queue.clone(),
-                        state.swap_chain.clone(),
-                        image_num,
-                    )
-                    .then_signal_fence_and_flush();
-
-                if let Some(v) = counter.tick() {
-                    log::info!("{:?} fps", v)
-                }
-                match future {
-                    Ok(future) => {
-                        state.previous_frame_end = Some(future.boxed());
+                    rendered => rendered,
+                };
+                match rendered {
+                    Ok(()) => {
+                        device_losses = 0;
+                        if let Some(v) = counter.tick() {
+                            log::info!("{:?} fps", v)
+                        }
                     }
-                    Err(FlushError::OutOfDate) => {
-                        state.recreate_swap_chain = true;
-                        state.previous_frame_end = Some(sync::now(state.device.clone()).boxed());
+                    Err(EngineError::DeviceLost) if device_losses < MAX_DEVICE_RECOVERIES => {
+                        device_losses += 1;
+                        let lost = renderer.take().unwrap();
+                        match lost.recover_from_device_loss() {
+                            Ok(recovered) => renderer = Some(recovered),
+                            Err(e) => {
+                                log::error!("Failed to recover from device loss: {}", e);
+                                result = Err(e);
+                                *control_flow = ControlFlow::Exit;
+                            }
+                        }
                     }
                     Err(e) => {
-                        log::info!("Failed to flush future: {:?}", e);
-                        state.previous_frame_end = Some(sync::now(state.device.clone()).boxed());
+                        log::error!("Render loop stopped: {}", e);
+                        result = Err(e);
+                        *control_flow = ControlFlow::Exit;
                     }
                 }
             }
             _ => (),
-        });
+        }
+    });
+
+    if let Some(path) = &trace_file {
+        if let Err(e) = tracing::dump_chrome_trace(path) {
+            log::error!("Failed to write trace {}: {}", path, e);
+        }
     }
+    result
+}
+
+/// Renders `path` through the camera of `scene` into `out_dir` as numbered PNG files, one every
+/// `1 / fps` seconds of the path whatever the time it takes to render them. `fps` has to be
+/// positive.
+///
+/// The frames are rendered in a window of the size of `config` and written once the GPU has
+/// finished each of them. Closing the window stops the rendering. Returns the number of written
+/// frames.
+pub fn render_sequence<T: PathCamera + Sized>(
+    scene: &Scene<T>,
+    path: &CameraPath,
+    fps: f32,
+    out_dir: &Path,
+    config: RendererConfig,
+    assets: AssetResolver,
+) -> Result<usize, EngineError> {
+    std::fs::create_dir_all(out_dir)?;
+    let instance = Renderer::create_instance(config.validation)?;
+    let mut event_loop = EventLoop::new();
+    let surface = create_window(&event_loop, &instance, &config)?;
+    let mut renderer = Renderer::new(surface, config, assets)?;
+
+    let frame_count = path.frame_count(fps);
+    let start = path.keyframes()[0].time;
+    let mut frame = 0;
+    let mut result = Ok(());
+    log::info!("Rendering {} frames to {}", frame_count, out_dir.display());
+
+    event_loop.run_return(|event, _, control_flow| match event {
+        Event::WindowEvent {
+            event: WindowEvent::CloseRequested,
+            ..
+        } => {
+            log::warn!("Window closed after {} of {} frames", frame, frame_count);
+            *control_flow = ControlFlow::Exit;
+        }
+        Event::WindowEvent {
+            event: WindowEvent::Resized(_),
+            ..
+        } => {
+            renderer.resize();
+        }
+        Event::RedrawEventsCleared => {
+            if frame >= frame_count {
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+            let pose = path.sample(start + frame as f32 / fps);
+            scene.camera.lock().unwrap().apply_pose(&pose);
+            let file = out_dir.join(format!("frame_{:05}.png", frame));
+            match renderer.capture_frame(scene, &file) {
+                Ok(true) => frame += 1,
+                // Rendered again once the swapchain is recreated or the window restored.
+                Ok(false) => (),
+                Err(e) => {
+                    log::error!("Sequence rendering stopped: {}", e);
+                    result = Err(e);
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+        }
+        _ => (),
+    });
+
+    result.map(|()| frame)
 }
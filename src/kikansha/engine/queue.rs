@@ -1,6 +1,8 @@
 pub struct QueueFamilyIndices {
     pub graphics_family: i32,
     pub present_family: i32,
+    // Family dedicated to transfers, -1 when the device has none.
+    pub transfer_family: i32,
 }
 
 impl QueueFamilyIndices {
@@ -9,6 +11,7 @@ impl QueueFamilyIndices {
         Self {
             graphics_family: -1,
             present_family: -1,
+            transfer_family: -1,
         }
     }
 
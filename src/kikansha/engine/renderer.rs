@@ -0,0 +1,1004 @@
+use crate::assets::AssetResolver;
+use crate::debug::messenger;
+use crate::debug::messenger::DebugMessengerConfig;
+use crate::debug::profiler::GpuProfiler;
+use crate::debug::tracing::timed;
+use crate::engine::cache::SceneCache;
+use crate::engine::config::DebugView;
+use crate::engine::config::GpuSelection;
+use crate::engine::config::RendererConfig;
+use crate::engine::error::EngineError;
+use crate::engine::gpu;
+use crate::engine::gpu::GpuInfo;
+use crate::engine::queue::QueueFamilyIndices;
+use crate::frame::culling::CullingSystem;
+use crate::frame::frame::Pass;
+use crate::frame::geometry::CullingStats;
+use crate::frame::geometry::TriangleDrawSystem;
+use crate::frame::system::FrameSystem;
+use crate::scene::camera::ViewAndProject;
+use crate::scene::Scene;
+use std::fs::File;
+use std::io::BufWriter;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState};
+use vulkano::device::{Device, DeviceExtensions, Features, Queue};
+use vulkano::format::Format;
+use vulkano::image::{ImageUsage, SwapchainImage};
+use vulkano::instance::{
+    debug::DebugCallback, layers_list, ApplicationInfo, Instance, InstanceExtensions,
+    PhysicalDevice, Version,
+};
+use vulkano::pipeline::viewport::Viewport;
+use vulkano::swapchain;
+use vulkano::swapchain::AcquireError;
+use vulkano::swapchain::PresentFuture;
+use vulkano::swapchain::SwapchainCreationError;
+use vulkano::swapchain::{Capabilities, ColorSpace, FullscreenExclusive, Surface, Swapchain};
+use vulkano::sync;
+use vulkano::sync::{FenceSignalFuture, FlushError, GpuFuture, SharingMode};
+use winit::window::Window;
+
+/// Required device extensions
+fn device_extensions(validation_layer: bool) -> DeviceExtensions {
+    DeviceExtensions {
+        khr_swapchain: true,
+        khr_storage_buffer_storage_class: true,
+        ext_debug_utils: validation_layer,
+        ..DeviceExtensions::none()
+    }
+}
+
+const VALIDATION_LAYERS: &[&str] = &[
+    // "VK_LAYER_RENDERDOC_Capture",
+    // "VK_LAYER_NV_optimus",
+    // "VK_LAYER_LUNARG_monitor",
+    // "VK_LAYER_LUNARG_screenshot",
+    // "VK_LAYER_LUNARG_device_simulation",
+    // "VK_LAYER_LUNARG_api_dump",
+    "VK_LAYER_KHRONOS_validation",
+];
+
+// Signalled once the GPU has finished a frame.
+/// Window rendered to, shared by the surfaces created for it.
+pub type SharedWindow = Arc<Window>;
+
+type FrameFence = Arc<FenceSignalFuture<PresentFuture<Box<dyn GpuFuture>, SharedWindow>>>;
+
+// Writes 8 bit BGRA or RGBA `pixels` of a captured frame as an RGBA PNG.
+fn write_png(
+    path: &Path,
+    dimensions: [u32; 2],
+    format: Format,
+    mut pixels: Vec<u8>,
+) -> Result<(), EngineError> {
+    match format {
+        Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => (),
+        format => {
+            return Err(EngineError::Capture(format!(
+                "{:?} frames can't be written as PNG",
+                format
+            )))
+        }
+    }
+    let file = File::create(path).map_err(|e| EngineError::Capture(e.to_string()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), dimensions[0], dimensions[1]);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|e| EngineError::Capture(e.to_string()))
+}
+
+/// Renders scenes into a window surface.
+///
+/// The application owns the window and its event loop, it calls `render_frame` when the window
+/// needs a new frame and `resize` when its size changes. `engine::run_loop` does this for a
+/// window it creates itself.
+pub struct Renderer {
+    instance: Arc<Instance>,
+    #[allow(dead_code)]
+    debug_callback: Option<DebugCallback>,
+    physical_device_index: usize,
+    pub device: Arc<Device>,
+    graphics_queue: Arc<Queue>,
+    present_queue: Arc<Queue>,
+    transfer_queue: Arc<Queue>,
+    pub surface: Arc<Surface<SharedWindow>>,
+    pub swap_chain: Arc<Swapchain<SharedWindow>>,
+    pub swap_chain_images: Vec<Arc<SwapchainImage<SharedWindow>>>,
+    pub dynamic_state: Mutex<DynamicState>,
+    previous_frame_end: Option<Box<dyn GpuFuture>>,
+    // One slot per frame in flight, the fence of a slot is waited on before it is reused.
+    frame_fences: Vec<Option<FrameFence>>,
+    // Profiler frame submitted in each slot, finished once the fence of the slot is signalled.
+    profiled_frames: Vec<Option<u64>>,
+    frame_index: usize,
+    recreate_swap_chain: bool,
+    scene_cache: SceneCache,
+    config: RendererConfig,
+    // Kept to recreate the scene cache after a device loss.
+    assets: AssetResolver,
+    pub frame_system: FrameSystem,
+    pub triangle_draw_system: TriangleDrawSystem,
+    // Set with `RendererConfig::gpu_culling`.
+    culling_system: Option<CullingSystem>,
+    color_debug_level: i32,
+    // The camera of the next rendered scene gets the aspect ratio of the swapchain.
+    aspect_ratio_outdated: bool,
+    culling_stats: CullingStats,
+}
+
+impl Renderer {
+    /// Creates the device and the swapchain of `surface`.
+    ///
+    /// The instance of the surface needs the extensions requested by `create_instance`.
+    pub fn new(
+        surface: Arc<Surface<SharedWindow>>,
+        config: RendererConfig,
+        assets: AssetResolver,
+    ) -> Result<Self, EngineError> {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        let instance = surface.instance().clone();
+        let validation_layer = config.validation;
+        let debug_callback =
+            Self::setup_debug_callback(&instance, validation_layer, &config.debug_messenger);
+
+        let physical_device_index = Self::pick_physical_device(
+            &instance,
+            &surface,
+            validation_layer,
+            &config.gpu,
+            &config.required_features,
+        )?;
+        let (device, graphics_queue, present_queue, transfer_queue) = Self::create_logical_device(
+            physical_device_index,
+            &instance,
+            &surface,
+            validation_layer,
+            &config.required_features,
+            config.gpu_culling,
+        )?;
+        let dynamic_state_raw = DynamicState::none();
+
+        let dynamic_state = Mutex::new(dynamic_state_raw);
+
+        let (swap_chain, swap_chain_images) = Self::create_swap_chain(
+            &instance,
+            &surface,
+            physical_device_index,
+            &device,
+            &graphics_queue,
+            &present_queue,
+            None,
+            &dynamic_state,
+            &config,
+        )?;
+
+        let dimensions = swap_chain_images[0].dimensions();
+        let mut frame_system =
+            FrameSystem::new(graphics_queue.clone(), swap_chain.format(), dimensions)?;
+        if config.gpu_profiler {
+            frame_system.profiler =
+                GpuProfiler::new(graphics_queue.clone(), 10, config.frames_in_flight);
+        }
+
+        let previous_frame_end = Some(sync::now(device.clone()).boxed());
+        let color_debug_level = config.debug_view.level();
+
+        let triangle_draw_system =
+            TriangleDrawSystem::new(graphics_queue.clone(), frame_system.deferred_subpass())?;
+        let culling_system = if !config.gpu_culling {
+            None
+        } else if !graphics_queue.family().supports_compute() {
+            log::warn!("The graphics queue doesn't support compute, culling on the CPU");
+            None
+        } else if !device.enabled_features().draw_indirect_first_instance {
+            log::warn!("The GPU doesn't support offset indirect draws, culling on the CPU");
+            None
+        } else {
+            Some(CullingSystem::new(graphics_queue.clone())?)
+        };
+
+        Ok(Renderer {
+            instance,
+            debug_callback,
+            physical_device_index,
+            device,
+            graphics_queue,
+            present_queue,
+            transfer_queue,
+            surface,
+            swap_chain,
+            swap_chain_images,
+            dynamic_state,
+            previous_frame_end,
+            frame_fences: (0..config.frames_in_flight.max(1)).map(|_| None).collect(),
+            profiled_frames: vec![None; config.frames_in_flight.max(1)],
+            frame_index: 0,
+            recreate_swap_chain: false,
+            scene_cache: SceneCache::new(assets.clone())?,
+            config,
+            assets,
+            frame_system,
+            triangle_draw_system,
+            culling_system,
+            color_debug_level,
+            aspect_ratio_outdated: true,
+            culling_stats: CullingStats::default(),
+        })
+    }
+
+    /// Creates an instance with the extensions needed for window surfaces, and the validation
+    /// layers if `validation_layer` is set and they are installed.
+    pub fn create_instance(validation_layer: bool) -> Result<Arc<Instance>, EngineError> {
+        let layers_supported = validation_layer && Self::check_validation_layer_support()?;
+        if validation_layer && !layers_supported {
+            log::error!("Validation layers requested, but not available!")
+        }
+        let supported_extensions = InstanceExtensions::supported_by_core()?;
+        let layers: Vec<_> = layers_list()?.map(|l| l.name().to_owned()).collect();
+
+        log::info!("Supported core extensions: {:?}", supported_extensions);
+        log::info!("Supported extensions: {:?}", layers);
+        let app_info = ApplicationInfo {
+            application_name: Some("Kikansha".into()),
+            application_version: Some(Version {
+                major: 1,
+                minor: 0,
+                patch: 0,
+            }),
+            engine_name: Some("No Engine".into()),
+            engine_version: Some(Version {
+                major: 1,
+                minor: 0,
+                patch: 0,
+            }),
+        };
+        let required_extensions = Self::get_required_extensions(validation_layer);
+        let instance = if layers_supported {
+            Instance::new(
+                Some(&app_info),
+                &required_extensions,
+                VALIDATION_LAYERS.iter().cloned(),
+            )?
+        } else {
+            Instance::new(Some(&app_info), &required_extensions, None)?
+        };
+        Ok(instance)
+    }
+
+    fn pick_physical_device(
+        instance: &Arc<Instance>,
+        surface: &Arc<Surface<SharedWindow>>,
+        validation_layer: bool,
+        selection: &GpuSelection,
+        required_features: &Features,
+    ) -> Result<usize, EngineError> {
+        gpu::select(instance, selection, required_features, |device| {
+            Self::is_device_suitable(device, surface, validation_layer)
+        })
+        .ok_or(EngineError::NoSuitableGpu)
+    }
+
+    /// Describes every physical device, without creating a window.
+    pub fn list_gpus() -> Result<Vec<GpuInfo>, EngineError> {
+        let instance = Self::create_instance(false)?;
+        Ok(gpu::list_gpus(&instance))
+    }
+
+    fn create_logical_device(
+        physical_device_idx: usize,
+        instance: &Arc<Instance>,
+        surface: &Arc<Surface<SharedWindow>>,
+        validation_layer: bool,
+        required_features: &Features,
+        gpu_culling: bool,
+    ) -> Result<(Arc<Device>, Arc<Queue>, Arc<Queue>, Arc<Queue>), EngineError> {
+        let physical_device = PhysicalDevice::from_index(instance, physical_device_idx)
+            .ok_or(EngineError::NoSuitableGpu)?;
+        let indices = Self::find_queue_families(surface, &physical_device);
+        log::trace!("1");
+        let families = [
+            indices.graphics_family,
+            indices.present_family,
+            indices.transfer_family,
+        ];
+
+        let mut uniquer_queue_family: Vec<i32> = Vec::new();
+        for family in families.iter() {
+            if *family >= 0 && !uniquer_queue_family.contains(family) {
+                uniquer_queue_family.push(*family);
+            }
+        }
+        let queue_priority = 1.0;
+
+        let queue_families = uniquer_queue_family.iter().map(|i| {
+            (
+                physical_device.queue_families().nth(*i as usize).unwrap(),
+                queue_priority,
+            )
+        });
+
+        // Enabled when supported, `CullingSystem` draws from an offset into the visible instances.
+        let supported = physical_device.supported_features();
+        let draw_indirect_first_instance = required_features.draw_indirect_first_instance
+            || (gpu_culling && supported.draw_indirect_first_instance);
+        let features = Features {
+            draw_indirect_first_instance,
+            ..*required_features
+        };
+
+        let (device, queues) = Device::new(
+            physical_device,
+            &features,
+            &device_extensions(validation_layer),
+            queue_families,
+        )?;
+        let queues: Vec<Arc<Queue>> = queues.collect();
+        let queue_of = |family: i32| {
+            queues
+                .iter()
+                .find(|queue| queue.family().id() as i32 == family)
+                .cloned()
+        };
+        let graphics_queue = queue_of(indices.graphics_family).ok_or(EngineError::NoSuitableGpu)?;
+        let present_queue =
+            queue_of(indices.present_family).unwrap_or_else(|| graphics_queue.clone());
+        // Without a dedicated family uploads simply go through the graphics queue.
+        let transfer_queue =
+            queue_of(indices.transfer_family).unwrap_or_else(|| graphics_queue.clone());
+        log::info!(
+            "Using queue families: graphics {}, present {}, transfer {}",
+            graphics_queue.family().id(),
+            present_queue.family().id(),
+            transfer_queue.family().id()
+        );
+        Ok((device, graphics_queue, present_queue, transfer_queue))
+    }
+
+    fn check_device_support_extension(device: &PhysicalDevice, validation_layer: bool) -> bool {
+        let available_extensions = DeviceExtensions::supported_by_device(*device);
+        let device_extensions = device_extensions(validation_layer);
+        log::trace!(
+            "available_extensions: {:?}, \ndevice_extensions: {:?}",
+            available_extensions,
+            device_extensions,
+        );
+        available_extensions.intersection(&device_extensions) == device_extensions
+    }
+
+    fn is_device_suitable(
+        device: &PhysicalDevice,
+        surface: &Arc<Surface<SharedWindow>>,
+        validation_layer: bool,
+    ) -> bool {
+        let indices = Self::find_queue_families(surface, device);
+        let extension_supported = Self::check_device_support_extension(device, validation_layer);
+        let swap_chain_adequate = if extension_supported {
+            match surface.capabilities(*device) {
+                Ok(capabilities) => {
+                    !capabilities.supported_formats.is_empty()
+                        && capabilities.present_modes.iter().next().is_some()
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Failed to get surface capabilities of {}: {}",
+                        device.name(),
+                        e
+                    );
+                    false
+                }
+            }
+        } else {
+            false
+        };
+        log::trace!(
+            "3 {}, {}, {},",
+            indices.is_complete(),
+            extension_supported,
+            swap_chain_adequate
+        );
+        indices.is_complete() && extension_supported && swap_chain_adequate
+    }
+
+    fn find_queue_families(
+        surface: &Arc<Surface<SharedWindow>>,
+        device: &PhysicalDevice,
+    ) -> QueueFamilyIndices {
+        let mut indices = QueueFamilyIndices::new();
+        // TODO: replace index with id to simplify?
+        for (i, queue_family) in device.queue_families().enumerate() {
+            if queue_family.supports_graphics() && indices.graphics_family < 0 {
+                indices.graphics_family = i as i32;
+            }
+
+            if surface.is_supported(queue_family).unwrap_or(false) && indices.present_family < 0 {
+                indices.present_family = i as i32;
+            }
+
+            if queue_family.explicitly_supports_transfers()
+                && !queue_family.supports_graphics()
+                && indices.transfer_family < 0
+            {
+                indices.transfer_family = i as i32;
+            }
+        }
+
+        indices
+    }
+
+    fn create_swap_chain(
+        instance: &Arc<Instance>,
+        surface: &Arc<Surface<SharedWindow>>,
+        physical_device_index: usize,
+        device: &Arc<Device>,
+        graphics_queue: &Arc<Queue>,
+        present_queue: &Arc<Queue>,
+        old_swapchain: Option<Arc<Swapchain<SharedWindow>>>,
+        dynamic_state: &Mutex<DynamicState>,
+        config: &RendererConfig,
+    ) -> Result<
+        (
+            Arc<Swapchain<SharedWindow>>,
+            Vec<Arc<SwapchainImage<SharedWindow>>>,
+        ),
+        EngineError,
+    > {
+        let physical_device = PhysicalDevice::from_index(&instance, physical_device_index)
+            .ok_or(EngineError::NoSuitableGpu)?;
+        let capabilities = surface.capabilities(physical_device)?;
+
+        let surface_format = Self::choose_swap_surface_format(
+            &capabilities.supported_formats,
+            config.surface_format.format(),
+        );
+        let present_mode = config.present_mode.choose(capabilities.present_modes);
+        let window_size: [u32; 2] = surface.window().inner_size().into();
+        let extent = Self::choose_swap_extent(&capabilities, window_size);
+        log::trace!("present mode {:?}", present_mode);
+
+        let mut image_count = capabilities.min_image_count + 1;
+        // No maximum means any number of images.
+        if let Some(max_image_count) = capabilities.max_image_count {
+            image_count = image_count.min(max_image_count);
+        }
+        log::trace!("image_count {}", image_count);
+
+        let alpha = match capabilities.supported_composite_alpha.iter().next() {
+            Some(alpha) => alpha,
+            None => {
+                let e = SwapchainCreationError::UnsupportedCompositeAlpha;
+                return Err(EngineError::Swapchain(e));
+            }
+        };
+
+        // Copying the images back allows capturing frames, see `capture_frame`.
+        let image_usage = ImageUsage {
+            transfer_source: capabilities.supported_usage_flags.transfer_source,
+            ..ImageUsage::color_attachment()
+        };
+
+        let indicies = Self::find_queue_families(&surface, &physical_device);
+
+        let sharing: SharingMode = if indicies.graphics_family != indicies.present_family {
+            vec![graphics_queue, present_queue].as_slice().into()
+        } else {
+            graphics_queue.into()
+        };
+
+        log::trace!("1");
+
+        let (swap_chain, images) = match old_swapchain {
+            Some(old) => Swapchain::with_old_swapchain(
+                device.clone(),
+                surface.clone(),
+                image_count,
+                surface_format.0,
+                extent,
+                1,
+                image_usage,
+                sharing,
+                capabilities.current_transform,
+                alpha,
+                present_mode,
+                FullscreenExclusive::Default,
+                true,
+                ColorSpace::SrgbNonLinear,
+                old,
+            )?,
+            None => Swapchain::new(
+                device.clone(),
+                surface.clone(),
+                image_count,
+                surface_format.0,
+                extent,
+                1,
+                image_usage,
+                sharing,
+                capabilities.current_transform,
+                alpha,
+                present_mode,
+                FullscreenExclusive::Default,
+                true,
+                ColorSpace::SrgbNonLinear,
+            )?,
+        };
+
+        let swap_chain_extent = swap_chain.dimensions();
+        let dimensions = [swap_chain_extent[0] as f32, swap_chain_extent[1] as f32];
+        let viewport = Viewport {
+            origin: [0.0, 0.0],
+            dimensions,
+            depth_range: 0.0..1.0,
+        };
+        {
+            dynamic_state.lock().unwrap().viewports = Some(vec![viewport]);
+        };
+
+        log::trace!("2");
+        Ok((swap_chain, images))
+    }
+
+    fn choose_swap_surface_format(
+        available_formats: &[(Format, ColorSpace)],
+        preferred_format: Format,
+    ) -> (Format, ColorSpace) {
+        // NOTE: the 'preferred format' mentioned in the tutorial doesn't seem to be
+        // queryable in Vulkano (no VK_FORMAT_UNDEFINED enum)
+        *available_formats
+            .iter()
+            .find(|(format, color_space)| {
+                *format == preferred_format && *color_space == ColorSpace::SrgbNonLinear
+            })
+            .unwrap_or_else(|| &available_formats[0])
+    }
+
+    fn choose_swap_extent(capabilities: &Capabilities, window_size: [u32; 2]) -> [u32; 2] {
+        if let Some(current_extent) = capabilities.current_extent {
+            current_extent
+        } else {
+            let mut actual_extent = window_size;
+            actual_extent[0] = capabilities.min_image_extent[0]
+                .max(capabilities.max_image_extent[0].min(actual_extent[0]));
+            actual_extent[1] = capabilities.min_image_extent[1]
+                .max(capabilities.max_image_extent[1].min(actual_extent[1]));
+            actual_extent
+        }
+    }
+
+    fn check_validation_layer_support() -> Result<bool, EngineError> {
+        let layers: Vec<_> = layers_list()?.map(|l| l.name().to_owned()).collect();
+        log::trace!(
+            "supported layers: {:?}, \n reuired layers: {:?}",
+            layers,
+            VALIDATION_LAYERS
+        );
+        Ok(VALIDATION_LAYERS
+            .iter()
+            .all(|layer_name| layers.contains(&layer_name.to_string())))
+    }
+
+    fn get_required_extensions(validation_layer: bool) -> InstanceExtensions {
+        let mut extensions = vulkano_win::required_extensions();
+
+        // extensions.khr_swapchain = true;
+        // extensions.khr_storage_buffer_storage_class = true;
+        // extensions.ext_debug_utils = true;
+
+        extensions.ext_debug_utils = validation_layer;
+        // extensions.khr_wayland_surface = false;
+        // extensions.khr_android_surface = false;
+        // extensions.khr_win32_surface = false;
+        // extensions.mvk_ios_surface = false;
+        // extensions.mvk_macos_surface = false;
+        // extensions.nn_vi_surface = false;
+
+        extensions
+    }
+    fn setup_debug_callback(
+        instance: &Arc<Instance>,
+        validation_layer: bool,
+        messenger_config: &DebugMessengerConfig,
+    ) -> Option<DebugCallback> {
+        if !validation_layer {
+            return None;
+        }
+        messenger::create_messenger(instance, messenger_config)
+    }
+
+    fn recreate_swap_chain(&mut self) -> Result<(), EngineError> {
+        let (swap_chain, images) = Self::create_swap_chain(
+            &self.instance,
+            &self.surface,
+            self.physical_device_index,
+            &self.device,
+            &self.graphics_queue,
+            &self.present_queue,
+            Some(self.swap_chain.clone()),
+            &self.dynamic_state,
+            &self.config,
+        )?;
+
+        let dimensions = images[0].dimensions();
+
+        self.frame_system
+            .recreate_render_pass(swap_chain.format(), dimensions)?;
+        self.swap_chain = swap_chain;
+        self.swap_chain_images = images;
+        Ok(())
+    }
+
+    /// Renders into `surface` from now on, used when `render_frame` returned
+    /// `EngineError::SurfaceLost`.
+    ///
+    /// The surface has to be created from the instance of the lost one and supported by the
+    /// same device, `engine::recreate_surface` creates one for the same window.
+    pub fn replace_surface(
+        &mut self,
+        surface: Arc<Surface<SharedWindow>>,
+    ) -> Result<(), EngineError> {
+        let (swap_chain, images) = Self::create_swap_chain(
+            &self.instance,
+            &surface,
+            self.physical_device_index,
+            &self.device,
+            &self.graphics_queue,
+            &self.present_queue,
+            None,
+            &self.dynamic_state,
+            &self.config,
+        )?;
+
+        let dimensions = images[0].dimensions();
+
+        self.frame_system
+            .recreate_render_pass(swap_chain.format(), dimensions)?;
+        self.surface = surface;
+        self.swap_chain = swap_chain;
+        self.swap_chain_images = images;
+        self.recreate_swap_chain = false;
+        self.aspect_ratio_outdated = true;
+        Ok(())
+    }
+
+    /// Recreates the device and everything created from it after `render_frame` returned
+    /// `EngineError::DeviceLost`, the new scene cache uploads the scene again.
+    pub fn recover_from_device_loss(mut self) -> Result<Self, EngineError> {
+        log::warn!("Device lost, recreating it");
+        // Dropping a frame future waits for its fence and vulkano unwraps the result, which fails
+        // on a lost device. The futures are released one by one so that a failure doesn't leave
+        // the swapchain of the surface alive.
+        let futures: Vec<Box<dyn GpuFuture>> = self
+            .frame_fences
+            .drain(..)
+            .flatten()
+            .map(|fence| fence.boxed())
+            .chain(self.previous_frame_end.take())
+            .collect();
+        for future in futures {
+            if panic::catch_unwind(AssertUnwindSafe(|| drop(future))).is_err() {
+                log::warn!("Failed to wait for a frame of the lost device");
+            }
+        }
+
+        let surface = self.surface.clone();
+        let config = self.config.clone();
+        let assets = self.assets.clone();
+        // The old swapchain has to be destroyed before the surface gets a new one.
+        drop(self);
+        Self::new(surface, config, assets)
+    }
+
+    pub fn config(&self) -> &RendererConfig {
+        &self.config
+    }
+
+    /// Instances drawn and skipped by frustum culling in the last rendered frame, instances
+    /// culled on the GPU are only counted as tested.
+    pub fn culling_stats(&self) -> CullingStats {
+        self.culling_stats
+    }
+
+    /// Shows a G-buffer attachment instead of the lit scene.
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.config.debug_view = debug_view;
+        self.color_debug_level = debug_view.level();
+    }
+
+    /// Recreates the swapchain before the next frame, to be called when the window is resized.
+    pub fn resize(&mut self) {
+        self.recreate_swap_chain = true;
+    }
+
+    /// A zero sized surface can't have a swapchain, nothing is rendered until the window is
+    /// restored.
+    pub fn is_minimized(&self) -> bool {
+        let size = self.surface.window().inner_size();
+        size.width == 0 || size.height == 0
+    }
+
+    fn update_aspect_ratio<T: ViewAndProject + Sized>(&mut self, scene: &Scene<T>) {
+        let dimensions = self.swap_chain.dimensions();
+        if dimensions[0] > 0 && dimensions[1] > 0 {
+            let mut locked_camera = scene.camera.lock().unwrap();
+            locked_camera.update_ar(dimensions[0] as f32 / dimensions[1] as f32);
+        }
+        self.aspect_ratio_outdated = false;
+    }
+
+    /// Records and submits a frame of `scene`, skipped while the window is minimized.
+    ///
+    /// Out of date swapchains are recreated here. Lost surfaces and devices are returned, see
+    /// `replace_surface` and `recover_from_device_loss`.
+    pub fn render_frame<T: ViewAndProject + Sized>(
+        &mut self,
+        scene: &Scene<T>,
+    ) -> Result<(), EngineError> {
+        self.draw_frame(scene, None).map(|_| ())
+    }
+
+    /// Renders a frame of `scene` like `render_frame` and writes it to `path` as a PNG once the
+    /// GPU has finished it.
+    ///
+    /// Returns `false` if no frame was rendered, because the window is minimized or the
+    /// swapchain had to be recreated, the call should then be repeated.
+    pub fn capture_frame<T: ViewAndProject + Sized>(
+        &mut self,
+        scene: &Scene<T>,
+        path: &Path,
+    ) -> Result<bool, EngineError> {
+        self.draw_frame(scene, Some(path))
+    }
+
+    // Command buffer copying the swapchain image `image_num` into a host visible buffer.
+    fn record_capture(
+        &self,
+        image_num: usize,
+    ) -> Result<(AutoCommandBuffer, Arc<CpuAccessibleBuffer<[u8]>>), EngineError> {
+        let physical_device =
+            PhysicalDevice::from_index(&self.instance, self.physical_device_index)
+                .ok_or(EngineError::NoSuitableGpu)?;
+        let capabilities = self.surface.capabilities(physical_device)?;
+        if !capabilities.supported_usage_flags.transfer_source {
+            return Err(EngineError::Capture(
+                "the swapchain images can't be copied".to_string(),
+            ));
+        }
+        let [width, height] = self.swap_chain.dimensions();
+        let buffer = CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            BufferUsage::transfer_destination(),
+            false,
+            (0..width * height * 4).map(|_| 0u8),
+        )?;
+        let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            self.graphics_queue.family(),
+        )?;
+        builder
+            .copy_image_to_buffer(self.swap_chain_images[image_num].clone(), buffer.clone())
+            .map_err(|e| EngineError::Capture(e.to_string()))?;
+        let command_buffer = builder
+            .build()
+            .map_err(|e| EngineError::Capture(e.to_string()))?;
+        Ok((command_buffer, buffer))
+    }
+
+    fn draw_frame<T: ViewAndProject + Sized>(
+        &mut self,
+        scene: &Scene<T>,
+        capture: Option<&Path>,
+    ) -> Result<bool, EngineError> {
+        if self.is_minimized() {
+            return Ok(false);
+        }
+        self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+
+        if self.recreate_swap_chain {
+            log::trace!("recreate_swap_chain");
+            match self.recreate_swap_chain() {
+                Ok(()) => (),
+                // The window is being resized, retried on the next frame.
+                Err(EngineError::Swapchain(SwapchainCreationError::UnsupportedDimensions)) => {
+                    return Ok(false)
+                }
+                Err(e) => return Err(e),
+            }
+            self.aspect_ratio_outdated = true;
+            self.recreate_swap_chain = false;
+        }
+        if self.aspect_ratio_outdated {
+            self.update_aspect_ratio(scene);
+        }
+
+        // The oldest frame has to finish before its slot is reused, which keeps at most
+        // `frames_in_flight` frames queued.
+        if let Some(fence) = self.frame_fences[self.frame_index].take() {
+            timed("wait for frame", || fence.wait(None))?;
+        }
+        if let Some(number) = self.profiled_frames[self.frame_index].take() {
+            if let Some(profiler) = self.frame_system.profiler.as_mut() {
+                profiler.frame_finished(number);
+            }
+        }
+
+        let (image_num, suboptimal, acquire_future) =
+            match swapchain::acquire_next_image(self.swap_chain.clone(), None) {
+                Ok(r) => r,
+                Err(AcquireError::OutOfDate) | Err(AcquireError::FullscreenExclusiveLost) => {
+                    self.recreate_swap_chain = true;
+                    return Ok(false);
+                }
+                Err(e) => return Err(e.into()),
+            };
+        if suboptimal {
+            self.recreate_swap_chain = true;
+        }
+
+        let mut after_future = None;
+
+        let matrices = {
+            let locked_camera = scene.camera.lock().unwrap();
+            locked_camera.get_matrices()
+        };
+
+        let dynamic_state = { self.dynamic_state.lock().unwrap().clone() };
+        let cached_scene = self
+            .scene_cache
+            .get_cache(scene, self.transfer_queue.clone())?;
+
+        // Meshes uploaded by the cache have to reach the device before they are drawn.
+        let previous_frame_end = match self.scene_cache.take_uploads() {
+            Some(uploads) => self
+                .previous_frame_end
+                .take()
+                .unwrap()
+                .join(uploads)
+                .boxed(),
+            None => self.previous_frame_end.take().unwrap(),
+        };
+        let future = previous_frame_end.join(acquire_future);
+
+        // The culling pass writes the indirect draws of the geometry pass.
+        let (future, indirect_draws) = match self
+            .culling_system
+            .as_mut()
+            .and_then(|system| system.cull(&matrices, &cached_scene))
+        {
+            Some(culling) => {
+                match future.then_execute(self.graphics_queue.clone(), culling.command_buffer) {
+                    Ok(future) => (future.then_signal_semaphore().boxed(), Some(culling.draws)),
+                    Err(e) => {
+                        self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                        return Err(e.into());
+                    }
+                }
+            }
+            None => (future.boxed(), None),
+        };
+
+        let recorded: Result<(), EngineError> = timed("record commands", || {
+            let mut frame = self.frame_system.frame(
+                future,
+                self.swap_chain_images[image_num].clone(),
+                scene.lights.clone(),
+                matrices,
+                cached_scene.clone(),
+                dynamic_state.clone(),
+            )?;
+
+            while let Some(pass) = frame.next_pass() {
+                match pass {
+                    Pass::Deferred(mut draw_pass) => {
+                        let (cb, culling) = self.triangle_draw_system.draw(
+                            &matrices,
+                            &cached_scene,
+                            indirect_draws.as_ref(),
+                            &dynamic_state,
+                        );
+                        let indirect_count = indirect_draws.as_ref().map_or(0, |i| i.draws.len());
+                        draw_pass.execute(cb);
+                        draw_pass.add_draw_calls(culling.visible + indirect_count);
+                        draw_pass.add_culling_stats(culling);
+                        self.culling_stats = culling;
+                    }
+                    Pass::Lighting(mut lighting) => {
+                        lighting.light(self.color_debug_level);
+                    }
+                    Pass::Finished(af) => {
+                        after_future = Some(af);
+                    }
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = recorded {
+            self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+            return Err(e);
+        }
+
+        let after_future = after_future.unwrap();
+        let (after_future, capture_buffer) = match capture {
+            Some(_) => {
+                let (command_buffer, buffer) = match self.record_capture(image_num) {
+                    Ok(recorded) => recorded,
+                    Err(e) => {
+                        self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                        return Err(e);
+                    }
+                };
+                match after_future.then_execute(self.graphics_queue.clone(), command_buffer) {
+                    Ok(future) => (future.boxed(), Some(buffer)),
+                    Err(e) => {
+                        self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                        return Err(EngineError::Capture(e.to_string()));
+                    }
+                }
+            }
+            None => (after_future, None),
+        };
+
+        let future = after_future
+            .then_swapchain_present(
+                self.graphics_queue.clone(),
+                self.swap_chain.clone(),
+                image_num,
+            )
+            .then_signal_fence_and_flush();
+
+        if self.config.debug_messenger.panic_on_error {
+            if let (count, Some(last)) = messenger::take_validation_errors() {
+                panic!("{} validation errors, last one: {}", count, last);
+            }
+        }
+        let frame_index = self.frame_index;
+        self.frame_index = (self.frame_index + 1) % self.frame_fences.len();
+        match future {
+            Ok(future) => {
+                let future = Arc::new(future);
+                self.frame_fences[frame_index] = Some(future.clone());
+                self.profiled_frames[frame_index] = self
+                    .frame_system
+                    .profiler
+                    .as_ref()
+                    .and_then(|profiler| profiler.last_frame());
+                self.previous_frame_end = Some(future.clone().boxed());
+                if let (Some(path), Some(buffer)) = (capture, capture_buffer) {
+                    future.wait(None)?;
+                    let pixels = buffer
+                        .read()
+                        .map_err(|e| EngineError::Capture(e.to_string()))?
+                        .to_vec();
+                    write_png(
+                        path,
+                        self.swap_chain.dimensions(),
+                        self.swap_chain.format(),
+                        pixels,
+                    )?;
+                }
+                Ok(true)
+            }
+            Err(FlushError::OutOfDate) | Err(FlushError::FullscreenExclusiveLost) => {
+                self.recreate_swap_chain = true;
+                self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                Ok(false)
+            }
+            Err(e) => {
+                self.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                Err(e.into())
+            }
+        }
+    }
+}
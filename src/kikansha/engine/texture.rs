@@ -0,0 +1,359 @@
+use crate::engine::loader::decode_png;
+use crate::engine::loader::DecodedTexture;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io;
+use std::sync::Arc;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::CpuAccessibleBuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::CommandBuffer;
+use vulkano::device::Queue;
+use vulkano::format::Format;
+use vulkano::image::{Dimensions, ImageLayout, ImageUsage, ImmutableImage, MipmapsCount};
+use vulkano::sync::GpuFuture;
+
+/// Identifies decoded pixels: the hash of the file, the format of the texture and its size.
+///
+/// Different images may still share a key, the pixels are compared before a texture is shared.
+type ContentKey = (u64, u32, u32, u32);
+
+#[derive(Debug)]
+struct CachedTexture<T> {
+    // Decoded pixels, kept to tell textures with the same content key apart.
+    data: Vec<u8>,
+    texture: Arc<T>,
+}
+
+/// Cache of GPU textures shared between figures and between scene rebuilds.
+///
+/// Textures are first looked up by path and then by their decoded content, so two paths pointing
+/// to the same image end up sharing a single GPU copy. The cache holds one reference to every
+/// texture; `evict_unused` drops the textures no figure references anymore. It is not `Clone`, a
+/// copy would hold a second reference and keep every texture alive.
+///
+/// Textures are decoded by the `AssetLoader`, the cache only remembers which paths are still in
+/// flight so every path is requested once.
+#[derive(Debug)]
+pub struct TextureCache<T = ImmutableImage<Format>> {
+    by_path: HashMap<(String, u32), u64>,
+    by_content: HashMap<ContentKey, Vec<u64>>,
+    textures: HashMap<u64, CachedTexture<T>>,
+    next_id: u64,
+    pending: HashSet<String>,
+}
+
+impl<T> Default for TextureCache<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> TextureCache<T> {
+    pub fn new() -> Self {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        TextureCache {
+            by_path: HashMap::new(),
+            by_content: HashMap::new(),
+            textures: HashMap::new(),
+            next_id: 0,
+            pending: HashSet::new(),
+        }
+    }
+
+    pub fn lookup(&self, path: &str, format: Format) -> Option<Arc<T>> {
+        self.by_path
+            .get(&(path.to_string(), format as u32))
+            .and_then(|id| self.textures.get(id))
+            .map(|cached| cached.texture.clone())
+    }
+
+    /// Marks `path` as requested, returns `false` if it is already loaded or being loaded.
+    pub fn start_loading(&mut self, path: &str, format: Format) -> bool {
+        if self.lookup(path, format).is_some() || self.pending.contains(path) {
+            return false;
+        }
+        self.pending.insert(path.to_string());
+        true
+    }
+
+    pub fn failed_loading(&mut self, path: &str) {
+        self.pending.remove(path);
+    }
+
+    /// Stores a decoded texture, calling `upload` only if no cached texture has the same pixels.
+    fn insert_with<F, U>(
+        &mut self,
+        path: &str,
+        format: Format,
+        decoded: DecodedTexture,
+        upload: F,
+    ) -> io::Result<Option<U>>
+    where
+        F: FnOnce(DecodedTexture) -> io::Result<(Arc<T>, U)>,
+    {
+        self.pending.remove(path);
+        let path_key = (path.to_string(), format as u32);
+        let content_key = (
+            decoded.content_hash,
+            format as u32,
+            decoded.width,
+            decoded.height,
+        );
+
+        let textures = &self.textures;
+        let shared = self.by_content.get(&content_key).and_then(|ids| {
+            ids.iter()
+                .copied()
+                .find(|id| textures[id].data == decoded.data)
+        });
+        if let Some(id) = shared {
+            log::trace!("texture {} shares content with a cached one", path);
+            self.by_path.insert(path_key, id);
+            return Ok(None);
+        }
+
+        let data = decoded.data.clone();
+        let (texture, upload) = upload(decoded)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.textures.insert(id, CachedTexture { data, texture });
+        self.by_content.entry(content_key).or_default().push(id);
+        self.by_path.insert(path_key, id);
+        Ok(Some(upload))
+    }
+
+    /// Drops every texture that is referenced only by the cache itself and whose path is not in
+    /// `referenced`.
+    ///
+    /// The paths of figures still waiting for their mesh have to be passed as `referenced`, nothing
+    /// else holds their textures yet and they are not requested again.
+    ///
+    /// Returns the number of evicted textures.
+    pub fn evict_unused<'a, I>(&mut self, referenced: I) -> usize
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let referenced: HashSet<&str> = referenced.into_iter().collect();
+        let live: HashSet<u64> = self
+            .by_path
+            .iter()
+            .filter(|((path, _), _)| referenced.contains(path.as_str()))
+            .map(|(_, id)| *id)
+            .collect();
+        let before = self.textures.len();
+        self.textures
+            .retain(|id, cached| live.contains(id) || Arc::strong_count(&cached.texture) > 1);
+        let textures = &self.textures;
+        self.by_path.retain(|_, id| textures.contains_key(id));
+        self.by_content.retain(|_, ids| {
+            ids.retain(|id| textures.contains_key(id));
+            !ids.is_empty()
+        });
+        let evicted = before - self.textures.len();
+        if evicted > 0 {
+            log::trace!("evicted {} unused textures", evicted);
+        }
+        evicted
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+}
+
+impl TextureCache {
+    /// Uploads a decoded texture unless an image with the same content is already cached.
+    ///
+    /// Returns the future of the upload, if one was started.
+    pub fn insert_decoded(
+        &mut self,
+        path: &str,
+        format: Format,
+        decoded: DecodedTexture,
+        queue: &Arc<Queue>,
+    ) -> io::Result<Option<Box<dyn GpuFuture>>> {
+        self.insert_with(path, format, decoded, |decoded| {
+            upload_texture(decoded, format, queue)
+        })
+    }
+}
+
+fn other_error<E>(e: E) -> io::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Copies decoded pixels into a device local image on `queue`.
+///
+/// The image is shared by every queue family of the device, so it may be uploaded on a transfer
+/// queue and sampled on the graphics one. The returned future signals a semaphore once the copy
+/// is done.
+pub fn upload_texture(
+    decoded: DecodedTexture,
+    format: Format,
+    queue: &Arc<Queue>,
+) -> io::Result<(Arc<ImmutableImage<Format>>, Box<dyn GpuFuture>)> {
+    let device = queue.device().clone();
+    let dimensions = Dimensions::Dim2d {
+        width: decoded.width,
+        height: decoded.height,
+    };
+    let usage = ImageUsage {
+        transfer_destination: true,
+        sampled: true,
+        ..ImageUsage::none()
+    };
+    let (texture, init) = ImmutableImage::uninitialized(
+        device.clone(),
+        dimensions,
+        format,
+        MipmapsCount::One,
+        usage,
+        ImageLayout::ShaderReadOnlyOptimal,
+        device.active_queue_families(),
+    )
+    .map_err(other_error)?;
+
+    let source = CpuAccessibleBuffer::from_iter(
+        device.clone(),
+        BufferUsage::transfer_source(),
+        false,
+        decoded.data.into_iter(),
+    )
+    .map_err(other_error)?;
+
+    let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(device, queue.family())
+        .map_err(other_error)?;
+    builder
+        .copy_buffer_to_image_dimensions(
+            source,
+            init,
+            [0, 0, 0],
+            dimensions.width_height_depth(),
+            0,
+            dimensions.array_layers_with_cube(),
+            0,
+        )
+        .map_err(other_error)?;
+    let upload = builder
+        .build()
+        .map_err(other_error)?
+        .execute(queue.clone())
+        .map_err(other_error)?
+        .then_signal_semaphore_and_flush()
+        .map_err(other_error)?;
+    Ok((texture, upload.boxed()))
+}
+
+/// Decodes and uploads a texture, blocking until the copy is finished.
+pub fn load_texture_from_bytes(
+    bytes: &[u8],
+    format: Format,
+    queue: Arc<Queue>,
+) -> io::Result<Arc<ImmutableImage<Format>>> {
+    let decoded = decode_png(bytes)?;
+    let (texture, upload) = upload_texture(decoded, format, &queue)?;
+    upload
+        .then_signal_fence_and_flush()
+        .and_then(|fence| fence.wait(None))
+        .map_err(other_error)?;
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: Format = Format::R8G8B8A8Srgb;
+
+    fn decoded(content_hash: u64, data: Vec<u8>) -> DecodedTexture {
+        DecodedTexture {
+            width: 1,
+            height: 1,
+            data,
+            content_hash,
+        }
+    }
+
+    /// Inserts a texture, returns `true` if it had to be uploaded.
+    fn insert(cache: &mut TextureCache<u32>, path: &str, content: DecodedTexture) -> bool {
+        let id = cache.len() as u32;
+        cache
+            .insert_with(path, FORMAT, content, |_| Ok((Arc::new(id), ())))
+            .unwrap()
+            .is_some()
+    }
+
+    #[test]
+    fn same_content_is_uploaded_once() {
+        let mut cache = TextureCache::new();
+        assert!(insert(&mut cache, "a.png", decoded(7, vec![1, 2, 3, 4])));
+        assert!(!insert(&mut cache, "b.png", decoded(7, vec![1, 2, 3, 4])));
+        assert_eq!(cache.len(), 1);
+        let a = cache.lookup("a.png", FORMAT).unwrap();
+        let b = cache.lookup("b.png", FORMAT).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+    }
+
+    #[test]
+    fn colliding_hashes_are_told_apart_by_the_pixels() {
+        let mut cache = TextureCache::new();
+        assert!(insert(&mut cache, "a.png", decoded(7, vec![1, 2, 3, 4])));
+        assert!(insert(&mut cache, "b.png", decoded(7, vec![4, 3, 2, 1])));
+        let unorm = cache
+            .insert_with(
+                "c.png",
+                Format::R8G8B8A8Unorm,
+                decoded(7, vec![1, 2, 3, 4]),
+                |_| Ok((Arc::new(2), ())),
+            )
+            .unwrap();
+        assert!(unorm.is_some());
+        assert_eq!(cache.len(), 3);
+        let a = cache.lookup("a.png", FORMAT).unwrap();
+        let b = cache.lookup("b.png", FORMAT).unwrap();
+        assert!(!Arc::ptr_eq(&a, &b));
+    }
+
+    #[test]
+    fn evict_keeps_referenced_textures() {
+        let mut cache = TextureCache::new();
+        insert(&mut cache, "a.png", decoded(1, vec![1]));
+        insert(&mut cache, "b.png", decoded(1, vec![1]));
+        assert_eq!(cache.evict_unused(vec!["b.png"]), 0);
+        assert!(cache.lookup("a.png", FORMAT).is_some());
+        assert!(cache.lookup("b.png", FORMAT).is_some());
+    }
+
+    #[test]
+    fn evict_keeps_textures_still_in_use() {
+        let mut cache = TextureCache::new();
+        insert(&mut cache, "a.png", decoded(1, vec![1]));
+        let in_use = cache.lookup("a.png", FORMAT).unwrap();
+        assert_eq!(cache.evict_unused(vec![]), 0);
+        assert!(cache.lookup("a.png", FORMAT).is_some());
+        drop(in_use);
+        assert_eq!(cache.evict_unused(vec![]), 1);
+    }
+
+    #[test]
+    fn evict_drops_everything_else() {
+        let mut cache = TextureCache::new();
+        insert(&mut cache, "kept.png", decoded(1, vec![1]));
+        insert(&mut cache, "a.png", decoded(2, vec![2]));
+        insert(&mut cache, "b.png", decoded(2, vec![3]));
+        assert_eq!(cache.evict_unused(vec!["kept.png"]), 2);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.lookup("a.png", FORMAT).is_none());
+        assert!(cache.lookup("b.png", FORMAT).is_none());
+        assert!(cache.start_loading("a.png", FORMAT));
+        assert!(insert(&mut cache, "a.png", decoded(2, vec![2])));
+    }
+}
@@ -0,0 +1,254 @@
+use crate::figure::FigureMutation;
+use crate::figure::FigureSet;
+use crate::figure::MeshPoint;
+use crate::figure::RenderableMesh;
+use nalgebra::Point3;
+use nalgebra::Vector3;
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+/// Sphere enclosing an `Aabb`, used to frame it from any direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Aabb { min, max }
+    }
+
+    /// Smallest box containing all `points`, `None` when there are none.
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Aabb::new(first, first), |aabb, point| {
+            aabb.union(&Aabb::new(point, point))
+        }))
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    /// The box moved by `mutation`, scaled around the origin and then offset.
+    pub fn transformed(&self, mutation: &FigureMutation) -> Aabb {
+        let scale = mutation.scale;
+        let offset = Vector3::from(mutation.position_offset);
+        let a = Point3::from(self.min.coords * scale + offset);
+        let b = Point3::from(self.max.coords * scale + offset);
+        // A negative scale swaps the corners.
+        Aabb::from_points(vec![a, b]).unwrap()
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere {
+            center: self.center(),
+            radius: self.size().norm() / 2.0,
+        }
+    }
+}
+
+fn points_bounds(points: &[MeshPoint]) -> Option<Aabb> {
+    Aabb::from_points(
+        points
+            .iter()
+            .map(|point| Point3::new(point.vert[0], point.vert[1], point.vert[2])),
+    )
+}
+
+impl RenderableMesh {
+    /// Bounds of the mesh in its own coordinates, `None` for an empty mesh.
+    pub fn bounds(&self) -> Option<Aabb> {
+        match self {
+            RenderableMesh::Indexed(mesh) => points_bounds(&mesh.points),
+            RenderableMesh::Regular(mesh) => points_bounds(&mesh.points),
+        }
+    }
+}
+
+impl FigureSet {
+    /// Bounds of all the instances of the mesh, of the mesh itself without mutations.
+    pub fn bounds(&self) -> Option<Aabb> {
+        let mesh_bounds = self.mesh.bounds()?;
+        if self.mutations.is_empty() {
+            return Some(mesh_bounds);
+        }
+        self.mutations
+            .iter()
+            .map(|mutation| mesh_bounds.transformed(mutation))
+            .fold(None, |bounds: Option<Aabb>, instance| {
+                Some(bounds.map_or(instance, |bounds| bounds.union(&instance)))
+            })
+    }
+}
+
+/// Bounds of all `figures`, `None` when they are all empty.
+pub fn figures_bounds<'a, I: IntoIterator<Item = &'a FigureSet>>(figures: I) -> Option<Aabb> {
+    figures
+        .into_iter()
+        .filter_map(FigureSet::bounds)
+        .fold(None, |bounds: Option<Aabb>, figure| {
+            Some(bounds.map_or(figure, |bounds| bounds.union(&figure)))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::figure::IndexedMesh;
+    use nalgebra::Rotation3;
+
+    const EPSILON: f32 = 1.0e-5;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Point3::new(-1.0, 0.0, 2.0), Point3::new(1.0, 2.0, 4.0))
+    }
+
+    fn corners(aabb: &Aabb) -> Vec<Point3<f32>> {
+        (0..8)
+            .map(|corner: usize| {
+                let (min, max) = (aabb.min, aabb.max);
+                Point3::new(
+                    [min.x, max.x][corner & 1],
+                    [min.y, max.y][(corner >> 1) & 1],
+                    [min.z, max.z][(corner >> 2) & 1],
+                )
+            })
+            .collect()
+    }
+
+    fn figure(points: &[[f32; 3]], mutations: Vec<FigureMutation>) -> FigureSet {
+        let points = points
+            .iter()
+            .map(|vert| MeshPoint::new(*vert, [1.0; 3], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]))
+            .collect();
+        let mesh = RenderableMesh::Indexed(IndexedMesh {
+            points,
+            indices: Vec::new(),
+        });
+        FigureSet::new(mesh, mutations, String::new(), String::new())
+    }
+
+    #[test]
+    fn translation_moves_the_box() {
+        let moved = unit_box().transformed(&FigureMutation::new([1.0, -2.0, 3.0], 1.0));
+        assert_eq!(moved.min, Point3::new(0.0, -2.0, 5.0));
+        assert_eq!(moved.max, Point3::new(2.0, 0.0, 7.0));
+    }
+
+    #[test]
+    fn scale_is_applied_around_the_origin_before_the_offset() {
+        let scaled = unit_box().transformed(&FigureMutation::new([0.0, 1.0, 0.0], 2.0));
+        assert_eq!(scaled.min, Point3::new(-2.0, 1.0, 4.0));
+        assert_eq!(scaled.max, Point3::new(2.0, 5.0, 8.0));
+    }
+
+    #[test]
+    fn negative_scale_swaps_the_corners() {
+        let flipped = unit_box().transformed(&FigureMutation::new([0.0; 3], -1.0));
+        assert_eq!(flipped.min, Point3::new(-1.0, -2.0, -4.0));
+        assert_eq!(flipped.max, Point3::new(1.0, 0.0, -2.0));
+    }
+
+    #[test]
+    fn transformed_matches_the_model_matrix() {
+        let mutation = FigureMutation::new([0.5, -1.0, 2.0], 3.0);
+        let model = mutation.model_matrix();
+        let expected = Aabb::from_points(
+            corners(&unit_box())
+                .iter()
+                .map(|c| model.transform_point(c)),
+        )
+        .unwrap();
+        let transformed = unit_box().transformed(&mutation);
+        assert!((transformed.min - expected.min).norm() < EPSILON);
+        assert!((transformed.max - expected.max).norm() < EPSILON);
+    }
+
+    #[test]
+    fn bounding_sphere_contains_the_rotated_box() {
+        let aabb = unit_box();
+        let sphere = aabb.bounding_sphere();
+        assert_eq!(sphere.center, Point3::new(0.0, 1.0, 3.0));
+        assert!((sphere.radius - 3.0f32.sqrt()).abs() < EPSILON);
+        for (axis, angle) in &[
+            (Vector3::x_axis(), 0.3),
+            (Vector3::y_axis(), 1.2),
+            (Vector3::z_axis(), -2.5),
+        ] {
+            let rotation = Rotation3::from_axis_angle(axis, *angle);
+            for corner in corners(&aabb) {
+                let rotated = sphere.center + rotation * (corner - sphere.center);
+                assert!((rotated - sphere.center).norm() <= sphere.radius + EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn bounding_sphere_scales_with_the_box() {
+        let sphere = unit_box().bounding_sphere();
+        let scaled = unit_box()
+            .transformed(&FigureMutation::new([4.0, 0.0, 0.0], 2.0))
+            .bounding_sphere();
+        assert!((scaled.radius - sphere.radius * 2.0).abs() < EPSILON);
+        assert_eq!(scaled.center, Point3::new(4.0, 2.0, 6.0));
+    }
+
+    #[test]
+    fn figure_bounds_cover_every_mutation() {
+        let figure = figure(
+            &[[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]],
+            vec![
+                FigureMutation::new([-2.0, 0.0, 0.0], 1.0),
+                FigureMutation::new([3.0, 0.0, 0.0], 2.0),
+            ],
+        );
+        let bounds = figure.bounds().unwrap();
+        assert_eq!(bounds.min, Point3::new(-2.0, 0.0, 0.0));
+        assert_eq!(bounds.max, Point3::new(5.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn empty_scene_has_no_bounds() {
+        assert_eq!(figures_bounds(&[]), None);
+        let empty = figure(&[], vec![FigureMutation::unit()]);
+        assert_eq!(figures_bounds(&[empty]), None);
+    }
+
+    #[test]
+    fn scene_bounds_skip_empty_figures() {
+        let figures = vec![
+            figure(&[], Vec::new()),
+            figure(&[[1.0, 2.0, 3.0], [-1.0, 0.0, 0.0]], Vec::new()),
+        ];
+        let bounds = figures_bounds(&figures).unwrap();
+        assert_eq!(bounds.min, Point3::new(-1.0, 0.0, 0.0));
+        assert_eq!(bounds.max, Point3::new(1.0, 2.0, 3.0));
+    }
+}
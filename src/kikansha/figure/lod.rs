@@ -0,0 +1,141 @@
+use crate::figure::simplify::simplify;
+use crate::figure::FigureSet;
+use crate::figure::IndexedMesh;
+use crate::figure::RenderableMesh;
+
+// Each generated level keeps about this share of the triangles of the previous one.
+const LEVEL_TRIANGLE_RATIO: f32 = 0.5;
+// Screen size below which the first generated level is used, halved for each further level.
+const FIRST_LEVEL_SCREEN_SIZE: f32 = 0.25;
+// Generation stops once a level has fewer triangles.
+const MIN_LEVEL_TRIANGLES: usize = 16;
+// Share of a threshold the screen size has to pass it by before the level changes, so that an
+// instance near a threshold doesn't switch levels every frame.
+const LOD_HYSTERESIS: f32 = 0.1;
+
+/// Coarser version of the mesh of a `FigureSet`.
+#[derive(Debug, Clone)]
+pub struct MeshLod {
+    pub mesh: IndexedMesh,
+    /// Used below this projected height of the instance bounds, as a share of the screen height.
+    pub screen_size: f32,
+}
+
+impl MeshLod {
+    pub fn new(mesh: IndexedMesh, screen_size: f32) -> Self {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        MeshLod { mesh, screen_size }
+    }
+}
+
+/// Simplifies `mesh` into up to `levels` coarser meshes, each with about half the triangles of
+/// the previous one.
+///
+/// Generation stops early when a level is too small or can't be simplified further.
+pub fn generate_lods(mesh: &IndexedMesh, levels: usize) -> Vec<MeshLod> {
+    let mut lods: Vec<MeshLod> = Vec::new();
+    let mut screen_size = FIRST_LEVEL_SCREEN_SIZE;
+    for _ in 0..levels {
+        let previous = lods.last().map_or(mesh, |lod| &lod.mesh);
+        if previous.indices.len() / 3 < MIN_LEVEL_TRIANGLES {
+            break;
+        }
+        let simplified = simplify(previous, LEVEL_TRIANGLE_RATIO);
+        if simplified.indices.len() >= previous.indices.len() {
+            break;
+        }
+        lods.push(MeshLod::new(simplified, screen_size));
+        screen_size /= 2.0;
+    }
+    lods
+}
+
+/// Level to draw an instance with at `screen_size`, 0 for the full mesh and `i + 1` for the
+/// level with the screen size `thresholds[i]`, starting from the `current` one.
+///
+/// The thresholds have to decrease.
+pub fn select_level(thresholds: &[f32], current: usize, screen_size: f32) -> usize {
+    let mut level = current.min(thresholds.len());
+    while level < thresholds.len() && screen_size < thresholds[level] * (1.0 - LOD_HYSTERESIS) {
+        level += 1;
+    }
+    while level > 0 && screen_size > thresholds[level - 1] * (1.0 + LOD_HYSTERESIS) {
+        level -= 1;
+    }
+    level
+}
+
+impl FigureSet {
+    /// Replaces the levels of detail, ordered from the finest to the coarsest.
+    pub fn with_lods(mut self, lods: Vec<MeshLod>) -> Self {
+        self.lods = lods;
+        self
+    }
+
+    /// Generates up to `levels` levels of detail from an indexed mesh, see `generate_lods`.
+    /// Regular meshes are left without.
+    pub fn with_generated_lods(self, levels: usize) -> Self {
+        let lods = match &self.mesh {
+            RenderableMesh::Indexed(mesh) => generate_lods(mesh, levels),
+            RenderableMesh::Regular(_) => Vec::new(),
+        };
+        self.with_lods(lods)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::figure::simplify::tests::grid;
+
+    const THRESHOLDS: [f32; 2] = [0.25, 0.125];
+
+    #[test]
+    fn generated_levels_get_coarser() {
+        let mesh = grid(16, 0.0, 1.0);
+        let lods = generate_lods(&mesh, 3);
+        assert_eq!(lods.len(), 3);
+        let mut indices = mesh.indices.len();
+        let mut screen_size = FIRST_LEVEL_SCREEN_SIZE;
+        for lod in &lods {
+            assert!(lod.mesh.indices.len() < indices);
+            assert!((lod.screen_size - screen_size).abs() < f32::EPSILON);
+            indices = lod.mesh.indices.len();
+            screen_size /= 2.0;
+        }
+    }
+
+    #[test]
+    fn generation_stops_at_small_meshes() {
+        assert!(generate_lods(&grid(2, 0.0, 1.0), 4).is_empty());
+        let lods = generate_lods(&grid(16, 0.0, 1.0), 20);
+        assert!(!lods.is_empty());
+        assert!(lods.len() < 20);
+        let before_last = lods.len().checked_sub(2).map(|index| &lods[index]);
+        if let Some(lod) = before_last {
+            assert!(lod.mesh.indices.len() / 3 >= MIN_LEVEL_TRIANGLES);
+        }
+    }
+
+    #[test]
+    fn select_level_follows_the_screen_size() {
+        assert_eq!(select_level(&THRESHOLDS, 0, 1.0), 0);
+        assert_eq!(select_level(&THRESHOLDS, 0, 0.2), 1);
+        assert_eq!(select_level(&THRESHOLDS, 0, 0.01), 2);
+        assert_eq!(select_level(&THRESHOLDS, 2, 1.0), 0);
+        assert_eq!(select_level(&[], 0, 0.01), 0);
+        // Levels that no longer exist are clamped.
+        assert_eq!(select_level(&THRESHOLDS, 5, 0.01), 2);
+    }
+
+    #[test]
+    fn select_level_keeps_the_level_within_the_hysteresis_band() {
+        let band = THRESHOLDS[0] * LOD_HYSTERESIS;
+        // Just below the threshold, but not by enough to switch to the coarser level.
+        assert_eq!(select_level(&THRESHOLDS, 0, THRESHOLDS[0] - band / 2.0), 0);
+        assert_eq!(select_level(&THRESHOLDS, 0, THRESHOLDS[0] - band * 2.0), 1);
+        // Just above it, but not by enough to switch back.
+        assert_eq!(select_level(&THRESHOLDS, 1, THRESHOLDS[0] + band / 2.0), 1);
+        assert_eq!(select_level(&THRESHOLDS, 1, THRESHOLDS[0] + band * 2.0), 0);
+    }
+}
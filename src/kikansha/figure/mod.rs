@@ -1,3 +1,11 @@
+pub mod bounds;
+pub mod lod;
+pub mod simplify;
+
+use crate::figure::lod::MeshLod;
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+
 #[derive(Default, Debug, Clone, Copy)]
 pub struct PerVerexParams {
     pub in_pos: [f32; 4],
@@ -51,6 +59,12 @@ impl FigureMutation {
     pub fn unit() -> Self {
         Self::new([0.0, 0.0, 0.0], 1.0)
     }
+
+    /// Transform of the mutated figure, scaled around the origin and then offset.
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&Vector3::from(self.position_offset))
+            * Matrix4::new_scaling(self.scale)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -104,6 +118,8 @@ pub struct RegularMesh {
 #[derive(Debug, Clone)]
 pub struct FigureSet {
     pub mesh: RenderableMesh,
+    /// Coarser meshes drawn for instances small on screen, see `lod::select_level`.
+    pub lods: Vec<MeshLod>,
     pub mutations: Vec<FigureMutation>,
     pub color_texture_path: String,
     pub normal_texture_path: String,
@@ -119,6 +135,7 @@ impl FigureSet {
         log::trace!("insance of {}",  std::any::type_name::<Self>());
         FigureSet {
             mesh,
+            lods: Vec::new(),
             mutations,
             color_texture_path,
             normal_texture_path,
@@ -0,0 +1,488 @@
+use crate::figure::IndexedMesh;
+use crate::figure::MeshPoint;
+use nalgebra::Vector3;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::BinaryHeap;
+
+// Open edges, including the seams of split vertices, are held by planes along them weighted this
+// much more than the triangles.
+const BORDER_WEIGHT: f64 = 100.0;
+
+/// Error quadric of a vertex, the symmetric 4x4 matrix of Garland and Heckbert stored as its
+/// upper triangle.
+#[derive(Debug, Default, Clone, Copy)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // Squared distance to the plane `normal . p + d = 0` times `weight`.
+    fn from_plane(normal: Vector3<f64>, d: f64, weight: f64) -> Self {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        let mut q = [
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ];
+        for value in q.iter_mut() {
+            *value *= weight;
+        }
+        Quadric(q)
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (q, o) in self.0.iter_mut().zip(other.0.iter()) {
+            *q += o;
+        }
+    }
+
+    fn error(&self, p: &Vector3<f64>) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+/// Candidate collapse of `removed` into `kept`, valid while both vertices have the revisions it
+/// was computed with.
+#[derive(Debug, Clone, Copy)]
+struct Collapse {
+    cost: f64,
+    kept: u32,
+    removed: u32,
+    kept_revision: u32,
+    removed_revision: u32,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Reversed, the heap pops the cheapest collapse first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+fn position(point: &MeshPoint) -> Vector3<f64> {
+    Vector3::new(
+        point.vert[0] as f64,
+        point.vert[1] as f64,
+        point.vert[2] as f64,
+    )
+}
+
+fn corners(triangle: &[u32; 3], positions: &[Vector3<f64>]) -> [Vector3<f64>; 3] {
+    [
+        positions[triangle[0] as usize],
+        positions[triangle[1] as usize],
+        positions[triangle[2] as usize],
+    ]
+}
+
+// Normal scaled by twice the area.
+fn triangle_normal(a: &Vector3<f64>, b: &Vector3<f64>, c: &Vector3<f64>) -> Vector3<f64> {
+    (b - a).cross(&(c - a))
+}
+
+struct Simplifier {
+    positions: Vec<Vector3<f64>>,
+    quadrics: Vec<Quadric>,
+    // Vertex each vertex was collapsed into, itself while it is alive.
+    collapsed_into: Vec<u32>,
+    revisions: Vec<u32>,
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    alive_count: usize,
+    vertex_triangles: Vec<Vec<usize>>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(mesh: &IndexedMesh) -> Self {
+        let positions: Vec<Vector3<f64>> = mesh.points.iter().map(position).collect();
+        let triangles: Vec<[u32; 3]> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .filter(|t| t.iter().all(|&i| (i as usize) < positions.len()))
+            .collect();
+
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut vertex_triangles = vec![Vec::new(); positions.len()];
+        // Ordered, so that the quadrics are summed and the edges queued the same way on every run
+        // and equal cost collapses are taken in the same order.
+        let mut edge_triangles: BTreeMap<(u32, u32), (usize, usize)> = BTreeMap::new();
+        for (index, triangle) in triangles.iter().enumerate() {
+            let [a, b, c] = corners(triangle, &positions);
+            let normal = triangle_normal(&a, &b, &c);
+            let area = normal.norm() / 2.0;
+            if area > 0.0 {
+                let normal = normal.normalize();
+                let plane = Quadric::from_plane(normal, -normal.dot(&a), area);
+                for &vertex in triangle {
+                    quadrics[vertex as usize].add(&plane);
+                }
+            }
+            for (i, &vertex) in triangle.iter().enumerate() {
+                vertex_triangles[vertex as usize].push(index);
+                let next = triangle[(i + 1) % 3];
+                let edge = (vertex.min(next), vertex.max(next));
+                edge_triangles.entry(edge).or_insert((index, 0)).1 += 1;
+            }
+        }
+
+        for (&(a, b), &(triangle, count)) in &edge_triangles {
+            if count != 1 {
+                continue;
+            }
+            let [p0, p1, p2] = corners(&triangles[triangle], &positions);
+            let normal = triangle_normal(&p0, &p1, &p2);
+            let (pa, pb) = (positions[a as usize], positions[b as usize]);
+            let edge = pb - pa;
+            let border_normal = edge.cross(&normal);
+            if border_normal.norm() > 0.0 {
+                let border_normal = border_normal.normalize();
+                let plane = Quadric::from_plane(
+                    border_normal,
+                    -border_normal.dot(&pa),
+                    edge.norm_squared() * BORDER_WEIGHT,
+                );
+                quadrics[a as usize].add(&plane);
+                quadrics[b as usize].add(&plane);
+            }
+        }
+
+        let mut simplifier = Simplifier {
+            collapsed_into: (0..positions.len() as u32).collect(),
+            revisions: vec![0; positions.len()],
+            alive: vec![true; triangles.len()],
+            alive_count: triangles.len(),
+            positions,
+            quadrics,
+            triangles,
+            vertex_triangles,
+            heap: BinaryHeap::new(),
+        };
+        for &(a, b) in edge_triangles.keys() {
+            simplifier.push_edge(a, b);
+        }
+        simplifier
+    }
+
+    // Queues the cheaper direction of collapsing the edge.
+    fn push_edge(&mut self, a: u32, b: u32) {
+        let mut quadric = self.quadrics[a as usize];
+        quadric.add(&self.quadrics[b as usize]);
+        let cost_a = quadric.error(&self.positions[a as usize]);
+        let cost_b = quadric.error(&self.positions[b as usize]);
+        let (kept, removed, cost) = if cost_a <= cost_b {
+            (a, b, cost_a)
+        } else {
+            (b, a, cost_b)
+        };
+        self.heap.push(Collapse {
+            cost,
+            kept,
+            removed,
+            kept_revision: self.revisions[kept as usize],
+            removed_revision: self.revisions[removed as usize],
+        });
+    }
+
+    fn is_current(&self, collapse: &Collapse) -> bool {
+        let (kept, removed) = (collapse.kept as usize, collapse.removed as usize);
+        self.collapsed_into[kept] == collapse.kept
+            && self.collapsed_into[removed] == collapse.removed
+            && self.revisions[kept] == collapse.kept_revision
+            && self.revisions[removed] == collapse.removed_revision
+    }
+
+    // Whether the collapse turns over a triangle that only moves with the removed vertex.
+    fn flips_triangle(&self, collapse: &Collapse) -> bool {
+        let kept_position = self.positions[collapse.kept as usize];
+        self.vertex_triangles[collapse.removed as usize]
+            .iter()
+            .filter(|&&triangle| self.alive[triangle])
+            .map(|&triangle| self.triangles[triangle])
+            .filter(|triangle| !triangle.contains(&collapse.kept))
+            .any(|triangle| {
+                let before = corners(&triangle, &self.positions);
+                let mut after = before;
+                for (i, &vertex) in triangle.iter().enumerate() {
+                    if vertex == collapse.removed {
+                        after[i] = kept_position;
+                    }
+                }
+                let before = triangle_normal(&before[0], &before[1], &before[2]);
+                let after = triangle_normal(&after[0], &after[1], &after[2]);
+                before.dot(&after) <= 0.0
+            })
+    }
+
+    fn collapse(&mut self, collapse: &Collapse) {
+        let (kept, removed) = (collapse.kept, collapse.removed);
+        self.collapsed_into[removed as usize] = kept;
+        let removed_quadric = self.quadrics[removed as usize];
+        self.quadrics[kept as usize].add(&removed_quadric);
+        self.revisions[kept as usize] += 1;
+
+        let moved = std::mem::take(&mut self.vertex_triangles[removed as usize]);
+        let mut kept_triangles = std::mem::take(&mut self.vertex_triangles[kept as usize]);
+        kept_triangles.extend(moved);
+        kept_triangles.sort_unstable();
+        kept_triangles.dedup();
+
+        let mut neighbours = Vec::new();
+        kept_triangles.retain(|&index| {
+            if !self.alive[index] {
+                return false;
+            }
+            let triangle = &mut self.triangles[index];
+            for vertex in triangle.iter_mut() {
+                if *vertex == removed {
+                    *vertex = kept;
+                }
+            }
+            let [a, b, c] = *triangle;
+            if a == b || b == c || a == c {
+                self.alive[index] = false;
+                self.alive_count -= 1;
+                return false;
+            }
+            neighbours.extend(triangle.iter().copied().filter(|&vertex| vertex != kept));
+            true
+        });
+        self.vertex_triangles[kept as usize] = kept_triangles;
+
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for neighbour in neighbours {
+            self.push_edge(kept, neighbour);
+        }
+    }
+
+    fn run(&mut self, target_triangles: usize) {
+        while self.alive_count > target_triangles {
+            let collapse = match self.heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            if self.is_current(&collapse) && !self.flips_triangle(&collapse) {
+                self.collapse(&collapse);
+            }
+        }
+    }
+
+    // The remaining triangles, with the unused vertices left out.
+    fn into_mesh(self, mesh: &IndexedMesh) -> IndexedMesh {
+        let mut remap: Vec<Option<u32>> = vec![None; mesh.points.len()];
+        let mut points = Vec::new();
+        let mut indices = Vec::with_capacity(self.alive_count * 3);
+        for (triangle, _) in self
+            .triangles
+            .iter()
+            .zip(self.alive.iter())
+            .filter(|(_, &alive)| alive)
+        {
+            for &vertex in triangle {
+                let index = *remap[vertex as usize].get_or_insert_with(|| {
+                    points.push(mesh.points[vertex as usize].clone());
+                    (points.len() - 1) as u32
+                });
+                indices.push(index);
+            }
+        }
+        IndexedMesh { points, indices }
+    }
+}
+
+/// Reduces `mesh` to about `ratio` of its triangles by quadric error edge collapses.
+///
+/// Vertices are only removed, the remaining ones keep their positions and attributes. Open edges
+/// are preserved, as are seams where vertices are split, and collapses flipping a triangle are
+/// skipped, so the result may keep more triangles than asked for.
+pub fn simplify(mesh: &IndexedMesh, ratio: f32) -> IndexedMesh {
+    let mut simplifier = Simplifier::new(mesh);
+    let target = (simplifier.alive_count as f32 * ratio.max(0.0).min(1.0)) as usize;
+    simplifier.run(target);
+    simplifier.into_mesh(mesh)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1.0e-5;
+
+    /// Flat grid of `size` by `size` quads in the z = 0 plane, from `x0` to `x1` and from 0 to 1
+    /// in y.
+    pub(crate) fn grid(size: u32, x0: f32, x1: f32) -> IndexedMesh {
+        let mut points = Vec::new();
+        for row in 0..=size {
+            for column in 0..=size {
+                let x = x0 + (x1 - x0) * column as f32 / size as f32;
+                let y = row as f32 / size as f32;
+                points.push(MeshPoint::new(
+                    [x, y, 0.0],
+                    [1.0, 1.0, 1.0],
+                    [0.0, 0.0, 1.0],
+                    [1.0, 0.0, 0.0],
+                ));
+            }
+        }
+        let mut indices = Vec::new();
+        let stride = size + 1;
+        for row in 0..size {
+            for column in 0..size {
+                let corner = row * stride + column;
+                indices.extend_from_slice(&[corner, corner + 1, corner + stride + 1]);
+                indices.extend_from_slice(&[corner, corner + stride + 1, corner + stride]);
+            }
+        }
+        IndexedMesh { points, indices }
+    }
+
+    fn area(mesh: &IndexedMesh) -> f64 {
+        let positions: Vec<Vector3<f64>> = mesh.points.iter().map(position).collect();
+        mesh.indices
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = corners(&[t[0], t[1], t[2]], &positions);
+                triangle_normal(&a, &b, &c).norm() / 2.0
+            })
+            .sum()
+    }
+
+    fn has_point(mesh: &IndexedMesh, vert: [f32; 3]) -> bool {
+        mesh.points.iter().any(|point| point.vert == vert)
+    }
+
+    // Two grids side by side whose vertices along x = 0.5 are split, like a texture seam.
+    fn split_grid() -> IndexedMesh {
+        let left = grid(8, 0.0, 0.5);
+        let right = grid(8, 0.5, 1.0);
+        let offset = left.points.len() as u32;
+        let mut mesh = left;
+        mesh.points.extend(right.points);
+        mesh.indices
+            .extend(right.indices.iter().map(|index| index + offset));
+        mesh
+    }
+
+    #[test]
+    fn reduces_the_triangles() {
+        let mesh = grid(8, 0.0, 1.0);
+        let simplified = simplify(&mesh, 0.25);
+        let triangles = simplified.indices.len() / 3;
+        assert!(triangles <= 32, "{} triangles left", triangles);
+        assert!(triangles > 0);
+        assert!(simplified.points.len() < mesh.points.len());
+        assert!(simplified
+            .indices
+            .iter()
+            .all(|&index| (index as usize) < simplified.points.len()));
+    }
+
+    #[test]
+    fn keeps_everything_with_a_ratio_of_one() {
+        let mesh = grid(4, 0.0, 1.0);
+        let simplified = simplify(&mesh, 1.0);
+        // Vertices are renumbered in the order the triangles use them.
+        let corners = |mesh: &IndexedMesh| -> Vec<[f32; 3]> {
+            mesh.indices
+                .iter()
+                .map(|&index| mesh.points[index as usize].vert)
+                .collect()
+        };
+        assert_eq!(corners(&simplified), corners(&mesh));
+        assert_eq!(simplified.points.len(), mesh.points.len());
+    }
+
+    #[test]
+    fn preserves_the_border() {
+        let simplified = simplify(&grid(8, 0.0, 1.0), 0.1);
+        assert!((area(&simplified) - 1.0).abs() < EPSILON);
+        for corner in [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+        ]
+        .iter()
+        {
+            assert!(has_point(&simplified, *corner), "{:?} was removed", corner);
+        }
+    }
+
+    #[test]
+    fn preserves_seams_of_split_vertices() {
+        let mesh = split_grid();
+        let simplified = simplify(&mesh, 0.25);
+        assert!(simplified.indices.len() < mesh.indices.len());
+        // Each side still reaches the seam, with no gap between them.
+        let (mut left_area, mut right_area) = (0.0, 0.0);
+        let positions: Vec<Vector3<f64>> = simplified.points.iter().map(position).collect();
+        for t in simplified.indices.chunks_exact(3) {
+            let [a, b, c] = corners(&[t[0], t[1], t[2]], &positions);
+            let triangle_area = triangle_normal(&a, &b, &c).norm() / 2.0;
+            if (a.x + b.x + c.x) / 3.0 < 0.5 {
+                left_area += triangle_area;
+            } else {
+                right_area += triangle_area;
+            }
+        }
+        assert!((left_area - 0.5).abs() < EPSILON, "left area {}", left_area);
+        assert!(
+            (right_area - 0.5).abs() < EPSILON,
+            "right area {}",
+            right_area
+        );
+        for y in [0.0, 1.0].iter() {
+            assert!(has_point(&simplified, [0.5, *y, 0.0]));
+        }
+    }
+
+    #[test]
+    fn is_deterministic() {
+        let mesh = split_grid();
+        let first = simplify(&mesh, 0.3);
+        for _ in 0..4 {
+            let again = simplify(&mesh, 0.3);
+            assert_eq!(again.indices, first.indices);
+            let verts = |mesh: &IndexedMesh| -> Vec<[f32; 3]> {
+                mesh.points.iter().map(|point| point.vert).collect()
+            };
+            assert_eq!(verts(&again), verts(&first));
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use crate::debug::labels;
+use crate::engine::cache::{CachedEntities, CachedEntity};
+use crate::engine::error::EngineError;
+use crate::scene::camera::CameraMatrices;
+use crate::scene::frustum::Frustum;
+use std::sync::Arc;
+use vulkano::buffer::cpu_pool::CpuBufferPoolChunk;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::CpuAccessibleBuffer;
+use vulkano::buffer::CpuBufferPool;
+use vulkano::buffer::TypedBufferAccess;
+use vulkano::command_buffer::AutoCommandBuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::DrawIndexedIndirectCommand;
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::device::Queue;
+use vulkano::memory::pool::StdMemoryPool;
+use vulkano::pipeline::ComputePipeline;
+use vulkano::pipeline::ComputePipelineAbstract;
+
+// Invocations per work group, `local_size_x` of the compute shader.
+const GROUP_SIZE: u32 = 64;
+
+pub type IndirectCommandBuffer = CpuBufferPoolChunk<DrawIndexedIndirectCommand, Arc<StdMemoryPool>>;
+pub type VisibleInstanceBuffer = CpuBufferPoolChunk<u32, Arc<StdMemoryPool>>;
+
+/// Instance read by the culling compute pass and the indirect geometry pass, laid out like the
+/// `Instance` struct of `cull.comp`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GpuInstance {
+    pub model: [[f32; 4]; 4],
+    pub bounds_min: [f32; 4],
+    pub bounds_max: [f32; 4],
+    /// Index of the draw command counting the instance.
+    pub draw: u32,
+    // The std430 struct is padded to the alignment of its matrix.
+    _padding: [u32; 3],
+}
+
+/// Draw of an indexed entity whose instances were tested by the culling pass.
+#[derive(Debug, Clone, Copy)]
+pub struct IndirectDraw {
+    /// Position of the entity in `CachedEntities::entities`.
+    pub entity: usize,
+    /// Instances tested, the draw has at most as many.
+    pub tested: usize,
+}
+
+/// Entities drawn indirectly after the culling pass, with one command per draw.
+///
+/// The command of a draw holds the number of its visible instances once the pass has run. Their
+/// indices into `instances` are written to `visible` from the `first_instance` of the command,
+/// which is where the draw reads them from.
+#[derive(Clone)]
+pub struct IndirectDraws {
+    pub instances: Arc<CpuAccessibleBuffer<[GpuInstance]>>,
+    pub visible: Arc<VisibleInstanceBuffer>,
+    pub commands: Arc<IndirectCommandBuffer>,
+    /// In the order of `commands`.
+    pub draws: Vec<IndirectDraw>,
+}
+
+/// Work of the culling pass for a frame.
+pub struct CullingPass {
+    /// Has to be executed before the geometry pass.
+    pub command_buffer: AutoCommandBuffer,
+    pub draws: IndirectDraws,
+}
+
+// Culled entities of a `CachedEntities` revision, with their instances concatenated.
+struct CullingBatch {
+    revision: u64,
+    instances: Arc<CpuAccessibleBuffer<[GpuInstance]>>,
+    // Commands with no visible instance yet, copied into the buffer of every frame.
+    commands: Vec<DrawIndexedIndirectCommand>,
+    draws: Vec<IndirectDraw>,
+}
+
+/// Tests the instances of indexed entities against the view frustum in a compute shader.
+///
+/// Every entity gets an indirect draw command whose instance count is the number of visible
+/// instances, so `TriangleDrawSystem::draw` records a single draw per entity regardless of its
+/// instances. The instances of all entities are tested by a single dispatch, they are
+/// concatenated once per revision of the cached entities. Non indexed entities and entities
+/// with levels of detail, which are selected per instance, are still culled on the CPU.
+/// Occlusion culling against the depth of the previous frame is not done.
+///
+/// Draws start at their offset into the visible instances, the device needs the
+/// `draw_indirect_first_instance` feature.
+pub struct CullingSystem {
+    gfx_queue: Arc<Queue>,
+    pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    // Every frame takes its buffers from the pools, see `TriangleDrawSystem`.
+    uniforms: CpuBufferPool<cs::ty::Frustum>,
+    commands: CpuBufferPool<DrawIndexedIndirectCommand>,
+    visible: CpuBufferPool<u32>,
+    batch: Option<CullingBatch>,
+}
+
+impl CullingSystem {
+    pub fn new(gfx_queue: Arc<Queue>) -> Result<CullingSystem, EngineError> {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        let device = gfx_queue.device().clone();
+        let cs = cs::Shader::load(device.clone())
+            .map_err(|e| EngineError::ShaderLoad("culling compute", e))?;
+        let pipeline = Arc::new(ComputePipeline::new(
+            device.clone(),
+            &cs.main_entry_point(),
+            &(),
+            None,
+        )?);
+        labels::name_object(&*pipeline, "Culling pipeline");
+
+        let uniforms = CpuBufferPool::new(device.clone(), BufferUsage::uniform_buffer());
+        let commands = CpuBufferPool::new(
+            device.clone(),
+            BufferUsage {
+                storage_buffer: true,
+                indirect_buffer: true,
+                ..BufferUsage::none()
+            },
+        );
+        let visible = CpuBufferPool::new(
+            device,
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::none()
+            },
+        );
+
+        Ok(CullingSystem {
+            gfx_queue,
+            pipeline,
+            uniforms,
+            commands,
+            visible,
+            batch: None,
+        })
+    }
+
+    // Concatenates the instances of the indexed entities without levels of detail, `None` if
+    // none of them has an instance with bounds.
+    fn build_batch(&self, cached_scene: &CachedEntities) -> Option<CullingBatch> {
+        let mut instances = Vec::new();
+        let mut commands = Vec::new();
+        let mut draws = Vec::new();
+        for (entity, cached_entity) in cached_scene.entities.iter().enumerate() {
+            let indexed = match cached_entity {
+                CachedEntity::Indexed(i) if i.lods.is_empty() => i,
+                CachedEntity::Indexed(_) => continue,
+                CachedEntity::Regular(_) => continue,
+            };
+            let first_instance = instances.len();
+            let draw = draws.len() as u32;
+            // Instances without bounds are never drawn, they are left out.
+            instances.extend(indexed.instances.iter().filter_map(|instance| {
+                instance.bounds.map(|bounds| GpuInstance {
+                    model: instance.model,
+                    bounds_min: [bounds.min.x, bounds.min.y, bounds.min.z, 1.0],
+                    bounds_max: [bounds.max.x, bounds.max.y, bounds.max.z, 1.0],
+                    draw,
+                    _padding: [0; 3],
+                })
+            }));
+            let tested = instances.len() - first_instance;
+            if tested == 0 {
+                continue;
+            }
+            commands.push(DrawIndexedIndirectCommand {
+                index_count: indexed.indices.len() as u32,
+                instance_count: 0,
+                first_index: 0,
+                vertex_offset: 0,
+                first_instance: first_instance as u32,
+            });
+            draws.push(IndirectDraw { entity, tested });
+        }
+        if draws.is_empty() {
+            return None;
+        }
+        let instances = CpuAccessibleBuffer::from_iter(
+            self.gfx_queue.device().clone(),
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            instances.into_iter(),
+        )
+        .unwrap();
+        labels::name_buffer(&*instances, "Culled instances");
+        Some(CullingBatch {
+            revision: cached_scene.revision,
+            instances,
+            commands,
+            draws,
+        })
+    }
+
+    /// Records the culling of the indexed entities with instances and without levels of detail in
+    /// `cached_scene`, `None` if there are none.
+    pub fn cull(
+        &mut self,
+        matrices: &CameraMatrices,
+        cached_scene: &CachedEntities,
+    ) -> Option<CullingPass> {
+        let stale = match &self.batch {
+            Some(batch) => batch.revision != cached_scene.revision,
+            None => true,
+        };
+        if stale {
+            self.batch = self.build_batch(cached_scene);
+        }
+        let batch = self.batch.as_ref()?;
+
+        let frustum = Frustum::from_matrices(matrices);
+        let mut planes = [[0.0; 4]; 6];
+        for (slot, plane) in planes.iter_mut().zip(frustum.planes()) {
+            *slot = [plane.x, plane.y, plane.z, plane.w];
+        }
+        let uniforms = self.uniforms.next(cs::ty::Frustum { planes }).unwrap();
+        let commands = Arc::new(self.commands.chunk(batch.commands.iter().cloned()).unwrap());
+        let tested = batch.instances.len();
+        let visible = Arc::new(self.visible.chunk((0..tested).map(|_| 0)).unwrap());
+
+        let layout = self.pipeline.descriptor_set_layout(0).unwrap();
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout)
+                .add_buffer(uniforms)
+                .unwrap()
+                .add_buffer(batch.instances.clone())
+                .unwrap()
+                .add_buffer(visible.clone())
+                .unwrap()
+                .add_buffer(commands.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            self.gfx_queue.device().clone(),
+            self.gfx_queue.family(),
+        )
+        .unwrap();
+        let groups = (tested as u32 + GROUP_SIZE - 1) / GROUP_SIZE;
+        builder
+            .dispatch([groups, 1, 1], self.pipeline.clone(), set, ())
+            .unwrap();
+
+        Some(CullingPass {
+            command_buffer: builder.build().unwrap(),
+            draws: IndirectDraws {
+                instances: batch.instances.clone(),
+                visible,
+                commands,
+                draws: batch.draws.clone(),
+            },
+        })
+    }
+}
+
+mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/kikansha/frame/shaders/cull.comp"
+    }
+}
@@ -1,8 +1,12 @@
+use crate::debug::labels;
+use crate::debug::profiler::TimestampPoint;
 use crate::engine::cache::CachedEntities;
 use crate::figure::PerVerexParams;
+use crate::frame::geometry::CullingStats;
 use crate::frame::system::FrameSystem;
 use crate::scene::camera::CameraMatrices;
 use crate::scene::lights::Light;
+use std::ffi::CStr;
 use std::sync::Arc;
 use vulkano::command_buffer::AutoCommandBufferBuilder;
 use vulkano::command_buffer::CommandBuffer;
@@ -11,10 +15,14 @@ use vulkano::command_buffer::SubpassContents;
 use vulkano::descriptor::PipelineLayoutAbstract;
 use vulkano::framebuffer::FramebufferAbstract;
 use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::framebuffer::Subpass;
 use vulkano::pipeline::vertex::SingleBufferDefinition;
 use vulkano::pipeline::GraphicsPipeline;
 use vulkano::sync::GpuFuture;
 
+const DEFERRED_LABEL: &[u8] = b"Deferred pass\0";
+const LIGHTING_LABEL: &[u8] = b"Lighting pass\0";
+
 pub type ConcreteGraphicsPipeline = GraphicsPipeline<
     SingleBufferDefinition<PerVerexParams>,
     Box<dyn PipelineLayoutAbstract + Send + Sync + 'static>,
@@ -49,6 +57,10 @@ pub struct Frame<'a> {
     pub lights: Vec<Light>,
     pub cached_scene: CachedEntities,
     pub dynamic_state: DynamicState,
+    // Draw calls recorded by the passes, reported by the GPU profiler.
+    pub draw_calls: usize,
+    // Instances drawn and skipped by the geometry pass, reported by the GPU profiler.
+    pub culling: CullingStats,
 }
 
 impl<'a> Frame<'a> {
@@ -66,17 +78,21 @@ impl<'a> Frame<'a> {
                 // We already called `begin_render_pass` (in the `frame()` method), and that's the
                 // state we are in.
                 // We return an object that will allow the user to draw objects on the scene.
+                self.begin_label(DEFERRED_LABEL, [0.2, 0.6, 1.0, 1.0]);
                 Some(Pass::Deferred(DrawPass { frame: self }))
             }
 
             1 => {
                 // If we are in pass 1 then we have finished drawing the objects on the scene.
                 // Going to the next subpass.
+                self.write_timestamp(TimestampPoint::GeometryEnd, Some(0));
+                self.end_label();
                 self.command_buffer_builder
                     .as_mut()
                     .unwrap()
                     .next_subpass(SubpassContents::SecondaryCommandBuffers)
                     .unwrap();
+                self.begin_label(LIGHTING_LABEL, [1.0, 0.8, 0.2, 1.0]);
 
                 // And returning an object that will allow the user to apply lighting to the scene.
                 Some(Pass::Lighting(LightingPass { frame: self }))
@@ -86,11 +102,17 @@ impl<'a> Frame<'a> {
                 // If we are in pass 2 then we have finished applying lighting.
                 // We take the builder, call `end_render_pass()`, and then `build()` it to obtain
                 // an actual command buffer.
+                self.write_timestamp(TimestampPoint::LightingEnd, Some(1));
+                self.end_label();
                 self.command_buffer_builder
                     .as_mut()
                     .unwrap()
                     .end_render_pass()
                     .unwrap();
+                self.write_timestamp(TimestampPoint::FrameEnd, None);
+                if let Some(profiler) = self.system.profiler.as_mut() {
+                    profiler.end_frame(self.draw_calls, self.culling.visible, self.culling.culled);
+                }
                 let command_buffer = self.command_buffer_builder.take().unwrap().build().unwrap();
 
                 // Extract `before_main_cb_future` and append the command buffer execution to it.
@@ -109,6 +131,43 @@ impl<'a> Frame<'a> {
             _ => None,
         }
     }
+
+    // Labels are opened and closed within the same subpass, as required by `ext_debug_utils`.
+    fn begin_label(&mut self, name: &'static [u8], color: [f32; 4]) {
+        let name = CStr::from_bytes_with_nul(name).unwrap();
+        let device = self.system.gfx_queue.device().clone();
+        labels::begin_label(
+            &device,
+            self.command_buffer_builder.as_mut().unwrap(),
+            name,
+            color,
+        );
+    }
+
+    fn end_label(&mut self) {
+        let device = self.system.gfx_queue.device().clone();
+        labels::end_label(&device, self.command_buffer_builder.as_mut().unwrap());
+    }
+
+    // Executes the profiler timestamp in `subpass`, or outside of the render pass for `None`.
+    fn write_timestamp(&mut self, point: TimestampPoint, subpass: Option<u32>) {
+        let render_pass = self.system.render_pass.clone();
+        let command_buffer = self.system.profiler.as_ref().and_then(|profiler| {
+            profiler.timestamp(
+                point,
+                subpass.map(|index| Subpass::from(render_pass, index).unwrap()),
+            )
+        });
+        if let Some(command_buffer) = command_buffer {
+            unsafe {
+                self.command_buffer_builder
+                    .as_mut()
+                    .unwrap()
+                    .execute_commands(command_buffer)
+                    .unwrap();
+            }
+        }
+    }
 }
 
 /// Struct provided to the user that allows them to customize or handle the pass.
@@ -132,6 +191,19 @@ pub struct DrawPass<'f, 's: 'f> {
 }
 
 impl<'f, 's: 'f> DrawPass<'f, 's> {
+    /// Counts draw calls recorded in the executed command buffers for the profiler.
+    #[inline]
+    pub fn add_draw_calls(&mut self, count: usize) {
+        self.frame.draw_calls += count;
+    }
+
+    /// Counts instances drawn and culled by the executed command buffers for the profiler.
+    #[inline]
+    pub fn add_culling_stats(&mut self, stats: CullingStats) {
+        self.frame.culling.visible += stats.visible;
+        self.frame.culling.culled += stats.culled;
+    }
+
     /// Appends a command that executes a secondary command buffer that performs drawing.
     #[inline]
     pub fn execute<C>(&mut self, command_buffer: C)
@@ -182,9 +254,7 @@ impl<'f, 's: 'f> LightingPass<'f, 's> {
                     // self.frame.system.depth_buffer.clone(),
                     &self.frame.lights,
                     &self.frame.matrices,
-                    &self.frame.cached_scene,
                     &self.frame.dynamic_state,
-                    false,
                     color_debug_level,
                 )
             };
@@ -196,5 +266,6 @@ impl<'f, 's: 'f> LightingPass<'f, 's> {
                 .execute_commands(command_buffer)
                 .unwrap();
         }
+        self.frame.draw_calls += 1;
     }
 }
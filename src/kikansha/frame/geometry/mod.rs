@@ -1,49 +1,96 @@
-use crate::engine::cache::empty_texture;
-use crate::engine::cache::{CachedEntities, CachedEntity};
+use crate::debug::labels;
+use crate::engine::cache::{CachedEntities, CachedEntity, CachedIndexedEntity};
+use crate::engine::error::EngineError;
+use crate::figure::bounds::Aabb;
+use crate::figure::lod::select_level;
+use crate::figure::PerVerexParams;
+use crate::frame::culling::IndirectDraws;
 use crate::frame::frame::ConcreteGraphicsPipeline;
 use crate::scene::camera::CameraMatrices;
+use crate::scene::frustum::Frustum;
+use nalgebra::Matrix4;
 use std::sync::Arc;
+use vulkano::buffer::BufferSlice;
 use vulkano::buffer::BufferUsage;
-use vulkano::buffer::CpuAccessibleBuffer;
+use vulkano::buffer::CpuBufferPool;
+use vulkano::buffer::ImmutableBuffer;
 use vulkano::command_buffer::AutoCommandBuffer;
 use vulkano::command_buffer::AutoCommandBufferBuilder;
 use vulkano::command_buffer::DynamicState;
 use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
-use vulkano::descriptor::descriptor_set::PersistentDescriptorSetBuf;
-use vulkano::descriptor::descriptor_set::PersistentDescriptorSetImg;
-use vulkano::descriptor::descriptor_set::PersistentDescriptorSetSampler;
+use vulkano::descriptor::DescriptorSet;
 use vulkano::device::Queue;
-use vulkano::format::Format;
 use vulkano::framebuffer::RenderPassAbstract;
 use vulkano::framebuffer::Subpass;
-use vulkano::image::ImmutableImage;
+use vulkano::pipeline::depth_stencil::{Compare, DepthStencil};
 use vulkano::pipeline::GraphicsPipeline;
 use vulkano::pipeline::GraphicsPipelineAbstract;
 use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
 
-type PDS = PersistentDescriptorSet<(
-    (
-        (
-            (
-                (
-                    (),
-                    PersistentDescriptorSetBuf<Arc<CpuAccessibleBuffer<vs::ty::UBO>>>,
-                ),
-                PersistentDescriptorSetImg<Arc<ImmutableImage<Format>>>,
-            ),
-            PersistentDescriptorSetSampler,
-        ),
-        PersistentDescriptorSetImg<Arc<ImmutableImage<Format>>>,
-    ),
-    PersistentDescriptorSetSampler,
-)>;
+// Bounds closer to the camera plane are treated as filling the screen.
+const MIN_CLIP_W: f32 = 1.0e-6;
+
+/// Projected height of `bounds` as a share of the screen height, from the size of their bounding
+/// sphere.
+///
+/// `vertical_scale` is the second diagonal element of the projection, which maps view space
+/// heights to clip space for both the perspective and the orthographic projection.
+fn screen_size(view_projection: &Matrix4<f32>, vertical_scale: f32, bounds: &Aabb) -> f32 {
+    let sphere = bounds.bounding_sphere();
+    let w = (view_projection * sphere.center.to_homogeneous()).w;
+    if w < MIN_CLIP_W {
+        return f32::INFINITY;
+    }
+    // The diameter over the clip space height of 2.
+    sphere.radius * vertical_scale.abs() / w
+}
+
+// Buffers of the level of detail `instance` of `entity` is drawn with at `screen_size`, the level
+// of the instance is updated.
+fn lod_buffers(
+    entity: &CachedIndexedEntity,
+    thresholds: &[f32],
+    instance: usize,
+    screen_size: f32,
+) -> (
+    Arc<ImmutableBuffer<[PerVerexParams]>>,
+    Arc<ImmutableBuffer<[u32]>>,
+) {
+    let mut levels = entity.lod_levels.lock().unwrap();
+    let level = match levels.get_mut(instance) {
+        Some(level) => {
+            *level = select_level(thresholds, *level, screen_size);
+            *level
+        }
+        None => select_level(thresholds, 0, screen_size),
+    };
+    match level.checked_sub(1).and_then(|lod| entity.lods.get(lod)) {
+        Some(lod) => (lod.vert_params.clone(), lod.indices.clone()),
+        None => (entity.vert_params.clone(), entity.indices.clone()),
+    }
+}
+
+/// Instances drawn and skipped by `TriangleDrawSystem::draw` in a frame.
+///
+/// Instances culled on the GPU are only counted in `gpu_tested`, how many of them were visible
+/// is not read back.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CullingStats {
+    pub visible: usize,
+    pub culled: usize,
+    pub gpu_tested: usize,
+}
 
 pub struct TriangleDrawSystem {
     gfx_queue: Arc<Queue>,
     pipeline: Arc<ConcreteGraphicsPipeline>,
+    // Draws the instances left by `CullingSystem`, which come from storage buffers.
+    indirect_pipeline: Arc<ConcreteGraphicsPipeline>,
     default_sampler: Arc<Sampler>,
-    buff: Arc<CpuAccessibleBuffer<vs::ty::UBO>>,
-    set: Arc<PDS>,
+    // Every frame takes its own uniform buffer from the pool, so a frame still in flight is
+    // never overwritten. Model matrices are push constants.
+    uniforms: CpuBufferPool<vs::ty::UBO>,
+    indirect_uniforms: CpuBufferPool<indirect_vs::ty::UBO>,
 }
 
 impl TriangleDrawSystem {
@@ -51,31 +98,51 @@ impl TriangleDrawSystem {
     pub fn new(
         gfx_queue: Arc<Queue>,
         subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync + 'static>>,
-        format: Format,
-    ) -> TriangleDrawSystem {
+    ) -> Result<TriangleDrawSystem, EngineError> {
         log::trace!("insance of {}", std::any::type_name::<Self>());
-        let pipeline = {
-            let vs = vs::Shader::load(gfx_queue.device().clone())
-                .expect("failed to create shader module");
-            let fs = fs::Shader::load(gfx_queue.device().clone())
-                .expect("failed to create shader module");
-
-            Arc::new(
-                GraphicsPipeline::start()
-                    .vertex_input_single_buffer()
-                    .vertex_shader(vs.main_entry_point(), ())
-                    .triangle_list()
-                    .cull_mode_back()
-                    .front_face_clockwise()
-                    .depth_write(true)
-                    .viewports_dynamic_scissors_irrelevant(1)
-                    .fragment_shader(fs.main_entry_point(), ())
-                    .depth_stencil_simple_depth()
-                    .render_pass(subpass)
-                    .build(gfx_queue.device().clone())
-                    .unwrap(),
-            )
-        };
+        let vs = vs::Shader::load(gfx_queue.device().clone())
+            .map_err(|e| EngineError::ShaderLoad("geometry vertex", e))?;
+        let indirect_vs = indirect_vs::Shader::load(gfx_queue.device().clone())
+            .map_err(|e| EngineError::ShaderLoad("indirect geometry vertex", e))?;
+        let fs = fs::Shader::load(gfx_queue.device().clone())
+            .map_err(|e| EngineError::ShaderLoad("geometry fragment", e))?;
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer()
+                .vertex_shader(vs.main_entry_point(), ())
+                .triangle_list()
+                .cull_mode_back()
+                .front_face_clockwise()
+                .depth_write(true)
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                // The depth is reversed, nearer fragments have a greater depth.
+                .depth_stencil(DepthStencil {
+                    depth_compare: Compare::Greater,
+                    ..DepthStencil::simple_depth_test()
+                })
+                .render_pass(subpass.clone())
+                .build(gfx_queue.device().clone())?,
+        );
+        labels::name_object(&*pipeline, "Geometry pipeline");
+        let indirect_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer()
+                .vertex_shader(indirect_vs.main_entry_point(), ())
+                .triangle_list()
+                .cull_mode_back()
+                .front_face_clockwise()
+                .depth_write(true)
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .depth_stencil(DepthStencil {
+                    depth_compare: Compare::Greater,
+                    ..DepthStencil::simple_depth_test()
+                })
+                .render_pass(subpass)
+                .build(gfx_queue.device().clone())?,
+        );
+        labels::name_object(&*indirect_pipeline, "Indirect geometry pipeline");
 
         let default_sampler = Sampler::new(
             pipeline.device().clone(),
@@ -89,61 +156,57 @@ impl TriangleDrawSystem {
             1.0,
             0.0,
             1.0,
-        )
-        .unwrap();
+        )?;
 
-        let push_constants = vs::ty::UBO {
-            projection: CameraMatrices::emmpty(),
-            model: [
-                [1.0, 0.0, 0.0, 0.0],
-                [0.0, 1.0, 0.0, 0.0],
-                [0.0, 0.0, 1.0, 0.0],
-                [0.0, 0.0, 0.0, 1.0],
-            ],
-            view: CameraMatrices::emmpty(),
-            instancePos: [0.0, 0.0, 0.0, 0.0],
-            // [-4.0, 0.0, -4.0, 0.0],
-            // [4.0, 0.0, -4.0, 0.0],
-        };
-
-        let buff = CpuAccessibleBuffer::from_data(
-            pipeline.device().clone(),
-            BufferUsage::all(),
-            false,
-            push_constants,
-        )
-        .unwrap();
+        let uniforms = CpuBufferPool::new(pipeline.device().clone(), BufferUsage::uniform_buffer());
+        let indirect_uniforms =
+            CpuBufferPool::new(pipeline.device().clone(), BufferUsage::uniform_buffer());
 
-        let layout = pipeline.layout().descriptor_set_layout(0).unwrap();
-        let texture = empty_texture(format, gfx_queue.clone());
-        let set = Arc::new(
-            PersistentDescriptorSet::start(layout.clone())
-                .add_buffer(buff.clone())
-                .unwrap()
-                .add_sampled_image(texture.clone(), default_sampler.clone())
-                .unwrap()
-                .add_sampled_image(texture.clone(), default_sampler.clone())
-                .unwrap()
-                .build()
-                .unwrap(),
-        );
-
-        TriangleDrawSystem {
+        Ok(TriangleDrawSystem {
             gfx_queue,
             pipeline,
+            indirect_pipeline,
             default_sampler,
-            buff,
-            set,
-        }
+            uniforms,
+            indirect_uniforms,
+        })
+    }
+
+    // Second descriptor set of both pipelines, built once for the textures of `entity` and kept
+    // with it.
+    fn texture_set(&self, entity: &CachedEntity) -> Arc<dyn DescriptorSet + Send + Sync> {
+        let (color, normal, texture_set) = entity.textures();
+        texture_set.get_or_build(|| {
+            let layout = self.pipeline.layout().descriptor_set_layout(1).unwrap();
+            Arc::new(
+                PersistentDescriptorSet::start(layout.clone())
+                    .add_sampled_image(color.clone(), self.default_sampler.clone())
+                    .unwrap()
+                    .add_sampled_image(normal.clone(), self.default_sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            )
+        })
     }
 
-    /// Builds a secondary command buffer that draws the triangle on the current subpass.
+    /// Builds a secondary command buffer that draws the instances of the cached entities in
+    /// view on the current subpass.
+    ///
+    /// Instances whose bounds are outside of the view frustum are skipped, how many were drawn
+    /// and skipped is returned with the command buffer. Indexed entities with levels of detail
+    /// are drawn with the level matching the screen size of each instance.
+    ///
+    /// The entities of `indirect_draws` were culled by `CullingSystem`, they are drawn with one
+    /// indirect draw each instead, all of them sharing a descriptor set. The culling pass has to
+    /// be executed before this command buffer.
     pub fn draw(
         &self,
         matrices_buff: &CameraMatrices,
         cached_scene: &CachedEntities,
+        indirect_draws: Option<&IndirectDraws>,
         dynamic_state: &DynamicState,
-    ) -> AutoCommandBuffer {
+    ) -> (AutoCommandBuffer, CullingStats) {
         let mut builder = AutoCommandBufferBuilder::secondary_graphics(
             self.gfx_queue.device().clone(),
             self.gfx_queue.family(),
@@ -151,47 +214,139 @@ impl TriangleDrawSystem {
         )
         .unwrap();
 
-        {
-            let mut content = self.buff.write().unwrap();
-            content.projection = matrices_buff.alligned_projection_matrix();
-            content.view = matrices_buff.alligned_view_matrix();
+        // Shared by every instance drawn on the CPU path, the model matrix is a push constant and
+        // the textures are bound per entity.
+        let layout = self.pipeline.layout().descriptor_set_layout(0).unwrap();
+        let uniforms = self
+            .uniforms
+            .next(vs::ty::UBO {
+                projection: matrices_buff.alligned_projection_matrix(),
+                view: matrices_buff.alligned_view_matrix(),
+            })
+            .unwrap();
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout)
+                .add_buffer(uniforms)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let mut stats = CullingStats::default();
+        // Entities drawn indirectly, skipped by the CPU path.
+        let mut gpu_culled = vec![false; cached_scene.entities.len()];
+        if let Some(indirect) = indirect_draws {
+            let uniforms = self
+                .indirect_uniforms
+                .next(indirect_vs::ty::UBO {
+                    projection: matrices_buff.alligned_projection_matrix(),
+                    view: matrices_buff.alligned_view_matrix(),
+                })
+                .unwrap();
+            let layout = self
+                .indirect_pipeline
+                .layout()
+                .descriptor_set_layout(0)
+                .unwrap();
+            let set = Arc::new(
+                PersistentDescriptorSet::start(layout)
+                    .add_buffer(uniforms)
+                    .unwrap()
+                    .add_buffer(indirect.instances.clone())
+                    .unwrap()
+                    .add_buffer(indirect.visible.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+            for (index, draw) in indirect.draws.iter().enumerate() {
+                let cached_entity = &cached_scene.entities[draw.entity];
+                let entity = match cached_entity {
+                    CachedEntity::Indexed(i) => i,
+                    CachedEntity::Regular(_) => continue,
+                };
+                gpu_culled[draw.entity] = true;
+                let command = BufferSlice::from_typed_buffer_access(indirect.commands.clone())
+                    .slice(index..index + 1)
+                    .unwrap();
+                builder
+                    .draw_indexed_indirect(
+                        self.indirect_pipeline.clone(),
+                        dynamic_state,
+                        entity.vert_params.clone(),
+                        entity.indices.clone(),
+                        command,
+                        (set.clone(), self.texture_set(cached_entity)),
+                        (),
+                    )
+                    .unwrap();
+                stats.gpu_tested += draw.tested;
+            }
         }
 
-        for cached_entity in cached_scene.entities.clone() {
-            match cached_entity {
-                CachedEntity::Regular(r) => {
-                    // for _mutation in r.mutations {
-
-                    builder
-                        .draw(
-                            self.pipeline.clone(),
-                            dynamic_state,
-                            r.vert_params.clone(),
-                            self.set.clone(),
-                            (),
-                        )
-                        .unwrap();
-                    // }
+        let frustum = Frustum::from_matrices(matrices_buff);
+        let projection = Matrix4::from_column_slice(&matrices_buff.projection_matrix);
+        let view_projection = projection * Matrix4::from_column_slice(&matrices_buff.view_matrix);
+        for (index, cached_entity) in cached_scene.entities.iter().enumerate() {
+            if gpu_culled[index] {
+                continue;
+            }
+            let thresholds: Vec<f32> = match cached_entity {
+                CachedEntity::Indexed(i) => i.lods.iter().map(|lod| lod.screen_size).collect(),
+                CachedEntity::Regular(_) => Vec::new(),
+            };
+            let sets = (set.clone(), self.texture_set(cached_entity));
+            for (instance_index, instance) in cached_entity.instances().iter().enumerate() {
+                let visible = match &instance.bounds {
+                    Some(bounds) => frustum.intersects_aabb(bounds),
+                    None => false,
+                };
+                if !visible {
+                    stats.culled += 1;
+                    continue;
                 }
-                CachedEntity::Indexed(i) => {
-                    // for _mutation in i.mutations {
-
-                    builder
-                        .draw_indexed(
-                            self.pipeline.clone(),
-                            dynamic_state,
-                            i.vert_params.clone(),
-                            i.indices.clone(),
-                            self.set.clone(),
-                            (),
-                        )
-                        .unwrap();
-                    // }
+                stats.visible += 1;
+
+                let push_constants = vs::ty::Instance {
+                    model: instance.model,
+                };
+                match cached_entity {
+                    CachedEntity::Regular(r) => {
+                        builder
+                            .draw(
+                                self.pipeline.clone(),
+                                dynamic_state,
+                                r.vert_params.clone(),
+                                sets.clone(),
+                                push_constants,
+                            )
+                            .unwrap();
+                    }
+                    CachedEntity::Indexed(i) => {
+                        let (vert_params, indices) = match &instance.bounds {
+                            Some(bounds) if !thresholds.is_empty() => {
+                                let size =
+                                    screen_size(&view_projection, projection[(1, 1)], bounds);
+                                lod_buffers(i, &thresholds, instance_index, size)
+                            }
+                            _ => (i.vert_params.clone(), i.indices.clone()),
+                        };
+                        builder
+                            .draw_indexed(
+                                self.pipeline.clone(),
+                                dynamic_state,
+                                vert_params,
+                                indices,
+                                sets.clone(),
+                                push_constants,
+                            )
+                            .unwrap();
+                    }
                 }
             }
         }
 
-        builder.build().unwrap()
+        (builder.build().unwrap(), stats)
     }
 }
 
@@ -202,6 +357,13 @@ mod vs {
     }
 }
 
+mod indirect_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/kikansha/frame/shaders/geomerty_culled.vert"
+    }
+}
+
 mod fs {
     vulkano_shaders::shader! {
         ty: "fragment",
@@ -1,3 +1,4 @@
+pub mod culling;
 pub mod geometry;
 pub mod lightning;
 pub mod system;
@@ -2,13 +2,16 @@ use std::sync::Arc;
 use vulkano::device::Queue;
 use vulkano::format::ClearValue;
 use vulkano::format::Format;
+use vulkano::format::FormatTy;
 use vulkano::framebuffer::RenderPass;
 use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::framebuffer::RenderPassCreationError;
 use vulkano::framebuffer::{
     AttachmentDescription, LoadOp, PassDependencyDescription, PassDescription, RenderPassDesc,
     RenderPassDescClearValues, StoreOp,
 };
 use vulkano::image::ImageLayout;
+use vulkano::instance::PhysicalDevice;
 use vulkano::sync::AccessFlagBits;
 use vulkano::sync::PipelineStages;
 
@@ -67,10 +70,41 @@ unsafe impl RenderPassDescClearValues<Vec<ClearValue>> for DefaultRenderPassDesc
     }
 }
 
+// Depth formats by preference. The reversed depth keeps its precision far away only with a
+// floating point format.
+const DEPTH_FORMATS: &[Format] = &[
+    Format::D32Sfloat,
+    Format::D32Sfloat_S8Uint,
+    Format::D24Unorm_S8Uint,
+    Format::D16Unorm,
+];
+
+/// Most precise depth format the device can render to and sample, `D16Unorm` is always
+/// supported.
+pub fn supported_depth_format(physical_device: PhysicalDevice) -> Format {
+    DEPTH_FORMATS
+        .iter()
+        .copied()
+        .find(|format| {
+            let features = format.properties(physical_device).optimal_tiling_features;
+            features.depth_stencil_attachment && features.sampled_image
+        })
+        .unwrap_or(Format::D16Unorm)
+}
+
+/// Clear value of the depth attachment, the far plane is at 0 with the reversed depth.
+pub fn depth_clear_value(depth_format: Format) -> ClearValue {
+    match depth_format.ty() {
+        FormatTy::DepthStencil => ClearValue::DepthStencil((0.0, 0)),
+        _ => ClearValue::Depth(0.0),
+    }
+}
+
 pub fn build_render_pass(
     gfx_queue: &Arc<Queue>,
     final_output_format: Format,
-) -> Arc<dyn RenderPassAbstract + Send + Sync> {
+    depth_format: Format,
+) -> Result<Arc<dyn RenderPassAbstract + Send + Sync>, RenderPassCreationError> {
     let render_pass_description = {
         let mut attachments = Vec::new();
 
@@ -124,7 +158,7 @@ pub fn build_render_pass(
 
         // 4: Depth
         attachments.push(AttachmentDescription {
-            format: Format::D16Unorm,
+            format: depth_format,
             samples: 1,
             load: LoadOp::Clear,
             store: StoreOp::Store,
@@ -240,5 +274,8 @@ pub fn build_render_pass(
         }
     };
 
-    Arc::new(RenderPass::new(gfx_queue.device().clone(), render_pass_description).unwrap())
+    Ok(Arc::new(RenderPass::new(
+        gfx_queue.device().clone(),
+        render_pass_description,
+    )?))
 }
@@ -9,11 +9,15 @@ layout (location = 4) in vec3 in_tangent;
 layout (binding = 0) uniform UBO
 {
 	mat4 projection;
-	mat4 model;
 	mat4 view;
-	vec4 instancePos;
 } ubo;
 
+// Pushed for every drawn instance.
+layout (push_constant) uniform Instance
+{
+	mat4 model;
+} instance;
+
 layout (location = 0) out vec3 outNormal;
 layout (location = 1) out vec2 outUV;
 layout (location = 2) out vec3 outColor;
@@ -22,17 +26,15 @@ layout (location = 4) out vec3 outTangent;
 
 void main()
 {
-	vec4 tmpPos = in_pos + ubo.instancePos;
-
-	gl_Position = ubo.projection * ubo.view * ubo.model * tmpPos;
+	gl_Position = ubo.projection * ubo.view * instance.model * in_pos;
 
 	outUV = in_uv;
 
 	// Vertex position in world space
-	outWorldPos = vec3(ubo.model * tmpPos);
+	outWorldPos = vec3(instance.model * in_pos);
 
 	// Normal in world space
-	mat3 mNormal = transpose(inverse(mat3(ubo.model)));
+	mat3 mNormal = transpose(inverse(mat3(instance.model)));
 	outNormal = mNormal * normalize(in_normal);
 	outTangent = mNormal * normalize(in_tangent);
 
@@ -0,0 +1,59 @@
+#version 450
+
+layout (location = 0) in vec4 in_pos;
+layout (location = 1) in vec2 in_uv;
+layout (location = 2) in vec3 in_color;
+layout (location = 3) in vec3 in_normal;
+layout (location = 4) in vec3 in_tangent;
+
+struct Instance
+{
+	mat4 model;
+	vec4 boundsMin;
+	vec4 boundsMax;
+	uint draw;
+};
+
+layout (binding = 0) uniform UBO
+{
+	mat4 projection;
+	mat4 view;
+} ubo;
+
+layout (std430, binding = 1) readonly buffer Instances
+{
+	Instance instances[];
+};
+
+// Indices of the instances that passed `cull.comp`, one per drawn instance. The instance index
+// starts at the first instance of the draw, where its range begins.
+layout (std430, binding = 2) readonly buffer Visible
+{
+	uint visible[];
+};
+
+layout (location = 0) out vec3 outNormal;
+layout (location = 1) out vec2 outUV;
+layout (location = 2) out vec3 outColor;
+layout (location = 3) out vec3 outWorldPos;
+layout (location = 4) out vec3 outTangent;
+
+void main()
+{
+	mat4 model = instances[visible[gl_InstanceIndex]].model;
+
+	gl_Position = ubo.projection * ubo.view * model * in_pos;
+
+	outUV = in_uv;
+
+	// Vertex position in world space
+	outWorldPos = vec3(model * in_pos);
+
+	// Normal in world space
+	mat3 mNormal = transpose(inverse(mat3(model)));
+	outNormal = mNormal * normalize(in_normal);
+	outTangent = mNormal * normalize(in_tangent);
+
+	// Currently just vertex color
+	outColor = in_color;
+}
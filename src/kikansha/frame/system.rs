@@ -1,7 +1,13 @@
+use crate::debug::labels;
+use crate::debug::profiler::GpuProfiler;
 use crate::engine::cache::CachedEntities;
+use crate::engine::error::EngineError;
 use crate::frame::frame::Frame;
+use crate::frame::geometry::CullingStats;
 use crate::frame::lightning::LightingSystem;
 use crate::frame::rendering::build_render_pass;
+use crate::frame::rendering::depth_clear_value;
+use crate::frame::rendering::supported_depth_format;
 use crate::scene::camera::CameraMatrices;
 use crate::scene::lights::Light;
 use std::sync::Arc;
@@ -34,9 +40,14 @@ pub struct FrameSystem {
     pub normals_buffer: Arc<AttachmentImage>,
     pub albedo_buffer: Arc<AttachmentImage>,
     pub depth_buffer: Arc<AttachmentImage>,
+    // Depth is reversed, 1 at the near plane and 0 at infinity.
+    pub depth_format: Format,
 
     // Will allow us to add an lighting to a scene during the second subpass.
     pub lighting_system: LightingSystem,
+
+    // Writes timestamps at the pass boundaries when enabled.
+    pub profiler: Option<GpuProfiler>,
 }
 
 type FrameState = (
@@ -59,14 +70,14 @@ impl FrameSystem {
     fn create_everything(
         gfx_queue: &Arc<Queue>,
         final_output_format: Format,
+        depth_format: Format,
         dimensions: [u32; 2],
-        color_debug_level: i32,
-    ) -> FrameState {
+    ) -> Result<FrameState, EngineError> {
         let render_pass: Arc<dyn RenderPassAbstract + Send + Sync + 'static> =
-            build_render_pass(gfx_queue, final_output_format);
+            build_render_pass(gfx_queue, final_output_format, depth_format)?;
 
         let (position_buffer, normals_buffer, albedo_buffer, depth_buffer) =
-            Self::create_images(gfx_queue, dimensions);
+            Self::create_images(gfx_queue, dimensions, depth_format)?;
 
         // For now we create three temporary images with a dimension of 1 by 1 pixel.
         // These images will be replaced the first time we call `frame()`.
@@ -75,25 +86,22 @@ impl FrameSystem {
         // Initialize the three lighting systems.
         // Note that we need to pass to them the subpass where they will be executed.
         let lighting_subpass = Subpass::from(render_pass.clone(), 1).unwrap();
-        let lighting_system = LightingSystem::new(
-            gfx_queue.clone(),
-            lighting_subpass.clone(),
-            position_buffer.clone(),
-            normals_buffer.clone(),
-            albedo_buffer.clone(),
-            color_debug_level, // depth_buffer.clone(),
-        );
-        (
+        let lighting_system = LightingSystem::new(gfx_queue.clone(), lighting_subpass.clone())?;
+        Ok((
             render_pass,
             position_buffer,
             normals_buffer,
             albedo_buffer,
             depth_buffer,
             lighting_system,
-        )
+        ))
     }
 
-    fn create_images(gfx_queue: &Arc<Queue>, dimensions: [u32; 2]) -> FrameImages {
+    fn create_images(
+        gfx_queue: &Arc<Queue>,
+        dimensions: [u32; 2],
+        depth_format: Format,
+    ) -> Result<FrameImages, EngineError> {
         let atch_usage = ImageUsage {
             color_attachment: true,
             input_attachment: true,
@@ -113,43 +121,45 @@ impl FrameSystem {
             dimensions,
             Format::R16G16B16A16Sfloat,
             atch_usage,
-        )
-        .unwrap();
+        )?;
 
         let normals_buffer = AttachmentImage::with_usage(
             gfx_queue.device().clone(),
             dimensions,
             Format::R16G16B16A16Sfloat,
             atch_usage,
-        )
-        .unwrap();
+        )?;
 
         let albedo_buffer = AttachmentImage::with_usage(
             gfx_queue.device().clone(),
             dimensions,
             Format::R8G8B8A8Unorm,
             atch_usage,
-        )
-        .unwrap();
+        )?;
 
         let depth_buffer = AttachmentImage::with_usage(
             gfx_queue.device().clone(),
             dimensions,
-            Format::D16Unorm,
+            depth_format,
             depth_atach_usage,
-        )
-        .unwrap();
+        )?;
+
+        labels::name_image(&*position_buffer, "G-buffer position");
+        labels::name_image(&*normals_buffer, "G-buffer normals");
+        labels::name_image(&*albedo_buffer, "G-buffer albedo");
+        labels::name_image(&*depth_buffer, "G-buffer depth");
 
-        (position_buffer, normals_buffer, albedo_buffer, depth_buffer)
+        Ok((position_buffer, normals_buffer, albedo_buffer, depth_buffer))
     }
 
     pub fn new(
         gfx_queue: Arc<Queue>,
         final_output_format: Format,
         dimensions: [u32; 2],
-        color_debug_level: i32,
-    ) -> FrameSystem {
+    ) -> Result<FrameSystem, EngineError> {
         log::trace!("insance of {}", std::any::type_name::<Self>());
+        let depth_format = supported_depth_format(gfx_queue.device().physical_device());
+        log::info!("Depth format {:?}", depth_format);
         let (
             render_pass,
             position_buffer,
@@ -157,30 +167,26 @@ impl FrameSystem {
             albedo_buffer,
             depth_buffer,
             lighting_system,
-        ) = Self::create_everything(
-            &gfx_queue,
-            final_output_format,
-            dimensions,
-            color_debug_level,
-        );
+        ) = Self::create_everything(&gfx_queue, final_output_format, depth_format, dimensions)?;
 
-        FrameSystem {
+        Ok(FrameSystem {
             gfx_queue,
             render_pass,
             position_buffer,
             normals_buffer,
             albedo_buffer,
             depth_buffer,
+            depth_format,
             lighting_system,
-        }
+            profiler: None,
+        })
     }
 
     pub fn recreate_render_pass(
         &mut self,
         final_output_format: Format,
         dimensions: [u32; 2],
-        color_debug_level: i32,
-    ) {
+    ) -> Result<(), EngineError> {
         let (
             render_pass,
             position_buffer,
@@ -191,9 +197,9 @@ impl FrameSystem {
         ) = Self::create_everything(
             &self.gfx_queue,
             final_output_format,
+            self.depth_format,
             dimensions,
-            color_debug_level,
-        );
+        )?;
 
         self.render_pass = render_pass;
         self.position_buffer = position_buffer;
@@ -201,6 +207,7 @@ impl FrameSystem {
         self.albedo_buffer = albedo_buffer;
         self.depth_buffer = depth_buffer;
         self.lighting_system = lighting_system;
+        Ok(())
     }
 
     /// Returns the subpass of the render pass where the rendering should write info to gbuffers.
@@ -222,6 +229,7 @@ impl FrameSystem {
     /// - `world_to_framebuffer` is the matrix that will be used to convert from 3D coordinates in
     ///   the world into 2D coordinates on the framebuffer.
     ///
+    /// Fails if the G-buffer can't be resized to the dimensions of `final_image`.
     pub fn frame<F, I>(
         &mut self,
         before_future: F,
@@ -230,7 +238,7 @@ impl FrameSystem {
         matrices: CameraMatrices,
         cached_scene: CachedEntities,
         dynamic_state: DynamicState,
-    ) -> Frame
+    ) -> Result<Frame, EngineError>
     where
         F: GpuFuture + 'static,
         I: ImageAccess + ImageViewAccess + Clone + Send + Sync + 'static,
@@ -241,7 +249,7 @@ impl FrameSystem {
         let img_dims = ImageAccess::dimensions(&final_image).width_height();
         if ImageAccess::dimensions(&self.albedo_buffer).width_height() != img_dims {
             let (position_buffer, normals_buffer, albedo_buffer, depth_buffer) =
-                Self::create_images(&self.gfx_queue, img_dims);
+                Self::create_images(&self.gfx_queue, img_dims, self.depth_format)?;
 
             // Note that we create "transient" images here. This means that the content of the
             // image is only defined when within a render pass. In other words you can draw to
@@ -277,6 +285,14 @@ impl FrameSystem {
             self.gfx_queue.family(),
         )
         .unwrap();
+        if let Some(profiler) = self.profiler.as_mut() {
+            // Safe for the same reasons as the other secondary command buffers of the frame.
+            unsafe {
+                command_buffer_builder
+                    .execute_commands(profiler.begin_frame())
+                    .unwrap();
+            }
+        }
         command_buffer_builder
             .begin_render_pass(
                 framebuffer.clone(),
@@ -286,12 +302,12 @@ impl FrameSystem {
                     ClearValue::Float([0.0, 0.0, 0.0, 0.0]),
                     ClearValue::Float([0.0, 0.0, 0.0, 0.0]),
                     ClearValue::Float([0.0, 0.0, 0.0, 0.0]),
-                    ClearValue::Depth(1.0),
+                    depth_clear_value(self.depth_format),
                 ],
             )
             .unwrap();
 
-        Frame {
+        Ok(Frame {
             system: self,
             before_main_cb_future: Some(Box::new(before_future)),
             framebuffer,
@@ -301,6 +317,8 @@ impl FrameSystem {
             matrices,
             cached_scene,
             dynamic_state,
-        }
+            draw_calls: 0,
+            culling: CullingStats::default(),
+        })
     }
 }
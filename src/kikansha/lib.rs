@@ -3,6 +3,7 @@ extern crate png;
 extern crate vk_sys as vk;
 extern crate vulkano;
 
+pub mod assets;
 pub mod debug;
 pub mod engine;
 pub mod figure;
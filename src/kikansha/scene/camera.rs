@@ -1,9 +1,13 @@
 extern crate nalgebra as na;
 extern crate nalgebra_glm as glm;
 
+use crate::figure::bounds::BoundingSphere;
+use crate::figure::FigureSet;
+use crate::scene::Scene;
 use nalgebra::Matrix4;
 use nalgebra::Point3;
 use nalgebra::Vector3;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
 
 #[derive(Default, Debug, Clone, Copy)]
 pub struct CameraMatrices {
@@ -49,6 +53,7 @@ pub trait ViewAndProject {
 
     fn update_ar(&mut self, aspect_ratio: f32);
 
+    /// Sets the vertical field of view, in degrees.
     fn update_fov(&mut self, fov: f32);
 
     fn get_matrices(&self) -> CameraMatrices {
@@ -69,21 +74,101 @@ pub trait ViewAndProject {
     }
 }
 
-fn calcullate_view_m(eye: Point3<f32>, dest: Point3<f32>) -> Matrix4<f32> {
-    let up: Vector3<f32> = Vector3::new(0.0, -1.0, 0.0);
-
+fn calcullate_view_m(eye: Point3<f32>, dest: Point3<f32>, up: Vector3<f32>) -> Matrix4<f32> {
     let center = Vector3::new(dest[0], dest[1], dest[2]);
     let eye_v = Vector3::new(eye[0], eye[1], eye[2]);
     glm::look_at(&eye_v, &center, &up)
 }
 
-fn calcullate_proj_m(
-    fov: f32,
+// Reversed depth, 1 at the near plane and 0 at infinity, there is no far plane.
+fn calcullate_proj_m(fov: f32, aspect_ratio: f32, near_plane_dist: f32) -> Matrix4<f32> {
+    glm::reversed_infinite_perspective_rh_zo(aspect_ratio, fov, near_plane_dist)
+}
+
+fn calcullate_ortho_m(
+    height: f32,
     aspect_ratio: f32,
     near_plane_dist: f32,
     far_plane_dist: f32,
 ) -> Matrix4<f32> {
-    glm::perspective(aspect_ratio, fov, near_plane_dist, far_plane_dist)
+    let half_height = height / 2.0;
+    let half_width = half_height * aspect_ratio;
+    // Swapping the planes reverses the depth like in the perspective projection.
+    glm::ortho_rh_zo(
+        -half_width,
+        half_width,
+        -half_height,
+        half_height,
+        far_plane_dist,
+        near_plane_dist,
+    )
+}
+
+// Room left around framed bounds.
+const FRAME_MARGIN: f32 = 1.1;
+// Bounds of a single point are framed as a sphere of this radius.
+const MIN_FRAMED_RADIUS: f32 = 0.001;
+
+// Distance from the center of a sphere at which it fits into the narrower field of view.
+fn framing_distance(radius: f32, fov: f32, aspect_ratio: f32) -> f32 {
+    let tan_half_fov = (fov / 2.0).tan();
+    let half_fov = tan_half_fov.atan();
+    let half_horizontal_fov = (tan_half_fov * aspect_ratio).atan();
+    radius * FRAME_MARGIN / half_fov.min(half_horizontal_fov).sin()
+}
+
+// Clip planes keeping a framed sphere visible while orbiting and zooming out.
+fn framing_clip_planes(radius: f32, distance: f32) -> (f32, f32) {
+    (radius * 0.01, (distance + radius) * 10.0)
+}
+
+/// Projection of a `StickyRotatingCamera`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,
+    /// Parallel projection covering what the perspective one shows at the target, changing the
+    /// distance zooms it.
+    Orthographic,
+}
+
+/// Standard views of a `StickyRotatingCamera`, named after the side of the model they look at
+/// when its front faces +Z and its top +Y.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewPreset {
+    Front,
+    Back,
+    /// Looks from +X.
+    Right,
+    Left,
+    Top,
+    Bottom,
+    /// Looks from the (1, 1, 1) diagonal.
+    Isometric,
+}
+
+impl ViewPreset {
+    pub const ALL: [ViewPreset; 7] = [
+        ViewPreset::Front,
+        ViewPreset::Back,
+        ViewPreset::Right,
+        ViewPreset::Left,
+        ViewPreset::Top,
+        ViewPreset::Bottom,
+        ViewPreset::Isometric,
+    ];
+
+    /// Yaw and pitch of the camera, a negative pitch puts it above the target.
+    pub fn yaw_pitch(self) -> (f32, f32) {
+        match self {
+            ViewPreset::Front => (FRAC_PI_2, 0.0),
+            ViewPreset::Back => (-FRAC_PI_2, 0.0),
+            ViewPreset::Right => (0.0, 0.0),
+            ViewPreset::Left => (PI, 0.0),
+            ViewPreset::Top => (FRAC_PI_2, -FRAC_PI_2),
+            ViewPreset::Bottom => (FRAC_PI_2, FRAC_PI_2),
+            ViewPreset::Isometric => (FRAC_PI_4, -(1.0 / 3.0_f32.sqrt()).asin()),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -96,30 +181,68 @@ pub struct StickyRotatingCamera {
     fov: f32,
     aspect_ratio: f32,
     near_plane_dist: f32,
+    // Only the orthographic projection has a far plane.
     far_plane_dist: f32,
     eye: Point3<f32>,
+    target: Point3<f32>,
+    projection: Projection,
 }
 
 impl StickyRotatingCamera {
-    fn calculate_eye(distance: f32, yaw: f32, pitch: f32) -> Point3<f32> {
+    fn calculate_eye(target: Point3<f32>, distance: f32, yaw: f32, pitch: f32) -> Point3<f32> {
         let x = yaw.cos() * pitch.cos();
         let y = -pitch.sin();
         let z = yaw.sin() * pitch.cos();
 
-        Point3::new(x * distance, y * distance, z * distance)
+        target + Vector3::new(x, y, z) * distance
+    }
+
+    // Derivative of the eye direction by the pitch, unlike a fixed up vector it stays valid when
+    // looking straight up or down.
+    fn calculate_up(yaw: f32, pitch: f32) -> Vector3<f32> {
+        Vector3::new(
+            -yaw.cos() * pitch.sin(),
+            -pitch.cos(),
+            -yaw.sin() * pitch.sin(),
+        )
+    }
+
+    fn update_view(&mut self) {
+        self.eye = Self::calculate_eye(self.target, self.distance, self.yaw, self.pitch);
+        let up = Self::calculate_up(self.yaw, self.pitch);
+        self.view_m = calcullate_view_m(self.eye, self.target, up);
+        // The orthographic projection depends on the distance.
+        if self.projection == Projection::Orthographic {
+            self.update_proj();
+        }
+    }
+
+    fn update_proj(&mut self) {
+        self.proj_m = match self.projection {
+            Projection::Perspective => {
+                calcullate_proj_m(self.fov, self.aspect_ratio, self.near_plane_dist)
+            }
+            Projection::Orthographic => calcullate_ortho_m(
+                2.0 * self.distance * (self.fov / 2.0).tan(),
+                self.aspect_ratio,
+                self.near_plane_dist,
+                self.far_plane_dist,
+            ),
+        };
     }
 
     pub fn new(distance: f32, yaw: f32, pitch: f32) -> Self {
         log::trace!("insance of {}", std::any::type_name::<Self>());
-        let eye = Self::calculate_eye(distance, yaw, pitch);
-        let fov: f32 = 45.0;
+        let target = Point3::new(0.0, 0.0, 0.0);
+        let eye = Self::calculate_eye(target, distance, yaw, pitch);
+        // 45 degrees, the projection takes radians.
+        let fov: f32 = FRAC_PI_4;
         let aspect_ratio: f32 = 16.0 / 9.0;
         let near_plane_dist: f32 = 0.1;
         let far_plane_dist: f32 = 1000.0;
 
-        let view_m: Matrix4<f32> = calcullate_view_m(eye, Point3::new(0.0, 0.0, 0.0));
-        let proj_m: Matrix4<f32> =
-            calcullate_proj_m(fov, aspect_ratio, near_plane_dist, far_plane_dist);
+        let view_m: Matrix4<f32> = calcullate_view_m(eye, target, Self::calculate_up(yaw, pitch));
+        let proj_m: Matrix4<f32> = calcullate_proj_m(fov, aspect_ratio, near_plane_dist);
         StickyRotatingCamera {
             view_m,
             proj_m,
@@ -131,21 +254,116 @@ impl StickyRotatingCamera {
             near_plane_dist,
             far_plane_dist,
             eye,
+            target,
+            projection: Projection::Perspective,
         }
     }
 
     pub fn set_yaw(&mut self, yaw: f32) {
-        let eye = Self::calculate_eye(self.distance, yaw, self.pitch);
-        let view_m: Matrix4<f32> = calcullate_view_m(eye, Point3::new(0.0, 0.0, 0.0));
-        self.view_m = view_m;
         self.yaw = yaw;
+        self.update_view();
     }
 
     pub fn set_pitch(&mut self, pitch: f32) {
-        let eye = Self::calculate_eye(self.distance, self.yaw, pitch);
-        let view_m: Matrix4<f32> = calcullate_view_m(eye, Point3::new(0.0, 0.0, 0.0));
-        self.view_m = view_m;
         self.pitch = pitch;
+        self.update_view();
+    }
+
+    pub fn set_distance(&mut self, distance: f32) {
+        self.distance = distance;
+        self.update_view();
+    }
+
+    /// Moves the point the camera rotates around and looks at.
+    pub fn set_target(&mut self, target: Point3<f32>) {
+        self.target = target;
+        self.update_view();
+    }
+
+    /// Sets the whole orbit at once, the view matrix is computed only once.
+    pub fn set_orbit(&mut self, target: Point3<f32>, distance: f32, yaw: f32, pitch: f32) {
+        self.target = target;
+        self.distance = distance;
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.update_view();
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+        self.update_proj();
+    }
+
+    /// Looks at the target from one of the standard sides, keeping the distance.
+    pub fn set_preset(&mut self, preset: ViewPreset) {
+        let (yaw, pitch) = preset.yaw_pitch();
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.update_view();
+    }
+
+    /// The perspective projection has no far plane, `far_plane_dist` limits only the
+    /// orthographic one.
+    pub fn set_clip_planes(&mut self, near_plane_dist: f32, far_plane_dist: f32) {
+        self.near_plane_dist = near_plane_dist;
+        self.far_plane_dist = far_plane_dist;
+        self.update_proj();
+    }
+
+    /// Targets the center of `sphere` from the current direction, choosing the distance and
+    /// the clip planes so that all of it is in view.
+    pub fn frame_sphere(&mut self, sphere: &BoundingSphere) {
+        let radius = sphere.radius.max(MIN_FRAMED_RADIUS);
+        let distance = framing_distance(radius, self.fov, self.aspect_ratio);
+        let (near_plane_dist, far_plane_dist) = framing_clip_planes(radius, distance);
+        self.near_plane_dist = near_plane_dist;
+        self.far_plane_dist = far_plane_dist;
+        self.target = sphere.center;
+        self.distance = distance;
+        self.update_view();
+        self.update_proj();
+    }
+
+    /// Frames all instances of `figure`, returns `false` if it has no geometry.
+    pub fn frame_figure(&mut self, figure: &FigureSet) -> bool {
+        match figure.bounds() {
+            Some(bounds) => {
+                self.frame_sphere(&bounds.bounding_sphere());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Frames every figure of `scene`, returns `false` if it has no geometry.
+    pub fn frame_scene<T: ViewAndProject + Sized>(&mut self, scene: &Scene<T>) -> bool {
+        match scene.bounds() {
+            Some(bounds) => {
+                self.frame_sphere(&bounds.bounding_sphere());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    pub fn target(&self) -> Point3<f32> {
+        self.target
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
     }
 }
 
@@ -159,28 +377,168 @@ impl ViewAndProject for StickyRotatingCamera {
     }
 
     fn update_ar(&mut self, aspect_ratio: f32) {
-        let proj_m: Matrix4<f32> = calcullate_proj_m(
-            self.fov,
-            aspect_ratio,
-            self.near_plane_dist,
-            self.far_plane_dist,
-        );
         self.aspect_ratio = aspect_ratio;
-        self.proj_m = proj_m;
+        self.update_proj();
     }
 
     fn update_fov(&mut self, fov: f32) {
-        let proj_m: Matrix4<f32> = calcullate_proj_m(
-            fov,
-            self.aspect_ratio,
-            self.near_plane_dist,
-            self.far_plane_dist,
-        );
-        self.fov = fov;
-        self.proj_m = proj_m;
+        self.fov = fov.to_radians();
+        self.update_proj();
     }
 
     fn camera_p(&self) -> Point3<f32> {
         self.eye
     }
 }
+
+/// First person camera, moved with `scene::fly::FlyController`.
+#[derive(Debug, Copy, Clone)]
+pub struct FreeFlyCamera {
+    view_m: Matrix4<f32>,
+    proj_m: Matrix4<f32>,
+    position: Point3<f32>,
+    yaw: f32,
+    pitch: f32,
+    roll: f32,
+    fov: f32,
+    aspect_ratio: f32,
+    near_plane_dist: f32,
+}
+
+impl FreeFlyCamera {
+    pub fn new(position: Point3<f32>, yaw: f32, pitch: f32) -> Self {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        // 45 degrees, the projection takes radians.
+        let fov: f32 = FRAC_PI_4;
+        let aspect_ratio: f32 = 16.0 / 9.0;
+        let near_plane_dist: f32 = 0.1;
+        let mut camera = FreeFlyCamera {
+            view_m: Matrix4::identity(),
+            proj_m: calcullate_proj_m(fov, aspect_ratio, near_plane_dist),
+            position,
+            yaw,
+            pitch,
+            roll: 0.0,
+            fov,
+            aspect_ratio,
+            near_plane_dist,
+        };
+        camera.update_view();
+        camera
+    }
+
+    fn update_view(&mut self) {
+        let forward = self.forward();
+        let eye = Vector3::new(self.position[0], self.position[1], self.position[2]);
+        let up = glm::rotate_vec3(&Vector3::new(0.0, -1.0, 0.0), self.roll, &forward);
+        self.view_m = glm::look_at(&eye, &(eye + forward), &up);
+    }
+
+    /// Unit vector of the view direction, a positive pitch looks down.
+    pub fn forward(&self) -> Vector3<f32> {
+        Vector3::new(
+            self.yaw.cos() * self.pitch.cos(),
+            -self.pitch.sin(),
+            self.yaw.sin() * self.pitch.cos(),
+        )
+    }
+
+    /// Unit vector pointing to the right of the screen, ignoring the roll.
+    pub fn right(&self) -> Vector3<f32> {
+        self.forward()
+            .cross(&Vector3::new(0.0, -1.0, 0.0))
+            .normalize()
+    }
+
+    pub fn position(&self) -> Point3<f32> {
+        self.position
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    pub fn roll(&self) -> f32 {
+        self.roll
+    }
+
+    pub fn set_position(&mut self, position: Point3<f32>) {
+        self.position = position;
+        self.update_view();
+    }
+
+    /// The projection has no far plane, everything in front of the near one is drawn.
+    pub fn set_near_plane(&mut self, near_plane_dist: f32) {
+        self.near_plane_dist = near_plane_dist;
+        self.update_ar(self.aspect_ratio);
+    }
+
+    /// Moves back along the view direction until all of `sphere` is in view, choosing the near
+    /// plane for its size.
+    pub fn frame_sphere(&mut self, sphere: &BoundingSphere) {
+        let radius = sphere.radius.max(MIN_FRAMED_RADIUS);
+        let distance = framing_distance(radius, self.fov, self.aspect_ratio);
+        let (near_plane_dist, _) = framing_clip_planes(radius, distance);
+        self.set_near_plane(near_plane_dist);
+        self.set_position(sphere.center - self.forward() * distance);
+    }
+
+    /// Frames all instances of `figure`, returns `false` if it has no geometry.
+    pub fn frame_figure(&mut self, figure: &FigureSet) -> bool {
+        match figure.bounds() {
+            Some(bounds) => {
+                self.frame_sphere(&bounds.bounding_sphere());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Frames every figure of `scene`, returns `false` if it has no geometry.
+    pub fn frame_scene<T: ViewAndProject + Sized>(&mut self, scene: &Scene<T>) -> bool {
+        match scene.bounds() {
+            Some(bounds) => {
+                self.frame_sphere(&bounds.bounding_sphere());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets the position and the orientation at once, the view matrix is computed only once.
+    pub fn set_pose(&mut self, position: Point3<f32>, yaw: f32, pitch: f32, roll: f32) {
+        self.position = position;
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.roll = roll;
+        self.update_view();
+    }
+}
+
+impl ViewAndProject for FreeFlyCamera {
+    fn view_m(&self) -> Matrix4<f32> {
+        self.view_m
+    }
+
+    fn proj_m(&self) -> Matrix4<f32> {
+        self.proj_m
+    }
+
+    fn update_ar(&mut self, aspect_ratio: f32) {
+        self.aspect_ratio = aspect_ratio;
+        self.proj_m = calcullate_proj_m(self.fov, aspect_ratio, self.near_plane_dist);
+    }
+
+    fn update_fov(&mut self, fov: f32) {
+        self.fov = fov.to_radians();
+        self.proj_m = calcullate_proj_m(self.fov, self.aspect_ratio, self.near_plane_dist);
+    }
+
+    fn camera_p(&self) -> Point3<f32> {
+        self.position
+    }
+}
@@ -0,0 +1,117 @@
+use crate::engine::input::InputHandler;
+use crate::engine::input::InputState;
+use crate::scene::camera::FreeFlyCamera;
+use nalgebra::Vector3;
+use std::f32::consts::FRAC_PI_2;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+// Looking straight up or down would make the view direction parallel to the up vector.
+const PITCH_LIMIT: f32 = FRAC_PI_2 - 0.01;
+// Speed change per scrolled line.
+const SCROLL_SPEED_STEP: f32 = 1.2;
+
+/// Moves a `FreeFlyCamera` like in a first person game.
+///
+/// WASD moves, E and Q rise and sink, holding the right mouse button looks around and scrolling
+/// changes the speed. Shift speeds the movement up and Ctrl slows it down.
+pub struct FlyController {
+    camera: Arc<Mutex<FreeFlyCamera>>,
+    speed: f32,
+    fast_multiplier: f32,
+    slow_multiplier: f32,
+    look_speed: f32,
+}
+
+impl FlyController {
+    pub fn new(camera: Arc<Mutex<FreeFlyCamera>>) -> Self {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        FlyController {
+            camera,
+            speed: 2.0,
+            fast_multiplier: 5.0,
+            slow_multiplier: 0.2,
+            look_speed: 0.003,
+        }
+    }
+
+    /// Units per second without modifiers.
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Speed multipliers while Shift and Ctrl are held.
+    pub fn with_modifiers(mut self, fast_multiplier: f32, slow_multiplier: f32) -> Self {
+        self.fast_multiplier = fast_multiplier;
+        self.slow_multiplier = slow_multiplier;
+        self
+    }
+
+    /// Radians per pixel of mouse movement.
+    pub fn with_look_speed(mut self, look_speed: f32) -> Self {
+        self.look_speed = look_speed;
+        self
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    fn key_axis(input: &InputState, negative: VirtualKeyCode, positive: VirtualKeyCode) -> f32 {
+        let mut axis = 0.0;
+        if input.is_key_down(negative) {
+            axis -= 1.0;
+        }
+        if input.is_key_down(positive) {
+            axis += 1.0;
+        }
+        axis
+    }
+}
+
+impl InputHandler for FlyController {
+    fn on_frame(&mut self, input: &InputState, elapsed: Duration) {
+        if !input.is_focused() {
+            return;
+        }
+        self.speed *= SCROLL_SPEED_STEP.powf(input.scroll()[1]);
+
+        let mut speed = self.speed;
+        if input.modifiers().shift() {
+            speed *= self.fast_multiplier;
+        }
+        if input.modifiers().ctrl() {
+            speed *= self.slow_multiplier;
+        }
+
+        let mut locked_camera = self.camera.lock().unwrap();
+        let (mut yaw, mut pitch) = (locked_camera.yaw(), locked_camera.pitch());
+        if input.is_button_down(MouseButton::Right) {
+            let [dx, dy] = input.mouse_delta();
+            yaw -= dx * self.look_speed;
+            pitch += dy * self.look_speed;
+            pitch = pitch.max(-PITCH_LIMIT).min(PITCH_LIMIT);
+        }
+
+        let forward = locked_camera.forward();
+        let right = locked_camera.right();
+        // The view matrix flips Y, +Y is up on the screen.
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let direction = forward * Self::key_axis(input, VirtualKeyCode::S, VirtualKeyCode::W)
+            + right * Self::key_axis(input, VirtualKeyCode::A, VirtualKeyCode::D)
+            + up * Self::key_axis(input, VirtualKeyCode::Q, VirtualKeyCode::E);
+        // Diagonal movement is as fast as straight movement.
+        let step = if direction.norm_squared() > 0.0 {
+            direction.normalize() * speed * elapsed.as_secs_f32()
+        } else {
+            direction
+        };
+
+        let position = locked_camera.position() + step;
+        let roll = locked_camera.roll();
+        locked_camera.set_pose(position, yaw, pitch, roll);
+    }
+}
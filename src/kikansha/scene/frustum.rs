@@ -0,0 +1,200 @@
+use crate::figure::bounds::Aabb;
+use crate::figure::bounds::BoundingSphere;
+use crate::scene::camera::CameraMatrices;
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+use nalgebra::Vector4;
+
+// Planes with a shorter normal are degenerate, like the far plane of an infinite projection.
+const MIN_PLANE_NORMAL: f32 = 1.0e-6;
+
+/// Planes bounding what a camera sees, for culling objects outside of the view.
+///
+/// Each plane is `(normal, distance)` with the normal pointing inside the frustum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frustum {
+    planes: Vec<Vector4<f32>>,
+}
+
+impl Frustum {
+    pub fn from_matrices(matrices: &CameraMatrices) -> Self {
+        let projection = Matrix4::from_column_slice(&matrices.projection_matrix);
+        let view = Matrix4::from_column_slice(&matrices.view_matrix);
+        Self::from_view_projection(&(projection * view))
+    }
+
+    /// Extracts the planes of the Vulkan clip volume, `-w <= x, y <= w` and `0 <= z <= w`, in
+    /// world coordinates.
+    pub fn from_view_projection(view_projection: &Matrix4<f32>) -> Self {
+        let row = |i: usize| view_projection.row(i).transpose();
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ]
+        .iter()
+        .filter_map(|plane| {
+            let length = plane.xyz().norm();
+            if length > MIN_PLANE_NORMAL {
+                Some(plane / length)
+            } else {
+                None
+            }
+        })
+        .collect();
+        Frustum { planes }
+    }
+
+    /// Normalized planes, at most six, for the culling compute shader.
+    pub fn planes(&self) -> &[Vector4<f32>] {
+        &self.planes
+    }
+
+    /// `false` only if all of `aabb` is outside, boxes near the corners may pass although they
+    /// are not visible.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let furthest = |normal: f32, min: f32, max: f32| if normal >= 0.0 { max } else { min };
+            // Corner of the box furthest along the normal.
+            let corner = Vector3::new(
+                furthest(plane.x, aabb.min.x, aabb.max.x),
+                furthest(plane.y, aabb.min.y, aabb.max.y),
+                furthest(plane.z, aabb.min.z, aabb.max.z),
+            );
+            plane.xyz().dot(&corner) + plane.w >= 0.0
+        })
+    }
+
+    /// `false` only if all of `sphere` is outside, spheres near the corners may pass like boxes.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(&sphere.center.coords) + plane.w >= -sphere.radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+    use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_2};
+
+    const EPSILON: f32 = 1.0e-5;
+    const NEAR: f32 = 0.1;
+
+    // Camera at the origin looking down -z with a 90 degree field of view, so the side planes go
+    // through the diagonals `x = ±z` and `y = ±z`.
+    fn frustum() -> Frustum {
+        let projection = nalgebra_glm::reversed_infinite_perspective_rh_zo(1.0, FRAC_PI_2, NEAR);
+        Frustum::from_view_projection(&projection)
+    }
+
+    fn cube(center: [f32; 3], half_size: f32) -> Aabb {
+        let center = Point3::from(center);
+        let half = Vector3::repeat(half_size);
+        Aabb::new(center - half, center + half)
+    }
+
+    fn sphere(center: [f32; 3], radius: f32) -> BoundingSphere {
+        BoundingSphere {
+            center: Point3::from(center),
+            radius,
+        }
+    }
+
+    fn has_plane(frustum: &Frustum, expected: Vector4<f32>) -> bool {
+        frustum
+            .planes()
+            .iter()
+            .any(|plane| (plane - expected).norm() < EPSILON)
+    }
+
+    #[test]
+    fn drops_the_degenerate_far_plane() {
+        let frustum = frustum();
+        assert_eq!(frustum.planes().len(), 5);
+        for plane in frustum.planes() {
+            assert!((plane.xyz().norm() - 1.0).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn extracts_inward_planes() {
+        let frustum = frustum();
+        let d = FRAC_1_SQRT_2;
+        for expected in &[
+            Vector4::new(0.0, 0.0, -1.0, -NEAR),
+            Vector4::new(d, 0.0, -d, 0.0),
+            Vector4::new(-d, 0.0, -d, 0.0),
+            Vector4::new(0.0, d, -d, 0.0),
+            Vector4::new(0.0, -d, -d, 0.0),
+        ] {
+            assert!(has_plane(&frustum, *expected), "{:?}", expected);
+        }
+    }
+
+    #[test]
+    fn keeps_what_is_inside() {
+        let frustum = frustum();
+        assert!(frustum.intersects_aabb(&cube([0.0, 0.0, -5.0], 1.0)));
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, -5.0], 1.0)));
+        // Nothing is too far away for an infinite projection.
+        assert!(frustum.intersects_aabb(&cube([0.0, 0.0, -1.0e6], 1.0)));
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, -1.0e6], 1.0)));
+    }
+
+    // Centers at a distance of 5 past each plane, at a depth of 10.
+    const OUTSIDE: [[f32; 3]; 5] = [
+        [-15.0, 0.0, -10.0],
+        [15.0, 0.0, -10.0],
+        [0.0, -15.0, -10.0],
+        [0.0, 15.0, -10.0],
+        [0.0, 0.0, 5.0],
+    ];
+
+    #[test]
+    fn culls_what_is_outside_of_each_plane() {
+        let frustum = frustum();
+        for center in &OUTSIDE {
+            let (aabb, ball) = (cube(*center, 1.0), sphere(*center, 1.0));
+            assert!(!frustum.intersects_aabb(&aabb), "{:?}", center);
+            assert!(!frustum.intersects_sphere(&ball), "{:?}", center);
+        }
+        // Between the camera and the near plane.
+        assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, -0.04], 0.05)));
+        assert!(!frustum.intersects_sphere(&sphere([0.0, 0.0, -0.04], 0.05)));
+    }
+
+    #[test]
+    fn keeps_what_straddles_each_plane() {
+        let frustum = frustum();
+        for center in &[
+            [-10.0, 0.0, -10.0],
+            [10.0, 0.0, -10.0],
+            [0.0, -10.0, -10.0],
+            [0.0, 10.0, -10.0],
+            [0.0, 0.0, -NEAR],
+        ] {
+            let (aabb, ball) = (cube(*center, 1.0), sphere(*center, 1.0));
+            assert!(frustum.intersects_aabb(&aabb), "{:?}", center);
+            assert!(frustum.intersects_sphere(&ball), "{:?}", center);
+        }
+    }
+
+    #[test]
+    fn planes_follow_the_view() {
+        let projection = nalgebra_glm::reversed_infinite_perspective_rh_zo(1.0, FRAC_PI_2, NEAR);
+        let view = nalgebra_glm::look_at(
+            &Vector3::new(0.0, 0.0, 5.0),
+            &Vector3::zeros(),
+            &Vector3::y(),
+        );
+        let frustum = Frustum::from_view_projection(&(projection * view));
+        assert!(frustum.intersects_aabb(&cube([0.0, 0.0, 0.0], 1.0)));
+        assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, 10.0], 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere([-20.0, 0.0, 0.0], 1.0)));
+    }
+}
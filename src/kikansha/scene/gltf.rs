@@ -1,3 +1,4 @@
+use crate::assets::AssetResolver;
 use crate::figure::IndexedMesh;
 use crate::figure::MeshPoint;
 use crate::figure::RegularMesh;
@@ -10,6 +11,7 @@ use nalgebra_glm::normalize;
 
 pub enum LoadingError {
     Ooops,
+    NotFound(String),
 }
 
 impl From<gltf::Error> for LoadingError {
@@ -18,9 +20,15 @@ impl From<gltf::Error> for LoadingError {
     }
 }
 
-pub fn load_figures(path: &str) -> Result<Vec<RenderableMesh>, LoadingError> {
+pub fn load_figures(
+    assets: &AssetResolver,
+    path: &str,
+) -> Result<Vec<RenderableMesh>, LoadingError> {
     let mut figures: Vec<RenderableMesh> = Vec::new();
-    let (gltf, buffers, _) = gltf::import(path)?;
+    let resolved = assets
+        .resolve(path)
+        .ok_or_else(|| LoadingError::NotFound(path.to_string()))?;
+    let (gltf, buffers, _) = gltf::import(resolved)?;
     for mesh in gltf.meshes() {
         for primitive in mesh.primitives() {
             let mut points: Vec<MeshPoint> = Vec::new();
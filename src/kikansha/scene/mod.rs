@@ -2,31 +2,167 @@ extern crate nalgebra as na;
 extern crate nalgebra_glm as glm;
 
 pub mod camera;
+pub mod fly;
+pub mod frustum;
 pub mod lights;
 pub mod gltf;
+pub mod orbit;
+pub mod path;
 
+use crate::figure::bounds;
+use crate::figure::bounds::Aabb;
+use crate::figure::FigureMutation;
 use crate::figure::FigureSet;
 use crate::scene::camera::ViewAndProject;
 use crate::scene::lights::Light;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+// Identifier of the next scene, 0 is never used.
+static NEXT_SCENE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Stable identifier of a figure inside a `Scene`.
+///
+/// Handles are never reused within a scene, so a cache may keep GPU resources keyed by them as
+/// long as it checks `Scene::scene_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FigureHandle(u32);
+
 #[derive(Debug, Clone)]
+pub struct SceneFigure {
+    pub set: FigureSet,
+    // Scene revision of the last change of the mesh or the textures.
+    pub(crate) mesh_revision: u32,
+    // Scene revision of the last change of the mutations.
+    pub(crate) mutations_revision: u32,
+}
+
+#[derive(Debug)]
 pub struct Scene<T: ViewAndProject + Sized> {
     pub camera: Arc<Mutex<T>>,
-    pub figures: Vec<FigureSet>,
-    pub global_scene_id: u32,
+    figures: BTreeMap<FigureHandle, SceneFigure>,
+    scene_id: u64,
+    global_scene_id: u32,
+    next_handle: u32,
     pub lights: Vec<Light>,
 }
 
 impl<T: ViewAndProject + Sized> Scene<T> {
     pub fn create(camera: Arc<Mutex<T>>, figures: Vec<FigureSet>, lights: Vec<Light>) -> Self {
-        Self {
+        let mut scene = Self {
             camera,
-            figures,
+            figures: BTreeMap::new(),
+            scene_id: NEXT_SCENE_ID.fetch_add(1, Ordering::Relaxed),
             global_scene_id: 1,
+            next_handle: 0,
             lights,
+        };
+        for figure in figures {
+            scene.add_figure(figure);
+        }
+        scene
+    }
+
+    /// Identifier unique to this scene in the process, a clone gets its own.
+    pub fn scene_id(&self) -> u64 {
+        self.scene_id
+    }
+
+    /// Revision of the scene content, changes on every figure modification.
+    pub fn global_scene_id(&self) -> u32 {
+        self.global_scene_id
+    }
+
+    pub fn figures(&self) -> impl Iterator<Item = (FigureHandle, &SceneFigure)> {
+        self.figures
+            .iter()
+            .map(|(handle, figure)| (*handle, figure))
+    }
+
+    /// Bounds of every figure instance, `None` for a scene without geometry.
+    pub fn bounds(&self) -> Option<Aabb> {
+        bounds::figures_bounds(self.figures.values().map(|figure| &figure.set))
+    }
+
+    pub fn figure(&self, handle: FigureHandle) -> Option<&FigureSet> {
+        self.figures.get(&handle).map(|figure| &figure.set)
+    }
+
+    pub fn add_figure(&mut self, set: FigureSet) -> FigureHandle {
+        let revision = self.bump_revision();
+        let handle = FigureHandle(self.next_handle);
+        self.next_handle += 1;
+        self.figures.insert(
+            handle,
+            SceneFigure {
+                set,
+                mesh_revision: revision,
+                mutations_revision: revision,
+            },
+        );
+        handle
+    }
+
+    pub fn remove_figure(&mut self, handle: FigureHandle) -> Option<FigureSet> {
+        let removed = self.figures.remove(&handle);
+        if removed.is_some() {
+            self.bump_revision();
+        }
+        removed.map(|figure| figure.set)
+    }
+
+    /// Replaces the whole figure, its mesh and textures will be uploaded again.
+    pub fn update_figure(&mut self, handle: FigureHandle, set: FigureSet) -> bool {
+        if !self.figures.contains_key(&handle) {
+            return false;
+        }
+        let revision = self.bump_revision();
+        if let Some(figure) = self.figures.get_mut(&handle) {
+            figure.set = set;
+            figure.mesh_revision = revision;
+            figure.mutations_revision = revision;
+        }
+        true
+    }
+
+    /// Replaces only the mutations of the figure, the mesh and textures stay on the GPU.
+    pub fn update_mutations(
+        &mut self,
+        handle: FigureHandle,
+        mutations: Vec<FigureMutation>,
+    ) -> bool {
+        if !self.figures.contains_key(&handle) {
+            return false;
+        }
+        let revision = self.bump_revision();
+        if let Some(figure) = self.figures.get_mut(&handle) {
+            figure.set.mutations = mutations;
+            figure.mutations_revision = revision;
+        }
+        true
+    }
+
+    fn bump_revision(&mut self) -> u32 {
+        self.global_scene_id = self.global_scene_id.wrapping_add(1);
+        self.global_scene_id
+    }
+}
+
+// Cloned scenes are modified independently, they can't share the identifier their revisions and
+// handles are cached with.
+impl<T: ViewAndProject + Sized> Clone for Scene<T> {
+    fn clone(&self) -> Self {
+        Self {
+            camera: self.camera.clone(),
+            figures: self.figures.clone(),
+            scene_id: NEXT_SCENE_ID.fetch_add(1, Ordering::Relaxed),
+            global_scene_id: self.global_scene_id,
+            next_handle: self.next_handle,
+            lights: self.lights.clone(),
         }
     }
 }
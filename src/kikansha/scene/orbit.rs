@@ -0,0 +1,222 @@
+use crate::engine::input::InputEvent;
+use crate::engine::input::InputHandler;
+use crate::engine::input::InputState;
+use crate::scene::camera::Projection;
+use crate::scene::camera::StickyRotatingCamera;
+use crate::scene::camera::ViewPreset;
+use nalgebra::Point3;
+use nalgebra::Vector3;
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use winit::event::{ModifiersState, MouseButton, VirtualKeyCode};
+
+// Going over a pole would turn the view upside down.
+const PITCH_LIMIT: f32 = FRAC_PI_2;
+// Lines zoomed per second while a zoom key is held.
+const KEY_ZOOM_RATE: f32 = 4.0;
+
+/// Orbits a `StickyRotatingCamera` around its target with the mouse and the keyboard.
+///
+/// Left drag or the arrow keys rotate, scrolling or page up/down zoom and middle drag pans the
+/// target. The camera eases towards the requested orbit instead of jumping to it.
+///
+/// Like in Blender, numpad 1, 3 and 7 show the front, right and top views, with Ctrl the
+/// opposite ones. Numpad 9 shows the isometric view and numpad 5 toggles the orthographic
+/// projection.
+pub struct OrbitController {
+    camera: Arc<Mutex<StickyRotatingCamera>>,
+    // Orbit the camera is moving to.
+    target: Point3<f32>,
+    distance: f32,
+    yaw: f32,
+    pitch: f32,
+    rotate_speed: f32,
+    key_rotate_speed: f32,
+    zoom_speed: f32,
+    pan_speed: f32,
+    damping: f32,
+    min_distance: f32,
+    max_distance: f32,
+    modifiers: ModifiersState,
+}
+
+impl OrbitController {
+    pub fn new(camera: Arc<Mutex<StickyRotatingCamera>>) -> Self {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        let (target, distance, yaw, pitch) = {
+            let locked_camera = camera.lock().unwrap();
+            (
+                locked_camera.target(),
+                locked_camera.distance(),
+                locked_camera.yaw(),
+                locked_camera.pitch(),
+            )
+        };
+        OrbitController {
+            camera,
+            target,
+            distance,
+            yaw,
+            pitch: pitch.max(-PITCH_LIMIT).min(PITCH_LIMIT),
+            rotate_speed: 0.005,
+            key_rotate_speed: 1.5,
+            zoom_speed: 0.1,
+            pan_speed: 0.001,
+            damping: 12.0,
+            min_distance: 0.1,
+            // Large scenes are framed from further away.
+            max_distance: 500.0_f32.max(distance * 10.0),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    /// Radians per pixel of mouse movement and per second while an arrow key is held.
+    pub fn with_rotate_speed(mut self, per_pixel: f32, per_second: f32) -> Self {
+        self.rotate_speed = per_pixel;
+        self.key_rotate_speed = per_second;
+        self
+    }
+
+    /// Fraction of the distance zoomed per scrolled line.
+    pub fn with_zoom_speed(mut self, zoom_speed: f32) -> Self {
+        self.zoom_speed = zoom_speed;
+        self
+    }
+
+    /// Fraction of the distance panned per pixel of mouse movement.
+    pub fn with_pan_speed(mut self, pan_speed: f32) -> Self {
+        self.pan_speed = pan_speed;
+        self
+    }
+
+    /// How fast the camera catches up with the input, per second. Zero disables the easing.
+    pub fn with_damping(mut self, damping: f32) -> Self {
+        self.damping = damping;
+        self
+    }
+
+    pub fn with_distance_range(mut self, min_distance: f32, max_distance: f32) -> Self {
+        self.min_distance = min_distance;
+        self.max_distance = max_distance;
+        self.distance = self.distance.max(min_distance).min(max_distance);
+        self
+    }
+
+    /// Turns the camera to a standard view, keeping the target and the distance.
+    pub fn set_preset(&mut self, preset: ViewPreset) {
+        let (yaw, pitch) = preset.yaw_pitch();
+        // Takes the short way around from the current yaw.
+        let turn = (yaw - self.yaw).rem_euclid(2.0 * PI);
+        self.yaw += if turn > PI { turn - 2.0 * PI } else { turn };
+        self.pitch = pitch;
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.camera.lock().unwrap().set_projection(projection);
+    }
+
+    pub fn toggle_projection(&mut self) {
+        let mut locked_camera = self.camera.lock().unwrap();
+        let projection = match locked_camera.projection() {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::Perspective,
+        };
+        locked_camera.set_projection(projection);
+    }
+
+    fn key_axis(input: &InputState, negative: VirtualKeyCode, positive: VirtualKeyCode) -> f32 {
+        let mut axis = 0.0;
+        if input.is_key_down(negative) {
+            axis -= 1.0;
+        }
+        if input.is_key_down(positive) {
+            axis += 1.0;
+        }
+        axis
+    }
+
+    fn pan(&mut self, delta: [f32; 2]) {
+        let (yaw, pitch) = (self.yaw, self.pitch);
+        let forward = -Vector3::new(
+            yaw.cos() * pitch.cos(),
+            -pitch.sin(),
+            yaw.sin() * pitch.cos(),
+        );
+        let up = Vector3::new(0.0, -1.0, 0.0);
+        let right = forward.cross(&up).normalize();
+        let camera_up = right.cross(&forward);
+        let scale = self.pan_speed * self.distance;
+        self.target += (right * -delta[0] + camera_up * delta[1]) * scale;
+    }
+
+    fn apply_input(&mut self, input: &InputState, elapsed: f32) {
+        let [dx, dy] = input.mouse_delta();
+        if input.is_button_down(MouseButton::Left) {
+            self.yaw += dx * self.rotate_speed;
+            self.pitch += dy * self.rotate_speed;
+        }
+        if input.is_button_down(MouseButton::Middle) {
+            self.pan([dx, dy]);
+        }
+        let key_rotation = self.key_rotate_speed * elapsed;
+        self.yaw +=
+            Self::key_axis(input, VirtualKeyCode::Left, VirtualKeyCode::Right) * key_rotation;
+        self.pitch +=
+            Self::key_axis(input, VirtualKeyCode::Up, VirtualKeyCode::Down) * key_rotation;
+        self.pitch = self.pitch.max(-PITCH_LIMIT).min(PITCH_LIMIT);
+
+        let zoom = input.scroll()[1]
+            + Self::key_axis(input, VirtualKeyCode::PageDown, VirtualKeyCode::PageUp)
+                * KEY_ZOOM_RATE
+                * elapsed;
+        self.distance *= (1.0 - self.zoom_speed).powf(zoom);
+        self.distance = self.distance.max(self.min_distance).min(self.max_distance);
+    }
+}
+
+impl InputHandler for OrbitController {
+    fn on_event(&mut self, event: &InputEvent) {
+        let key = match event {
+            InputEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                return;
+            }
+            InputEvent::KeyPressed(key) => *key,
+            _ => return,
+        };
+        let opposite = self.modifiers.ctrl();
+        match key {
+            VirtualKeyCode::Numpad1 if opposite => self.set_preset(ViewPreset::Back),
+            VirtualKeyCode::Numpad1 => self.set_preset(ViewPreset::Front),
+            VirtualKeyCode::Numpad3 if opposite => self.set_preset(ViewPreset::Left),
+            VirtualKeyCode::Numpad3 => self.set_preset(ViewPreset::Right),
+            VirtualKeyCode::Numpad7 if opposite => self.set_preset(ViewPreset::Bottom),
+            VirtualKeyCode::Numpad7 => self.set_preset(ViewPreset::Top),
+            VirtualKeyCode::Numpad9 => self.set_preset(ViewPreset::Isometric),
+            VirtualKeyCode::Numpad5 => self.toggle_projection(),
+            _ => (),
+        }
+    }
+
+    fn on_frame(&mut self, input: &InputState, elapsed: Duration) {
+        let elapsed = elapsed.as_secs_f32();
+        if input.is_focused() {
+            self.apply_input(input, elapsed);
+        }
+
+        // Frame rate independent exponential easing.
+        let t = if self.damping > 0.0 {
+            1.0 - (-self.damping * elapsed).exp()
+        } else {
+            1.0
+        };
+        let mut locked_camera = self.camera.lock().unwrap();
+        let target = locked_camera.target() + (self.target - locked_camera.target()) * t;
+        let distance = locked_camera.distance() + (self.distance - locked_camera.distance()) * t;
+        let yaw = locked_camera.yaw() + (self.yaw - locked_camera.yaw()) * t;
+        let pitch = locked_camera.pitch() + (self.pitch - locked_camera.pitch()) * t;
+        locked_camera.set_orbit(target, distance, yaw, pitch);
+    }
+}
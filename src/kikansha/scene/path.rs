@@ -0,0 +1,583 @@
+use crate::engine::input::InputEvent;
+use crate::engine::input::InputHandler;
+use crate::engine::input::InputState;
+use crate::scene::camera::FreeFlyCamera;
+use crate::scene::camera::StickyRotatingCamera;
+use crate::scene::camera::ViewAndProject;
+use nalgebra::Point3;
+use nalgebra::Unit;
+use nalgebra::Vector3;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use winit::event::VirtualKeyCode;
+
+// Directions closer than this to parallel or opposite are not slerped.
+const MIN_SLERP_SINE: f32 = 1.0e-4;
+
+/// Camera pose at a point in time of a `CameraPath`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct CameraKeyframe {
+    /// Seconds from the start of the path.
+    pub time: f32,
+    pub position: [f32; 3],
+    pub target: [f32; 3],
+    /// Vertical field of view in degrees, between 0 and 180 exclusive. Passed to
+    /// `ViewAndProject::update_fov`, the camera keeps its own when missing.
+    #[serde(default)]
+    pub fov: Option<f32>,
+}
+
+/// Camera pose sampled from a `CameraPath`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,
+    /// Vertical field of view in degrees.
+    pub fov: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum PathError {
+    Parse(toml::de::Error),
+    Empty,
+    /// The keyframe at this index is not later than the previous one.
+    Unordered(usize),
+    /// The field of view of the keyframe at this index is not between 0 and 180 degrees.
+    Fov(usize),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Parse(e) => write!(f, "{}", e),
+            PathError::Empty => write!(f, "the path has no keyframes"),
+            PathError::Unordered(index) => {
+                write!(f, "keyframe {} is not later than the previous one", index)
+            }
+            PathError::Fov(index) => write!(
+                f,
+                "keyframe {} has a field of view outside of (0, 180) degrees",
+                index
+            ),
+        }
+    }
+}
+
+impl Error for PathError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PathError::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<toml::de::Error> for PathError {
+    fn from(e: toml::de::Error) -> Self {
+        PathError::Parse(e)
+    }
+}
+
+/// Keyframed camera poses, positions follow a Catmull-Rom spline through the keyframes and the
+/// view direction is slerped between them.
+///
+/// Loaded from TOML:
+///
+/// ```toml
+/// looped = true
+///
+/// [[keyframes]]
+/// time = 0.0
+/// position = [3.0, 2.0, 0.0]
+/// target = [0.0, 0.0, 0.0]
+/// ```
+///
+/// A looped path should end on the pose it starts with, the spline then goes smoothly through
+/// that pose on every loop.
+///
+/// Deserialized paths are validated like the ones created with `new`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "UncheckedPath")]
+pub struct CameraPath {
+    keyframes: Vec<CameraKeyframe>,
+    looped: bool,
+}
+
+// `CameraPath` as written in TOML, before the keyframes are validated.
+#[derive(Deserialize)]
+struct UncheckedPath {
+    keyframes: Vec<CameraKeyframe>,
+    #[serde(default)]
+    looped: bool,
+}
+
+impl TryFrom<UncheckedPath> for CameraPath {
+    type Error = PathError;
+
+    fn try_from(unchecked: UncheckedPath) -> Result<Self, PathError> {
+        CameraPath::new(unchecked.keyframes, unchecked.looped)
+    }
+}
+
+fn catmull_rom(
+    p0: Vector3<f32>,
+    p1: Vector3<f32>,
+    p2: Vector3<f32>,
+    p3: Vector3<f32>,
+    t: f32,
+) -> Vector3<f32> {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+fn slerp(from: Vector3<f32>, to: Vector3<f32>, t: f32) -> Vector3<f32> {
+    let from = Unit::new_normalize(from);
+    let to = Unit::new_normalize(to);
+    // Opposite directions have no single shortest arc, the camera turns at the end instead.
+    match from.try_slerp(&to, t, MIN_SLERP_SINE) {
+        Some(direction) => direction.into_inner(),
+        None if t < 1.0 => from.into_inner(),
+        None => to.into_inner(),
+    }
+}
+
+impl CameraPath {
+    pub fn new(keyframes: Vec<CameraKeyframe>, looped: bool) -> Result<Self, PathError> {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        let path = CameraPath { keyframes, looped };
+        path.validate()?;
+        Ok(path)
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self, PathError> {
+        let unchecked: UncheckedPath = toml::from_str(source)?;
+        CameraPath::try_from(unchecked)
+    }
+
+    /// Looped circle around `center` at `distance` and `pitch`, like `StickyRotatingCamera`,
+    /// taking `period` seconds per turn.
+    pub fn orbit(center: Point3<f32>, distance: f32, pitch: f32, period: f32) -> Self {
+        const STEPS: usize = 8;
+        let keyframes = (0..=STEPS)
+            .map(|step| {
+                let yaw = 2.0 * PI * step as f32 / STEPS as f32;
+                let direction = Vector3::new(
+                    yaw.cos() * pitch.cos(),
+                    -pitch.sin(),
+                    yaw.sin() * pitch.cos(),
+                );
+                CameraKeyframe {
+                    time: period * step as f32 / STEPS as f32,
+                    position: (center + direction * distance).coords.into(),
+                    target: center.coords.into(),
+                    fov: None,
+                }
+            })
+            .collect();
+        CameraPath {
+            keyframes,
+            looped: true,
+        }
+    }
+
+    fn validate(&self) -> Result<(), PathError> {
+        if self.keyframes.is_empty() {
+            return Err(PathError::Empty);
+        }
+        if let Some(index) = self
+            .keyframes
+            .windows(2)
+            .position(|pair| pair[1].time <= pair[0].time)
+        {
+            return Err(PathError::Unordered(index + 1));
+        }
+        // Also rejects NaN.
+        match self.keyframes.iter().position(|frame| match frame.fov {
+            Some(fov) => !(fov > 0.0 && fov < 180.0),
+            None => false,
+        }) {
+            Some(index) => Err(PathError::Fov(index)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_looped(&self) -> bool {
+        self.looped
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().unwrap().time
+    }
+
+    /// Number of frames to render the whole path at `fps`, once for a looped path.
+    ///
+    /// `fps` has to be positive.
+    pub fn frame_count(&self, fps: f32) -> usize {
+        let start = self.keyframes[0].time;
+        let frames = ((self.duration() - start) * fps).floor() as usize;
+        // The last pose of a looped path is its first one.
+        if self.looped {
+            frames.max(1)
+        } else {
+            frames + 1
+        }
+    }
+
+    // Keyframe at `index` past the ends, wrapping around a looped path and clamped otherwise.
+    fn neighbour(&self, index: isize) -> &CameraKeyframe {
+        let last = self.keyframes.len() as isize - 1;
+        let index = if self.looped && last > 0 {
+            // The last keyframe repeats the first one.
+            if index < 0 {
+                index + last
+            } else if index > last {
+                index - last
+            } else {
+                index
+            }
+        } else {
+            index
+        };
+        &self.keyframes[index.max(0).min(last) as usize]
+    }
+
+    /// Pose at `time`, wrapped into the path when looped and clamped to its ends otherwise.
+    pub fn sample(&self, time: f32) -> CameraPose {
+        let first = &self.keyframes[0];
+        let last = self.keyframes.last().unwrap();
+        let time = if self.looped && last.time > first.time {
+            first.time + (time - first.time).rem_euclid(last.time - first.time)
+        } else {
+            time.max(first.time).min(last.time)
+        };
+
+        let segment =
+            self.keyframes
+                .windows(2)
+                .position(|pair| time < pair[1].time)
+                .unwrap_or_else(|| self.keyframes.len().saturating_sub(2)) as isize;
+        let a = self.neighbour(segment);
+        let b = self.neighbour(segment + 1);
+        if a.time >= b.time {
+            return CameraPose {
+                position: Point3::from(a.position),
+                target: Point3::from(a.target),
+                fov: a.fov,
+            };
+        }
+        let before = self.neighbour(segment - 1);
+        let after = self.neighbour(segment + 2);
+        let t = ((time - a.time) / (b.time - a.time)).max(0.0).min(1.0);
+
+        let position = catmull_rom(
+            Vector3::from(before.position),
+            Vector3::from(a.position),
+            Vector3::from(b.position),
+            Vector3::from(after.position),
+            t,
+        );
+        let a_look = Vector3::from(a.target) - Vector3::from(a.position);
+        let b_look = Vector3::from(b.target) - Vector3::from(b.position);
+        let distance = a_look.norm() + (b_look.norm() - a_look.norm()) * t;
+        let target = position + slerp(a_look, b_look, t) * distance;
+        let fov = match (a.fov, b.fov) {
+            (Some(a_fov), Some(b_fov)) => Some(a_fov + (b_fov - a_fov) * t),
+            (fov, None) | (None, fov) => fov,
+        };
+        CameraPose {
+            position: Point3::from(position),
+            target: Point3::from(target),
+            fov,
+        }
+    }
+}
+
+/// Camera that can be moved along a `CameraPath`.
+pub trait PathCamera: ViewAndProject {
+    fn apply_pose(&mut self, pose: &CameraPose);
+}
+
+// Yaw and pitch of `direction` in the convention of both cameras.
+fn yaw_pitch(direction: Vector3<f32>) -> (f32, f32) {
+    let length = direction.norm();
+    if length <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let yaw = direction.z.atan2(direction.x);
+    let pitch = (-direction.y / length).max(-1.0).min(1.0).asin();
+    (yaw, pitch)
+}
+
+impl PathCamera for StickyRotatingCamera {
+    fn apply_pose(&mut self, pose: &CameraPose) {
+        let eye = pose.position - pose.target;
+        let (yaw, pitch) = yaw_pitch(eye);
+        self.set_orbit(pose.target, eye.norm(), yaw, pitch);
+        if let Some(fov) = pose.fov {
+            self.update_fov(fov);
+        }
+    }
+}
+
+impl PathCamera for FreeFlyCamera {
+    fn apply_pose(&mut self, pose: &CameraPose) {
+        let (yaw, pitch) = yaw_pitch(pose.target - pose.position);
+        let roll = self.roll();
+        self.set_pose(pose.position, yaw, pitch, roll);
+        if let Some(fov) = pose.fov {
+            self.update_fov(fov);
+        }
+    }
+}
+
+/// Plays a `CameraPath` in real time on a camera.
+///
+/// Space pauses and resumes the playback, Home restarts it.
+pub struct PathPlayer<C: PathCamera> {
+    camera: Arc<Mutex<C>>,
+    path: CameraPath,
+    time: f32,
+    speed: f32,
+    playing: bool,
+}
+
+impl<C: PathCamera> PathPlayer<C> {
+    pub fn new(camera: Arc<Mutex<C>>, path: CameraPath) -> Self {
+        log::trace!("insance of {}", std::any::type_name::<Self>());
+        let time = path.keyframes()[0].time;
+        camera.lock().unwrap().apply_pose(&path.sample(time));
+        PathPlayer {
+            camera,
+            path,
+            time,
+            speed: 1.0,
+            playing: true,
+        }
+    }
+
+    /// Path seconds per second of playback.
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn path(&self) -> &CameraPath {
+        &self.path
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// A looped path never finishes.
+    pub fn is_finished(&self) -> bool {
+        !self.path.is_looped() && self.time >= self.path.duration()
+    }
+
+    pub fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+    }
+
+    /// Moves the camera to the pose at `time`.
+    pub fn seek(&mut self, time: f32) {
+        self.time = time;
+        self.camera
+            .lock()
+            .unwrap()
+            .apply_pose(&self.path.sample(time));
+    }
+}
+
+impl<C: PathCamera> InputHandler for PathPlayer<C> {
+    fn on_event(&mut self, event: &InputEvent) {
+        match event {
+            InputEvent::KeyPressed(VirtualKeyCode::Space) => self.playing = !self.playing,
+            InputEvent::KeyPressed(VirtualKeyCode::Home) => {
+                let start = self.path.keyframes()[0].time;
+                self.seek(start);
+            }
+            _ => (),
+        }
+    }
+
+    fn on_frame(&mut self, _input: &InputState, elapsed: Duration) {
+        if !self.playing || self.is_finished() {
+            return;
+        }
+        let mut time = self.time + elapsed.as_secs_f32() * self.speed;
+        // Keeps the time small so that it does not lose precision on long runs.
+        let start = self.path.keyframes()[0].time;
+        if self.path.is_looped() && self.path.duration() > start {
+            time = start + (time - start).rem_euclid(self.path.duration() - start);
+        }
+        self.seek(time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1.0e-4;
+
+    fn keyframe(time: f32, x: f32) -> CameraKeyframe {
+        CameraKeyframe {
+            time,
+            position: [x, 0.0, 5.0],
+            target: [x, 0.0, 0.0],
+            fov: None,
+        }
+    }
+
+    fn line(looped: bool) -> CameraPath {
+        CameraPath::new(
+            vec![
+                keyframe(0.0, 0.0),
+                keyframe(1.0, 1.0),
+                keyframe(2.0, 2.0),
+                keyframe(3.0, 3.0),
+            ],
+            looped,
+        )
+        .unwrap()
+    }
+
+    fn assert_near(actual: Point3<f32>, expected: [f32; 3]) {
+        let distance = (actual - Point3::from(expected)).norm();
+        assert!(distance < EPSILON, "{} is not {:?}", actual, expected);
+    }
+
+    #[test]
+    fn sample_goes_through_keyframes() {
+        let path = line(false);
+        for frame in path.keyframes() {
+            let pose = path.sample(frame.time);
+            assert_near(pose.position, frame.position);
+            assert_near(pose.target, frame.target);
+        }
+    }
+
+    #[test]
+    fn sample_interpolates_evenly_spaced_keyframes_linearly() {
+        let pose = line(false).sample(1.5);
+        assert_near(pose.position, [1.5, 0.0, 5.0]);
+        assert_near(pose.target, [1.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn sample_clamps_an_open_path() {
+        let path = line(false);
+        assert_near(path.sample(-1.0).position, [0.0, 0.0, 5.0]);
+        assert_near(path.sample(10.0).position, [3.0, 0.0, 5.0]);
+    }
+
+    #[test]
+    fn sample_wraps_a_looped_path() {
+        let path = line(true);
+        let wrapped = path.sample(4.5);
+        let inside = path.sample(1.5);
+        assert_near(wrapped.position, inside.position.coords.into());
+        let before_start = path.sample(-0.5);
+        let before_end = path.sample(2.5);
+        assert_near(before_start.position, before_end.position.coords.into());
+    }
+
+    #[test]
+    fn sample_interpolates_fov() {
+        let mut keyframes = vec![keyframe(0.0, 0.0), keyframe(2.0, 2.0)];
+        keyframes[0].fov = Some(40.0);
+        keyframes[1].fov = Some(60.0);
+        let path = CameraPath::new(keyframes, false).unwrap();
+        let fov = path.sample(1.0).fov.unwrap();
+        assert!((fov - 50.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn neighbour_clamps_an_open_path() {
+        let path = line(false);
+        assert_eq!(path.neighbour(-1).time, 0.0);
+        assert_eq!(path.neighbour(4).time, 3.0);
+        assert_eq!(path.neighbour(5).time, 3.0);
+    }
+
+    #[test]
+    fn neighbour_wraps_around_a_looped_path() {
+        let path = line(true);
+        // The last keyframe stands for the first one, so it is skipped when wrapping.
+        assert_eq!(path.neighbour(-1).time, 2.0);
+        assert_eq!(path.neighbour(4).time, 1.0);
+        assert_eq!(path.neighbour(2).time, 2.0);
+    }
+
+    #[test]
+    fn frame_count_includes_the_end_of_an_open_path() {
+        assert_eq!(line(false).frame_count(10.0), 31);
+        assert_eq!(line(false).frame_count(0.5), 2);
+    }
+
+    #[test]
+    fn frame_count_skips_the_repeated_pose_of_a_looped_path() {
+        assert_eq!(line(true).frame_count(10.0), 30);
+        assert_eq!(line(true).frame_count(0.1), 1);
+    }
+
+    #[test]
+    fn new_rejects_invalid_keyframes() {
+        assert!(matches!(
+            CameraPath::new(Vec::new(), false),
+            Err(PathError::Empty)
+        ));
+        assert!(matches!(
+            CameraPath::new(vec![keyframe(1.0, 0.0), keyframe(1.0, 1.0)], false),
+            Err(PathError::Unordered(1))
+        ));
+        for fov in &[0.0, -45.0, 180.0, f32::NAN] {
+            let mut keyframes = vec![keyframe(0.0, 0.0), keyframe(1.0, 1.0)];
+            keyframes[1].fov = Some(*fov);
+            assert!(matches!(
+                CameraPath::new(keyframes, false),
+                Err(PathError::Fov(1))
+            ));
+        }
+    }
+
+    #[test]
+    fn deserializing_validates_the_keyframes() {
+        assert!(matches!(
+            CameraPath::from_toml("keyframes = []"),
+            Err(PathError::Empty)
+        ));
+        assert!(toml::from_str::<CameraPath>("keyframes = []").is_err());
+
+        let path = CameraPath::from_toml(
+            "looped = true\n\
+             [[keyframes]]\n\
+             time = 0.0\n\
+             position = [1.0, 0.0, 0.0]\n\
+             target = [0.0, 0.0, 0.0]\n",
+        )
+        .unwrap();
+        assert!(path.is_looped());
+        assert_eq!(path.keyframes().len(), 1);
+    }
+}
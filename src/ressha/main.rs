@@ -5,42 +5,174 @@ extern crate winit;
 
 use clap::App;
 use clap::Arg;
-use kikansha::engine::State;
+use clap::ArgMatches;
+use kikansha::assets::AssetResolver;
+use kikansha::engine;
+use kikansha::engine::config::DebugView;
+use kikansha::engine::config::GpuSelection;
+use kikansha::engine::config::RendererConfig;
+use kikansha::engine::renderer::Renderer;
 use kikansha::figure::FigureMutation;
 use kikansha::figure::FigureSet;
 use kikansha::figure::RenderableMesh;
+use kikansha::scene::camera::FreeFlyCamera;
+use kikansha::scene::camera::Projection;
 use kikansha::scene::camera::StickyRotatingCamera;
+use kikansha::scene::camera::ViewAndProject;
+use kikansha::scene::camera::ViewPreset;
+use kikansha::scene::fly::FlyController;
 use kikansha::scene::gltf::load_figures;
 use kikansha::scene::gltf::LoadingError;
 use kikansha::scene::lights::PointLight;
+use kikansha::scene::orbit::OrbitController;
+use kikansha::scene::path::CameraPath;
+use kikansha::scene::path::PathPlayer;
 use kikansha::scene::Scene;
+use log::LevelFilter;
+use log4rs::append::console::ConsoleAppender;
+use log4rs::config::{Appender, Config, Root};
 use std::f32::consts::PI;
+use std::path::Path;
 use std::process::exit;
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::{Duration, SystemTime};
 
-struct QuitOnScopeExit<'a> {
-    quit_send: &'a std::sync::mpsc::Sender<bool>,
+const DEFAULT_MODEL: &str = "data/models/teapot.gltf";
+const DEFAULT_TEXTURE: &str = "src/kikansha/frame/resources/tex.png";
+const DEFAULT_LOG_CONFIG: &str = "config/log4rs.yaml";
+// Values of `--view`.
+const VIEW_PRESETS: [(&str, ViewPreset); 7] = [
+    ("front", ViewPreset::Front),
+    ("back", ViewPreset::Back),
+    ("right", ViewPreset::Right),
+    ("left", ViewPreset::Left),
+    ("top", ViewPreset::Top),
+    ("bottom", ViewPreset::Bottom),
+    ("isometric", ViewPreset::Isometric),
+];
+// Seconds per turn of the default camera path.
+const DEFAULT_ORBIT_PERIOD: f32 = 12.0;
+const DEFAULT_RECORD_FPS: f32 = 30.0;
+
+fn init_logging(assets: &AssetResolver, log_config: &str) {
+    match assets.resolve(log_config) {
+        Some(path) => log4rs::init_file(path, Default::default()).unwrap(),
+        None => {
+            let stdout = ConsoleAppender::builder().build();
+            let config = Config::builder()
+                .appender(Appender::builder().build("stdout", Box::new(stdout)))
+                .build(Root::builder().appender("stdout").build(LevelFilter::Info))
+                .unwrap();
+            log4rs::init_config(config).unwrap();
+            log::warn!("{} not found, logging to stdout", log_config);
+        }
+    }
+}
+
+fn camera_path(path: &str, assets: &AssetResolver) -> CameraPath {
+    let loaded = assets
+        .resolve_or_err(path)
+        .and_then(std::fs::read_to_string)
+        .map_err(|e| e.to_string())
+        .and_then(|source| CameraPath::from_toml(&source).map_err(|e| e.to_string()));
+    match loaded {
+        Ok(camera_path) => camera_path,
+        Err(e) => {
+            log::error!("Failed to load camera path {}: {}", path, e);
+            exit(1);
+        }
+    }
 }
 
-impl Drop for QuitOnScopeExit<'_> {
-    fn drop(&mut self) {
-        if std::thread::panicking() {
-            log::error!("Panicking");
+/// Loads the configuration file, if any, and applies command line overrides on top of it.
+fn renderer_config(matches: &ArgMatches, assets: &AssetResolver) -> RendererConfig {
+    let mut config = match matches.value_of("config") {
+        Some(path) => {
+            let loaded = assets
+                .resolve_or_err(path)
+                .and_then(std::fs::read_to_string)
+                .map_err(|e| e.to_string())
+                .and_then(|source| RendererConfig::from_toml(&source).map_err(|e| e.to_string()));
+            match loaded {
+                Ok(config) => config,
+                Err(e) => {
+                    log::error!("Failed to load config {}: {}", path, e);
+                    exit(1);
+                }
+            }
         }
+        None => RendererConfig::default(),
+    };
 
-        let _ = self.quit_send.send(true);
+    let [mut width, mut height] = config.window_size;
+    if let Some(w) = matches.value_of("width").and_then(|s| s.parse().ok()) {
+        width = w;
+    }
+    if let Some(h) = matches.value_of("height").and_then(|s| s.parse().ok()) {
+        height = h;
+    }
+    config = config.with_window_size(width, height);
+
+    if matches.is_present("fullscreen") {
+        config = config.with_fullscreen(true);
+    }
+    if matches.is_present("vsync") {
+        config = config.with_vsync(true);
+    }
+    if matches.is_present("validation") {
+        config = config.with_validation(true);
+    }
+    if matches.is_present("panic_on_validation") {
+        let mut messenger = config.debug_messenger.clone();
+        messenger.panic_on_error = true;
+        config = config.with_validation(true).with_debug_messenger(messenger);
+    }
+    if matches.is_present("gpu_profiler") {
+        config = config.with_gpu_profiler(true);
     }
+    if matches.is_present("gpu_culling") {
+        config = config.with_gpu_culling(true);
+    }
+    if let Some(trace) = matches.value_of("trace") {
+        // The GPU spans of the trace come from the profiler.
+        config = config.with_trace_file(trace).with_gpu_profiler(true);
+    }
+    if let Some(level) = matches
+        .value_of("color_l")
+        .and_then(|s| s.parse::<i32>().ok())
+    {
+        config = config.with_debug_view(DebugView::from_level(level));
+    }
+    if let Some(gpu) = matches.value_of("gpu") {
+        config = config.with_gpu(match gpu.parse::<usize>() {
+            Ok(index) => GpuSelection::Index(index),
+            // UUIDs are printed by --list-gpus as 8-4-4-4-12 hex digits.
+            Err(_) if gpu.len() == 36 && gpu.matches('-').count() == 4 => {
+                GpuSelection::Uuid(gpu.to_string())
+            }
+            Err(_) => GpuSelection::Name(gpu.to_string()),
+        });
+    }
+    log::info!("{:?}", config);
+    config
 }
 
-fn main() {
-    log4rs::init_file(
-        "/home/michey/Projects/hello_vulkan/config/log4rs.yaml",
-        Default::default(),
-    )
-    .unwrap();
+/// Preset named by the `--view` option, clap only accepts the names of `VIEW_PRESETS`.
+fn view_preset(name: &str) -> ViewPreset {
+    match VIEW_PRESETS
+        .iter()
+        .find(|(preset_name, _)| *preset_name == name)
+    {
+        Some((_, preset)) => *preset,
+        None => {
+            log::error!("Unknown view {}", name);
+            exit(1);
+        }
+    }
+}
 
+fn main() {
+    let view_names: Vec<&str> = VIEW_PRESETS.iter().map(|(name, _)| *name).collect();
     let matches = App::new("kikansha")
         .version("1.0")
         .author("")
@@ -57,6 +189,31 @@ fn main() {
                 .long("validation")
                 .help("Run with validation layer"),
         )
+        .arg(
+            Arg::with_name("panic_on_validation")
+                .long("panic-on-validation")
+                .help("Abort after a frame with validation errors, implies --validation"),
+        )
+        .arg(
+            Arg::with_name("gpu_profiler")
+                .long("gpu-profiler")
+                .help("Log per-pass GPU timings"),
+        )
+        .arg(
+            Arg::with_name("gpu_culling")
+                .long("gpu-culling")
+                .help("Cull instances in a compute pass and draw them indirectly"),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .takes_value(true)
+                .value_name("file")
+                .help(
+                    "Write a Chrome trace of CPU and GPU timings on exit and on F12, implies \
+                     --gpu-profiler",
+                ),
+        )
         .arg(
             Arg::with_name("color_l")
                 .short("c")
@@ -65,8 +222,140 @@ fn main() {
                 .value_name("level")
                 .help("Set debug level for deferred shader"),
         )
+        .arg(
+            Arg::with_name("assets")
+                .short("a")
+                .long("assets")
+                .takes_value(true)
+                .value_name("dir")
+                .help("Asset root, searched before $KIKANSHA_ASSET_ROOT and the binary directory"),
+        )
+        .arg(
+            Arg::with_name("model")
+                .short("m")
+                .long("model")
+                .takes_value(true)
+                .value_name("path")
+                .help("glTF model to show"),
+        )
+        .arg(
+            Arg::with_name("lods")
+                .long("lods")
+                .takes_value(true)
+                .value_name("levels")
+                .help("Generate up to this many simplified levels of detail of the model"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .value_name("path")
+                .help("Renderer configuration file (TOML), command line flags override it"),
+        )
+        .arg(
+            Arg::with_name("width")
+                .long("width")
+                .takes_value(true)
+                .value_name("px")
+                .help("Window width"),
+        )
+        .arg(
+            Arg::with_name("height")
+                .long("height")
+                .takes_value(true)
+                .value_name("px")
+                .help("Window height"),
+        )
+        .arg(
+            Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .help("Borderless fullscreen window"),
+        )
+        .arg(
+            Arg::with_name("vsync")
+                .long("vsync")
+                .help("Wait for vertical blank"),
+        )
+        .arg(
+            Arg::with_name("gpu")
+                .long("gpu")
+                .takes_value(true)
+                .value_name("index|name|uuid")
+                .help("Render on the given GPU"),
+        )
+        .arg(
+            Arg::with_name("orbit")
+                .long("orbit")
+                .help("Orbit the camera with the mouse and the arrow keys instead of spinning it"),
+        )
+        .arg(
+            Arg::with_name("view")
+                .long("view")
+                .takes_value(true)
+                .possible_values(&view_names)
+                .help("Start from a standard view, implies --orbit"),
+        )
+        .arg(
+            Arg::with_name("ortho")
+                .long("ortho")
+                .help("Orthographic projection, numpad 5 toggles it with --orbit"),
+        )
+        .arg(
+            Arg::with_name("fly")
+                .long("fly")
+                .conflicts_with_all(&["orbit", "view"])
+                .help(
+                "Fly through the scene with WASD, looking around while the right button is held",
+            ),
+        )
+        .arg(
+            Arg::with_name("camera_path")
+                .long("camera-path")
+                .takes_value(true)
+                .value_name("path")
+                .conflicts_with_all(&["orbit", "view", "fly"])
+                .help("Move the camera along a keyframed path (TOML) instead of circling the model"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .value_name("dir")
+                .conflicts_with_all(&["orbit", "view", "fly"])
+                .help("Render the camera path once into numbered PNG files and exit"),
+        )
+        .arg(
+            Arg::with_name("fps")
+                .long("fps")
+                .takes_value(true)
+                .value_name("n")
+                .requires("record")
+                .validator(|s| match s.parse::<f32>() {
+                    Ok(fps) if fps > 0.0 && fps.is_finite() => Ok(()),
+                    _ => Err(format!("{} is not a positive number", s)),
+                })
+                .help("Frames per second of path time rendered by --record"),
+        )
+        .arg(
+            Arg::with_name("list_gpus")
+                .long("list-gpus")
+                .help("Print the available GPUs and exit"),
+        )
+        .arg(
+            Arg::with_name("log_config")
+                .long("log-config")
+                .takes_value(true)
+                .value_name("path")
+                .help("log4rs configuration file"),
+        )
         .get_matches();
 
+    let assets = AssetResolver::discover(matches.value_of("assets").map(Path::new));
+    init_logging(
+        &assets,
+        matches.value_of("log_config").unwrap_or(DEFAULT_LOG_CONFIG),
+    );
+
     if matches.is_present("debugger") {
         let url = format!(
             "vscode://vadimcn.vscode-lldb/launch/config?{{'request':'attach','pid':{}}}",
@@ -81,24 +370,34 @@ fn main() {
         std::thread::sleep_ms(10000); // Wait for debugger to attach
     }
 
-    let color_debug_level: i32 = matches
-        .value_of("color_l")
-        .and_then(|s| s.parse::<i32>().ok())
-        .unwrap_or(0);
-
-    let run_with_validation = matches.is_present("validation");
-
+    if matches.is_present("list_gpus") {
+        match Renderer::list_gpus() {
+            Ok(gpus) => {
+                for gpu in gpus {
+                    println!("{}", gpu);
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to list GPUs: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let mut yaw = PI / 4.0;
-    let mut pitch = -PI / 4.0;
-    let yaw_loop = Duration::from_secs(6_u64);
-    let mut yaw_step = (PI * 2.0) / yaw_loop.as_millis() as f32;
+    let config = renderer_config(&matches, &assets);
 
-    let pitch_loop = Duration::from_secs(10_u64);
-    let mut pitch_step = PI / pitch_loop.as_millis() as f32;
+    let yaw = PI / 4.0;
+    let pitch = -PI / 4.0;
 
-    let mut init_ts = SystemTime::now();
-    let p_camera = StickyRotatingCamera::new(5.5, yaw, pitch);
+    let mut p_camera = StickyRotatingCamera::new(1.0, yaw, pitch);
+    let view = matches.value_of("view").map(view_preset);
+    if let Some(preset) = view {
+        p_camera.set_preset(preset);
+    }
+    if matches.is_present("ortho") {
+        p_camera.set_projection(Projection::Orthographic);
+    }
     let camera = Arc::new(Mutex::new(p_camera));
 
     let mut scene_sets: Vec<FigureSet> = Vec::new();
@@ -106,85 +405,94 @@ fn main() {
     let teapot_scale = 1.0;
     let teapot_mutations = vec![FigureMutation::new([0.0, 0.0, 0.0], teapot_scale)];
 
-    let sce2: Result<Vec<RenderableMesh>, LoadingError> =
-        // load_scene_from_file("/home/michey/Projects/hello_vulkan/data/models/teapot.gltf");
-        load_figures("/home/michey/Projects/hello_vulkan/data/models/teapot.gltf");
+    let model = matches.value_of("model").unwrap_or(DEFAULT_MODEL);
+    let sce2: Result<Vec<RenderableMesh>, LoadingError> = load_figures(&assets, model);
 
     match sce2 {
         Ok(meshes) => match meshes.first() {
             Some(mesh) => {
-                let teapot_set = FigureSet::new(
+                let mut teapot_set = FigureSet::new(
                     mesh.clone(),
                     teapot_mutations,
-                    "/home/michey/Projects/hello_vulkan/src/kikansha/frame/resources/tex.png"
-                        .to_string(),
-                    "/home/michey/Projects/hello_vulkan/src/kikansha/frame/resources/tex.png"
-                        .to_string(),
+                    DEFAULT_TEXTURE.to_string(),
+                    DEFAULT_TEXTURE.to_string(),
                 );
+                if let Some(levels) = matches
+                    .value_of("lods")
+                    .and_then(|s| s.parse::<usize>().ok())
+                {
+                    teapot_set = teapot_set.with_generated_lods(levels);
+                    log::info!("Generated {} levels of detail", teapot_set.lods.len());
+                }
                 scene_sets.push(teapot_set);
             }
             _ => {}
         },
+        Err(LoadingError::NotFound(path)) => log::error!("Model {} not found", path),
         _ => {}
     }
 
-    let scene = Scene::create(camera.clone(), scene_sets, PointLight::default_lights());
-
-    let sleep = Duration::from_millis(100);
+    // Nothing asks the render loop to quit, it stops when the window is closed.
+    let (_quit_send, quit_recv) = std::sync::mpsc::channel();
 
-    let (event_send, _event_recv) = std::sync::mpsc::sync_channel(1);
-    let (quit_send, quit_recv) = std::sync::mpsc::channel();
-
-    std::thread::spawn(move || {
-        let _scoped_quit = QuitOnScopeExit {
-            quit_send: &quit_send,
-        };
-
-        log::info!("Thread created");
+    if matches.is_present("fly") {
+        // Looks in the direction of the spinning camera, from where the whole model is visible.
+        let eye = camera.lock().unwrap().camera_p();
+        let fly_camera = Arc::new(Mutex::new(FreeFlyCamera::new(eye, yaw + PI, -pitch)));
+        let scene = Scene::create(fly_camera.clone(), scene_sets, PointLight::default_lights());
+        fly_camera.lock().unwrap().frame_scene(&scene);
+        let mut controller = FlyController::new(fly_camera);
+        if let Err(e) = engine::run_loop(&scene, &mut controller, quit_recv, config, assets) {
+            log::error!("Rendering failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-        loop {
-            let current_ts = SystemTime::now();
-            let elapsed = current_ts.duration_since(init_ts).unwrap();
-            init_ts = current_ts;
+    let scene = Scene::create(camera.clone(), scene_sets, PointLight::default_lights());
+    // The model is fully in view whatever its size.
+    camera.lock().unwrap().frame_scene(&scene);
 
-            let new_yaw = yaw + (elapsed.as_millis() as f32 * yaw_step);
-            yaw = new_yaw;
-            if new_yaw >= (PI * 2.0) {
-                yaw = PI * 2.0;
-                yaw_step = -yaw_step;
-            }
-            if new_yaw <= 0.0 {
-                yaw = 0.0;
-                yaw_step = -yaw_step;
-            }
+    if matches.is_present("orbit") || view.is_some() {
+        let mut controller = OrbitController::new(camera);
+        if let Err(e) = engine::run_loop(&scene, &mut controller, quit_recv, config, assets) {
+            log::error!("Rendering failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-            let new_pitch = pitch + (elapsed.as_millis() as f32 * pitch_step);
-            pitch = new_pitch;
-            if new_pitch >= (PI / 2.0) {
-                pitch = PI / 2.0 - pitch_step.abs();
-                pitch_step = -pitch_step
-            }
+    let path = match matches.value_of("camera_path") {
+        Some(path) => camera_path(path, &assets),
+        None => {
+            let locked_camera = camera.lock().unwrap();
+            CameraPath::orbit(
+                locked_camera.target(),
+                locked_camera.distance(),
+                locked_camera.pitch(),
+                DEFAULT_ORBIT_PERIOD,
+            )
+        }
+    };
 
-            if new_pitch <= -(PI / 2.0) {
-                pitch = -(PI / 2.0) + pitch_step.abs();
-                pitch_step = -pitch_step
-            }
-            {
-                camera.lock().unwrap().set_yaw(yaw);
+    if let Some(dir) = matches.value_of("record") {
+        let fps = matches
+            .value_of("fps")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_RECORD_FPS);
+        match engine::render_sequence(&scene, &path, fps, Path::new(dir), config, assets) {
+            Ok(frames) => log::info!("{} frames written to {}", frames, dir),
+            Err(e) => {
+                log::error!("Recording failed: {}", e);
+                std::process::exit(1);
             }
-            // {
-            //     camera.lock().unwrap().set_pitch(pitch);
-            // }
-
-            std::thread::sleep(sleep);
         }
-    });
-
-    State::run_loop(
-        &scene,
-        event_send,
-        quit_recv,
-        run_with_validation,
-        color_debug_level,
-    );
+        return;
+    }
+
+    let mut player = PathPlayer::new(camera, path);
+    if let Err(e) = engine::run_loop(&scene, &mut player, quit_recv, config, assets) {
+        log::error!("Rendering failed: {}", e);
+        std::process::exit(1);
+    }
 }